@@ -1,9 +1,18 @@
 #![forbid(unsafe_code)]
 #![deny(clippy::unwrap_used)]
 
+use audio::{
+    decode::{AudioDecoder, ChannelSelection},
+    wav::WavFormat,
+    waveform::Waveform,
+};
 use color_eyre::{self, eyre::Context};
 use rodio::{buffer::SamplesBuffer, OutputStream, Sink};
-use std::io::{self, Write};
+use spectrum::{ChannelVocoder, ChannelVocoderOptions};
+use std::{
+    io::{self, Write},
+    path::Path,
+};
 use tts::{load_language, setup_tts, synthesize};
 
 fn main() -> color_eyre::Result<()> {
@@ -35,6 +44,8 @@ fn main() -> color_eyre::Result<()> {
         OutputStream::try_default().wrap_err("unable to open audio output stream")?;
     let sink = Sink::try_new(&stream_handle).wrap_err("unable to create sink")?;
 
+    let mut last_waveform = None;
+
     loop {
         write!(stdout, "synth> ").wrap_err("unable to write to stdout")?;
         stdout.flush().wrap_err("unable to write to stdout")?;
@@ -46,14 +57,75 @@ fn main() -> color_eyre::Result<()> {
 
         let line = line.trim_end();
 
-        let waveform = synthesize(&mut engine, line)?;
+        // Save the previously synthesized speech instead of speaking the line
+        if let Some(path) = line.strip_prefix(":save ") {
+            match &last_waveform {
+                Some(waveform) => match waveform.save_wav(path, WavFormat::Int16) {
+                    Ok(()) => println!("saved to {path}"),
+                    Err(error) => eprintln!("unable to save {path}: {error}"),
+                },
+                None => eprintln!("nothing has been synthesized yet"),
+            }
+
+            continue;
+        }
+
+        // Vocode or play back a file instead of speaking the line
+        let waveform = if let Some(path) = line.strip_prefix(":vocode ") {
+            // Let the file speak the previously synthesized speech
+            let modulator = match &last_waveform {
+                Some(waveform) => waveform,
+                None => {
+                    eprintln!("nothing has been synthesized yet");
+
+                    continue;
+                }
+            };
+
+            match decode_file(Path::new(path)) {
+                Ok(carrier) if !carrier.is_empty() => {
+                    ChannelVocoder::vocode(ChannelVocoderOptions::default(), modulator, &carrier)
+                }
+                Ok(_) => {
+                    eprintln!("{path} is empty");
+
+                    continue;
+                }
+                Err(error) => {
+                    eprintln!("{error:?}");
+
+                    continue;
+                }
+            }
+        } else if let Some(path) = line.strip_prefix(":play ") {
+            match decode_file(Path::new(path)) {
+                Ok(waveform) => waveform,
+                Err(error) => {
+                    eprintln!("{error:?}");
+
+                    continue;
+                }
+            }
+        } else {
+            synthesize(&mut engine, line)?
+        };
 
         sink.append(SamplesBuffer::new(
-            1,
+            waveform.channels(),
             waveform.sample_rate(),
             waveform.samples(),
         ));
 
         sink.sleep_until_end();
+
+        last_waveform = Some(waveform);
     }
 }
+
+fn decode_file(path: &Path) -> color_eyre::Result<Waveform<'static>> {
+    let decoder = AudioDecoder::open(path).wrap_err("unable to open file")?;
+
+    decoder
+        .decode(ChannelSelection::All, |_| {})
+        .wrap_err("unable to decode file")
+}
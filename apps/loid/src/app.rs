@@ -1,6 +1,7 @@
 #![forbid(unsafe_code)]
 
 use std::{
+    iter, mem,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, Once,
@@ -9,7 +10,8 @@ use std::{
 };
 
 use audio::{
-    output::{AudioSink, AudioSinkProgress},
+    device::{output_settings_ui, DeviceInfo, DeviceSettings, OUTPUT_SETTINGS_KEY},
+    output::{AudioSink, AudioSinkProgress, PlaybackHandle, Sink},
     waveform::Waveform,
 };
 use eframe::{
@@ -17,20 +19,35 @@ use eframe::{
         Button, CentralPanel, Context, RichText, ScrollArea, SidePanel, Slider, TopBottomPanel,
     },
     epaint::Vec2,
-    epi::{App, Frame},
+    epi::{self, App, Frame, Storage},
 };
 use instant::Instant;
-use spectrum::{WaveformSpectrum, Window};
+use spectrum::{fft, Complex, PhaseVocoder, Stft, WaveformSpectrum, Window};
 use tracing::warn;
 
 mod plot;
 
+/// How much of the live input is kept for analysis
+#[cfg(not(target_arch = "wasm32"))]
+const LIVE_INPUT_DURATION: Duration = Duration::from_secs(5);
+
 pub struct Application {
     math_elapsed: Option<Duration>,
 
     audio_sink: AudioSink,
+    playback: Option<PlaybackHandle>,
+    gain: f32,
+    output_settings: DeviceSettings,
+    output_devices: Vec<DeviceInfo>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    live_input: Option<audio::input::InputStream>,
+    #[cfg(not(target_arch = "wasm32"))]
+    live_samples: Vec<f32>,
 
     waveform: Option<Waveform<'static>>,
+    /// The waveform after being resynthesized from its shifted spectra
+    reconstructed: Option<Waveform<'static>>,
     window: Window,
 
     is_playing: Arc<AtomicBool>,
@@ -49,16 +66,30 @@ pub struct Application {
     hop_frac: usize,
 
     shift: f64,
+    /// Shift with the phase vocoder instead of moving whole buckets
+    phase_vocoder: bool,
+    semitones: f64,
+    stretch: f64,
 }
 
 impl Application {
-    pub fn new(audio_sink: AudioSink) -> Self {
+    pub fn new(audio_sink: AudioSink, output_settings: DeviceSettings) -> Self {
         Self {
             math_elapsed: None,
 
             audio_sink,
+            playback: None,
+            gain: 1.0,
+            output_settings,
+            output_devices: AudioSink::devices(),
+
+            #[cfg(not(target_arch = "wasm32"))]
+            live_input: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            live_samples: Vec::new(),
 
             waveform: None,
+            reconstructed: None,
 
             window: Window::Hann,
 
@@ -82,6 +113,9 @@ impl Application {
             hop_frac: 4,
 
             shift: 0.0,
+            phase_vocoder: false,
+            semitones: 0.0,
+            stretch: 1.0,
         }
     }
 }
@@ -105,73 +139,71 @@ impl Application {
     // let (samples, SampleRate(sample_rate)) = audio::input::h()?;
     // }
 
-    // fn reconstruct_samples(&mut self) {
-    //     self.reconstructed_samples.clear();
+    /// Shift every frame of the waveform and resynthesize it with overlap-add, or pitch shift
+    /// and stretch it with the phase vocoder
+    fn reconstruct_samples(&mut self) {
+        let waveform = match &self.waveform {
+            Some(waveform) => waveform,
+            None => return,
+        };
 
-    //     let mut window_samples = Vec::new();
+        let fft_width = 1 << self.fft_width;
+        let hop = (self.window_width / self.hop_frac).max(1);
 
-    //     for window_start in (0..self.samples.len()).step_by(self.width) {
-    //         if window_start + self.width >= self.samples.len() {
-    //             let window = window_start..window_start + self.width;
-    //             warn!(?window, "skipping window");
+        if self.phase_vocoder {
+            let vocoder = PhaseVocoder::new(self.window, self.window_width, fft_width, hop);
+            let shifted = vocoder.pitch_shift(waveform, self.semitones);
 
-    //             break;
-    //         }
+            self.reconstructed = Some(vocoder.time_stretch(&shifted, self.stretch));
 
-    //         spectrum(window_start, self.width, &self.samples, &mut self.spectrum);
-    //         if self.is_scale {
-    //             todo!();
-    //             // scale_spectrum(spectrum, &mut self.shifted_spectrum, self.shift);
+            return;
+        }
 
-    //             // self.shifted_spectrum[0] = Complex::new(0.0, 0.0);
-    //         } else {
-    //             shift_spectrum(
-    //                 self.bucket_from_freq(self.shift),
-    //                 &self.spectrum,
-    //                 &mut self.shifted_spectrum,
-    //             )
-    //         }
+        let stft = Stft::new(self.window, self.window_width, fft_width, hop);
 
-    //         reconstruct_samples(
-    //             &self.shifted_spectrum,
-    //             &mut self.reconstructed_work_buffer,
-    //             &mut window_samples,
-    //             self.width,
-    //         );
+        let shift =
+            (self.shift * fft_width as f64 / waveform.sample_rate() as f64).round() as usize;
 
-    //         self.reconstructed_samples.append(&mut window_samples);
+        let mut istft = stft.inverse();
+        for frame in stft.frames(waveform) {
+            let shifted = iter::repeat(Complex::new(0.0, 0.0))
+                .take(shift)
+                .chain(frame)
+                .take(stft.buckets())
+                .collect::<Vec<_>>();
 
-    //         // self.shift += 500.0 * (self.width as f64 / self.samples.len() as f64) as f64;
-    //     }
-    // }
+            istft.push(&shifted);
+        }
+
+        self.reconstructed = Some(istft.finish(waveform.len(), waveform.sample_rate()));
+    }
 
     // FIXME: broken on web
-    fn play(&self, waveform: &Waveform<'_>, ctx: Context) {
+    fn play(&self, waveform: &Waveform<'_>, ctx: Context) -> PlaybackHandle {
         tracing::info!(
             "Playing {} samples ({} seconds)",
             waveform.len(),
             waveform.duration()
         );
 
-        let did_queue = self.audio_sink.queue(waveform, {
+        let playback = self.audio_sink.queue(waveform, {
             let playback_head = self.playback_head.clone();
             let is_playing = self.is_playing.clone();
-            let waveform_len = waveform.len() as f32;
+            let waveform_len = waveform.len();
 
             let once = Once::new();
 
             move |progress| {
                 match progress {
-                    AudioSinkProgress::Samples(progress) => {
+                    AudioSinkProgress::Samples(sample) => {
                         once.call_once(|| {
                             is_playing.store(true, Ordering::SeqCst);
                         });
 
-                        playback_head
-                            .store((progress * waveform_len).round() as _, Ordering::SeqCst);
+                        playback_head.store(sample, Ordering::SeqCst);
                     }
                     AudioSinkProgress::Finished => {
-                        playback_head.store(waveform_len as _, Ordering::SeqCst);
+                        playback_head.store(waveform_len, Ordering::SeqCst);
                         is_playing.store(false, Ordering::SeqCst);
                     }
                 };
@@ -179,13 +211,74 @@ impl Application {
             }
         });
 
-        if !did_queue {
-            warn!("Failed to queue waveform");
+        playback.set_gain(self.gain);
+
+        playback
+    }
+
+    /// Start or stop analysing the default input device as it records
+    #[cfg(not(target_arch = "wasm32"))]
+    fn toggle_live_input(&mut self) {
+        use audio::{decode::ChannelSelection, input::InputStream};
+
+        if self.live_input.take().is_some() {
+            return;
         }
+
+        match InputStream::new(ChannelSelection::Downmix, Duration::from_secs(1)) {
+            Ok(input) => {
+                self.live_samples.clear();
+                self.live_input = Some(input);
+            }
+            Err(error) => warn!(%error, "failed to open audio input"),
+        }
+    }
+
+    /// Move newly recorded samples into the waveform, keeping only the most recent ones
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_live_input(&mut self, ctx: &Context) {
+        let input = match &mut self.live_input {
+            Some(input) => input,
+            None => return,
+        };
+
+        input.read(&mut self.live_samples);
+
+        let max_len = (LIVE_INPUT_DURATION.as_secs_f64() * input.sample_rate() as f64) as usize;
+        if self.live_samples.len() > max_len {
+            self.live_samples.drain(..self.live_samples.len() - max_len);
+        }
+
+        ctx.request_repaint();
+
+        // Wait until there is enough to fill the analysis window
+        if self.live_samples.len() <= self.window_width {
+            return;
+        }
+
+        self.waveform = Some(Waveform::new(
+            self.live_samples.clone(),
+            input.sample_rate(),
+        ));
+
+        // Always analyse the latest samples, the cursor is clamped to the end of the waveform
+        self.cursor = usize::MAX;
     }
 }
 
+// TODO: decode on a background thread
+#[cfg(not(target_arch = "wasm32"))]
+fn load_file(path: &std::path::Path) -> Result<Waveform<'static>, Box<dyn std::error::Error>> {
+    use audio::decode::{AudioDecoder, ChannelSelection};
+
+    Ok(AudioDecoder::open(path)?.decode(ChannelSelection::All, |_| {})?)
+}
+
 impl App for Application {
+    fn save(&mut self, storage: &mut dyn Storage) {
+        epi::set_value(storage, OUTPUT_SETTINGS_KEY, &self.output_settings);
+    }
+
     fn persist_native_window(&self) -> bool {
         false
     }
@@ -195,6 +288,9 @@ impl App for Application {
     }
 
     fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.read_live_input(ctx);
+
         TopBottomPanel::top("nav_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 eframe::egui::widgets::global_dark_light_mode_switch(ui);
@@ -202,6 +298,36 @@ impl App for Application {
                     if ui.button("Load Sine Wave").clicked() {
                         self.waveform =
                             Some(Waveform::sine_wave(220.0, 0.5, Waveform::CD_SAMPLE_RATE));
+                        self.reconstructed = None;
+
+                        ui.close_menu();
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("Load File…").clicked() {
+                        ui.close_menu();
+
+                        if let Some(path) = rfd::FileDialog::new().pick_file() {
+                            match load_file(&path) {
+                                Ok(waveform) => {
+                                    self.waveform = Some(waveform);
+                                    self.reconstructed = None;
+                                }
+                                Err(error) => {
+                                    warn!(%error, path = %path.display(), "failed to load file");
+                                }
+                            }
+                        }
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui
+                        .button(if self.live_input.is_some() {
+                            "Stop Live Input"
+                        } else {
+                            "Start Live Input"
+                        })
+                        .clicked()
+                    {
+                        self.toggle_live_input();
 
                         ui.close_menu();
                     }
@@ -211,10 +337,36 @@ impl App for Application {
                         .clicked()
                     {
                         self.waveform = None;
+                        self.reconstructed = None;
+                        self.audio_sink.clear();
+
+                        #[cfg(not(target_arch = "wasm32"))]
+                        {
+                            self.live_input = None;
+                        }
 
                         ui.close_menu();
                     }
                 });
+                ui.menu_button("Settings", |ui| {
+                    if output_settings_ui(ui, &mut self.output_settings, &self.output_devices) {
+                        ui.close_menu();
+
+                        // Stop playback before dropping the old output stream
+                        self.playback = None;
+
+                        match AudioSink::with_device(&self.output_settings) {
+                            Ok(audio_sink) => self.audio_sink = audio_sink,
+                            Err(error) => warn!(%error, "failed to open audio output"),
+                        }
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Rescan Devices").clicked() {
+                        self.output_devices = AudioSink::devices();
+                    }
+                });
             });
         });
 
@@ -269,32 +421,87 @@ impl App for Application {
                     self.waveform.is_some() && !self.audio_sink.playing(),
                     |ui| {
                         if ui.button("Play Original").clicked() {
-                            self.play(
-                                self.waveform
-                                    .as_ref()
-                                    .expect("button cannot be pressed with no waveform"),
-                                ctx.clone(),
+                            self.playback = Some(
+                                self.play(
+                                    self.waveform
+                                        .as_ref()
+                                        .expect("button cannot be pressed with no waveform"),
+                                    ctx.clone(),
+                                ),
                             );
                         }
 
-                        if ui
-                            .add_enabled(
-                                false,
-                                // self.audio_sink.empty() && !self.reconstructed_samples.is_empty(),
-                                Button::new("Play Reconstructed"),
-                            )
-                            .clicked()
-                        {
-                            // self.play(self.reconstructed_samples.as_ref(), frame.clone());
+                        if let Some(reconstructed) = &self.reconstructed {
+                            if ui.button("Play Reconstructed").clicked() {
+                                self.playback = Some(self.play(reconstructed, ctx.clone()));
+                            }
+                        } else {
+                            ui.add_enabled(false, Button::new("Play Reconstructed"));
                         }
                     },
                 );
 
+                // Drop the handle once the sink is done with the waveform
+                if self
+                    .playback
+                    .as_ref()
+                    .is_some_and(PlaybackHandle::is_finished)
+                {
+                    self.playback = None;
+                }
+
+                ui.add_enabled_ui(self.playback.is_some(), |ui| {
+                    ui.horizontal(|ui| {
+                        let paused = self
+                            .playback
+                            .as_ref()
+                            .is_some_and(PlaybackHandle::is_paused);
+
+                        if ui.button(if paused { "Resume" } else { "Pause" }).clicked() {
+                            if let Some(playback) = &self.playback {
+                                if paused {
+                                    playback.resume();
+                                } else {
+                                    playback.pause();
+                                }
+                            }
+                        }
+
+                        if ui.button("Stop").clicked() {
+                            if let Some(playback) = &self.playback {
+                                playback.stop();
+                            }
+                        }
+                    });
+
+                    if let Some(playback) = &self.playback {
+                        let mut position = playback.position();
+
+                        ui.label("Position");
+                        if ui
+                            .add(Slider::new(&mut position, 0..=playback.len()).prefix("sample "))
+                            .changed()
+                        {
+                            playback.seek(position);
+                        }
+                    }
+                });
+
+                ui.label("Volume");
+                if ui
+                    .add(Slider::new(&mut self.gain, 0.0..=2.0).fixed_decimals(2))
+                    .changed()
+                {
+                    if let Some(playback) = &self.playback {
+                        playback.set_gain(self.gain);
+                    }
+                }
+
                 if ui
-                    .add_enabled(false, Button::new("Reconstruct Samples"))
+                    .add_enabled(self.waveform.is_some(), Button::new("Reconstruct Samples"))
                     .clicked()
                 {
-                    // self.reconstruct_samples();
+                    self.reconstruct_samples();
                 }
 
                 ui.checkbox(&mut self.follow_playback, "FFT follows playback");
@@ -330,10 +537,37 @@ impl App for Application {
                     ui.label("Window Function");
                     ui.horizontal_wrapped(|ui| {
                         for window in Window::ALL {
-                            ui.selectable_value(&mut self.window, window, window.to_string());
+                            // Keep the parameter of the selected window when it is clicked again
+                            let selected =
+                                mem::discriminant(&self.window) == mem::discriminant(&window);
+
+                            let label = if selected { self.window } else { window }.to_string();
+
+                            if ui.selectable_label(selected, label).clicked() && !selected {
+                                self.window = window;
+                            }
                         }
                     });
 
+                    match &mut self.window {
+                        Window::Kaiser(beta) => {
+                            ui.add(Slider::new(beta, 0.0..=20.0).text("β"));
+                        }
+                        Window::Gaussian(sigma) => {
+                            ui.add(Slider::new(sigma, 0.05..=1.0).text("σ"));
+                        }
+                        Window::Tukey(alpha) => {
+                            ui.add(Slider::new(alpha, 0.0..=1.0).text("α"));
+                        }
+                        _ => {}
+                    }
+
+                    let properties = self.window.properties(self.window_width);
+                    ui.label(format!(
+                        "Coherent gain: {:.3}, ENBW: {:.2} buckets, scalloping loss: {:.2} dB",
+                        properties.coherent_gain, properties.enbw, properties.scalloping_loss,
+                    ));
+
                     ui.label("Hop Fraction");
                     ui.add(
                         Slider::new(&mut self.hop_frac, 1..=16)
@@ -370,8 +604,19 @@ impl App for Application {
 
                     ui.separator();
                     ui.heading("DSP");
+                    ui.checkbox(&mut self.phase_vocoder, "Phase vocoder");
                     ui.label("Frequency shift");
-                    ui.add(Slider::new(&mut self.shift, 0.0..=1000.0).suffix(" Hz"));
+                    if self.phase_vocoder {
+                        ui.add(Slider::new(&mut self.semitones, -24.0..=24.0).suffix(" semitones"));
+                        ui.label("Time stretch");
+                        ui.add(
+                            Slider::new(&mut self.stretch, 0.25..=4.0)
+                                .logarithmic(true)
+                                .suffix("x"),
+                        );
+                    } else {
+                        ui.add(Slider::new(&mut self.shift, 0.0..=1000.0).suffix(" Hz"));
+                    }
                 });
 
                 ui.separator();
@@ -379,7 +624,7 @@ impl App for Application {
                 ui.horizontal_wrapped(|ui| {
                     ui.checkbox(&mut self.full_spectrum, "Show full spectrum");
                     ui.checkbox(&mut self.phase, "Show phase");
-                    ui.checkbox(&mut self.decibels, "Decibels");
+                    ui.checkbox(&mut self.decibels, "Decibels (dBFS)");
                     ui.checkbox(&mut self.line, "Line Plot");
                     ui.checkbox(&mut self.stems, "Stems");
                 });
@@ -447,7 +692,26 @@ impl App for Application {
                     spectrum.freq_resolution()
                 ));
 
-                ui.label(format!("FFT algorithm: cfft_{}", fft_width));
+                // Real signals are transformed as a complex signal of half the width
+                ui.label(format!(
+                    "FFT algorithm: {} ({} samples)",
+                    fft::algorithm(fft_width / 2),
+                    fft_width
+                ));
+
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui.button("Save Reconstructed…").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("WAV", &["wav"])
+                        .save_file()
+                    {
+                        let format = audio::wav::WavFormat::Float32;
+
+                        if let Err(error) = reconstructed.save_wav(&path, format) {
+                            warn!(%error, path = %path.display(), "failed to save reconstruction");
+                        }
+                    }
+                }
             });
 
             CentralPanel::default().show(ctx, |ui| {
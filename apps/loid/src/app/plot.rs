@@ -24,12 +24,16 @@ pub fn waveform_display(
         .include_y(1.0)
         .include_y(-1.0)
         .show(ui, |ui| {
-            point_line(
-                ui,
-                "Original waveform",
-                Values::from_values_iter(waveform.time_domain().map(|(x, y)| Value::new(x, y))),
-                (line, stems),
-            );
+            for channel in 0..waveform.channels() {
+                point_line(
+                    ui,
+                    &format!("Original waveform (channel {channel})"),
+                    Values::from_values_iter(
+                        waveform.time_domain(channel).map(|(x, y)| Value::new(x, y)),
+                    ),
+                    (line, stems),
+                );
+            }
 
             // TODO:
             // ui.points(
@@ -82,7 +86,12 @@ pub fn window_display(
             point_line(
                 ui,
                 "Original samples",
-                Values::from_ys_f32(waveform.samples()),
+                Values::from_values_iter(
+                    waveform
+                        .downmix_iter()
+                        .enumerate()
+                        .map(|(i, sample)| Value::new(i as f32, sample)),
+                ),
                 (line, stems),
             );
 
@@ -101,7 +110,7 @@ pub fn window_display(
                 "Windowed samples",
                 Values::from_values_iter(
                     waveform
-                        .samples_iter()
+                        .downmix_iter()
                         .zip(window.into_iter(window_width))
                         .enumerate()
                         .map(|(i, (sample, w))| Value::new(i as f32, w * sample)),
@@ -172,36 +181,20 @@ fn display_spectrum(
     phase: bool,
     decibels: bool,
 ) {
-    // TODO: DECIBELS
-
-    #[inline(always)]
-    fn map(
-        iterator: impl Iterator<Item = f32>,
-        freq: impl Fn(usize) -> f64,
-        db: impl Fn(f32) -> f32,
-    ) -> Vec<Bar> {
-        iterator
-            .enumerate()
-            .map(|(bucket, mag)| Bar::new(freq(bucket), db(mag) as f64))
-            .collect()
-    }
-
-    let db = |mag: f32| -> f32 {
-        if decibels {
-            20.0 * if mag == 0.0 { 0.0 } else { mag.log10() }
-        } else {
-            mag
-        }
+    let values = match (phase, full_spectrum, decibels) {
+        (true, true, _) => spectrum.phases().collect::<Vec<_>>(),
+        (true, false, _) => spectrum.phases_real().collect(),
+        (false, true, false) => spectrum.magnitudes().collect(),
+        (false, false, false) => spectrum.magnitudes_real().collect(),
+        (false, true, true) => spectrum.dbfs().collect(),
+        (false, false, true) => spectrum.dbfs_real().collect(),
     };
 
-    let freq = |b| spectrum.freq_from_bucket(b);
-
-    let buckets = match (phase, full_spectrum) {
-        (true, true) => map(&mut spectrum.phases(), freq, db),
-        (true, false) => map(spectrum.phases_real(), freq, db),
-        (false, true) => map(spectrum.amplitudes(), freq, db),
-        (false, false) => map(spectrum.amplitudes_real(), freq, db),
-    };
+    let buckets = values
+        .iter()
+        .enumerate()
+        .map(|(bucket, &value)| Bar::new(spectrum.freq_from_bucket(bucket), value as f64))
+        .collect();
 
     ui.bar_chart(
         BarChart::new(buckets)
@@ -210,12 +203,12 @@ fn display_spectrum(
     );
 
     if !phase {
-        if let Some((bucket, max)) = spectrum.main_frequency() {
+        if let Some((bucket, _)) = spectrum.main_frequency() {
             let freq = spectrum.freq_from_bucket(bucket);
 
             ui.text(
                 Text::new(
-                    Value::new(freq, db(max)),
+                    Value::new(freq, values[bucket]),
                     RichText::new(format!("{:.2}Hz", freq)).monospace(),
                 )
                 .anchor(Align2::CENTER_BOTTOM),
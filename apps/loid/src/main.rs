@@ -3,12 +3,16 @@
 mod app;
 
 use app::Application;
-use audio::output::AudioSink;
+use audio::{
+    device::{DeviceSettings, OUTPUT_SETTINGS_KEY},
+    output::AudioSink,
+};
 use color_eyre::eyre::Context;
+use eframe::epi::Storage;
 use tracing::*;
 use util::install_tracing;
 
-pub fn init() -> color_eyre::Result<Application> {
+pub fn init(storage: Option<&dyn Storage>) -> color_eyre::Result<Application> {
     #[cfg(not(target_arch = "wasm32"))]
     color_eyre::install().wrap_err("failed to install color_eyre")?;
 
@@ -16,11 +20,22 @@ pub fn init() -> color_eyre::Result<Application> {
 
     trace!("Setting up audio");
 
-    let audio_sink = AudioSink::new().wrap_err("failed to setup audio sink")?;
+    let output_settings: DeviceSettings = storage
+        .and_then(|storage| eframe::epi::get_value(storage, OUTPUT_SETTINGS_KEY))
+        .unwrap_or_default();
+
+    // Fall back to the default device if the saved one is no longer available
+    let audio_sink = AudioSink::with_device(&output_settings)
+        .or_else(|error| {
+            warn!(%error, "unable to open the saved audio output");
+
+            AudioSink::new()
+        })
+        .wrap_err("failed to setup audio sink")?;
 
     info!("Starting Application");
 
-    Ok(Application::new(audio_sink))
+    Ok(Application::new(audio_sink, output_settings))
 }
 
 #[cfg(all(not(target_arch = "wasm32"), feature = "snmalloc"))]
@@ -33,7 +48,7 @@ fn main() -> color_eyre::Result<()> {
     eframe::run_native(
         "Fun with FFT",
         eframe::NativeOptions::default(),
-        Box::new(|_cc| Box::new(init().unwrap())),
+        Box::new(|cc| Box::new(init(cc.storage).unwrap())),
     )
 }
 
@@ -57,7 +72,8 @@ fn main() {
         console_error_panic_hook::hook(panic_info);
     }));
 
-    let app = match init() {
+    // The app is created before eframe starts, so settings are not restored on the web
+    let app = match init(None) {
         Ok(app) => app,
         Err(err) => {
             error!("Encountered error in application initialization");
@@ -1,19 +1,115 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::{
+    collections::BTreeMap,
+    f32::consts::SQRT_2,
+    fmt::{self, Display},
+    mem,
+    time::Duration,
+};
 
 use audio::waveform::Waveform;
 use eframe::epaint::{Color32, ColorImage};
-use spectrum::WaveformSpectrum;
+use spectrum::{
+    Chroma, ChromaOptions, ConstantQ, ConstantQOptions, Onset, OnsetDetector, OnsetFunction,
+    OnsetOptions, PeakInterpolation, PeakOptions, PitchAlgorithm, PitchOptions, PitchTracker,
+    WaveformSpectrum,
+};
+use tts::sing::Note;
+
+use crate::{harmony::Harmony, key::PianoKey};
+
+/// The level shown as the darkest color of the spectrogram, in dBFS
+const SPECTROGRAM_FLOOR: f32 = -100.0;
+/// Frames whose pitch is less certain than this are treated as unvoiced in monophonic mode
+const MIN_CONFIDENCE: f32 = 0.8;
+/// Notes held for fewer frames than this are dropped as glitches in monophonic mode
+const MIN_NOTE_FRAMES: usize = 3;
+/// How much louder than in the frame before a held key has to get at an onset to be struck
+/// again, in decibels
+const REATTACK_RISE: f32 = 3.0;
+
+/// Turn the key presses into a melody to sing, keeping only the highest key of the notes that
+/// start at the same time
+pub fn melody(notes: &BTreeMap<PianoKey, KeyPresses>) -> Vec<Note> {
+    let mut melody = BTreeMap::new();
+
+    // Keys are iterated from lowest to highest, so higher keys replace lower ones
+    for (key, key_presses) in notes {
+        for key_press in key_presses.iter() {
+            melody.insert(
+                key_press.start(),
+                Note {
+                    frequency: key.concert_pitch(),
+                    start: Duration::from_millis(key_press.start() as u64),
+                    duration: key_press.duration(),
+                },
+            );
+        }
+    }
+
+    melody.into_values().collect()
+}
+
+/// How the waveform is turned into a spectrogram and notes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalysisBackend {
+    /// Peaks of evenly spaced frequency buckets, which are too coarse for the lowest keys
+    Fft,
+    /// One bin centered on each piano key, every one a twelfth of an octave wide
+    ConstantQ,
+}
 
-use crate::key::PianoKey;
+impl Display for AnalysisBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fft => write!(f, "FFT"),
+            Self::ConstantQ => write!(f, "Constant-Q"),
+        }
+    }
+}
+
+impl AnalysisBackend {
+    pub const ALL: [AnalysisBackend; 2] = [Self::Fft, Self::ConstantQ];
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct AnalysisOptions {
+    pub backend: AnalysisBackend,
+
     pub fft_size: u8,
 
     pub window_fraction: f32,
     pub step_fraction: f32,
 
+    /// The quietest peak that presses a key, in dBFS
     pub threshold: f32,
+    /// How far below the threshold a pressed key has to fall to be released, in decibels
+    pub hysteresis: f32,
+    /// Notes held for less than this are dropped, in seconds
+    pub min_duration: f32,
+    /// How the starts of notes are found, which splits repeated notes of the same key
+    pub onset_function: OnsetFunction,
+    /// How the frequencies of the peaks are refined beyond the buckets of the FFT
+    pub interpolation: PeakInterpolation,
+
+    /// Transcribe a single line of notes with a pitch tracker instead of every loud bucket
+    pub monophonic: Option<PitchAlgorithm>,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            backend: AnalysisBackend::Fft,
+            fft_size: 14,
+            window_fraction: 0.5,
+            step_fraction: 1.0,
+            threshold: -40.0,
+            hysteresis: 10.0,
+            min_duration: 0.05,
+            onset_function: OnsetFunction::SpectralFlux,
+            interpolation: PeakInterpolation::Gaussian,
+            monophonic: None,
+        }
+    }
 }
 
 impl AnalysisOptions {
@@ -26,13 +122,25 @@ impl AnalysisOptions {
     pub fn step(&self) -> usize {
         (self.window_width() as f32 * self.step_fraction).ceil() as usize
     }
+
+    /// The level below which pressed keys are released, in dBFS
+    pub fn release_threshold(&self) -> f32 {
+        self.threshold - self.hysteresis
+    }
+
+    /// How hard a key was struck, from 0 at the release threshold to 1 at full scale
+    pub fn intensity(&self, level: f32) -> f32 {
+        let release = self.release_threshold();
+
+        ((spectrum::dbfs(level) - release) / (-release).max(f32::EPSILON)).clamp(0.0, 1.0)
+    }
 }
 
 pub fn analyze(
     waveform: &Waveform,
     options: AnalysisOptions,
     progress_callback: &dyn Fn(f32),
-) -> (BTreeMap<PianoKey, KeyPresses>, ColorImage) {
+) -> (BTreeMap<PianoKey, KeyPresses>, ColorImage, Harmony) {
     let fft_width = options.fft_width();
     let window_width = options.window_width();
     let step = options.step();
@@ -42,50 +150,304 @@ pub fn analyze(
         .map(|start| start..start + window_width);
     let window_count = dbg!(windows.len());
 
-    let seconds_per_window = window_width as f64 / waveform.sample_rate() as f64;
+    let seconds_per_step = step as f64 / waveform.sample_rate() as f64;
+
+    // The constant-Q transform sees the whole waveform, as its lowest keys need more samples
+    // than a window holds
+    let constant_q = match options.backend {
+        AnalysisBackend::ConstantQ => {
+            let sample_rate = waveform.sample_rate();
+            let bins = PianoKey::all()
+                .filter(|key| key.concert_pitch() < sample_rate as f32 / 2.0)
+                .count();
+            let transform = ConstantQ::new(
+                ConstantQOptions {
+                    bins,
+                    ..ConstantQOptions::piano()
+                },
+                sample_rate,
+            );
+
+            Some((transform, waveform.downmix_iter().collect::<Vec<_>>()))
+        }
+        AnalysisBackend::Fft => None,
+    };
 
-    let mut image = ColorImage::new([window_count, fft_width / 2], Color32::BLACK);
-    let mut keys = BTreeMap::<PianoKey, KeyPresses>::new();
+    let height = match &constant_q {
+        Some((transform, _)) => transform.bins(),
+        None => fft_width / 2,
+    };
+
+    let mut image = ColorImage::new([window_count, height], Color32::BLACK);
+    let mut frames = Vec::with_capacity(window_count);
+    let mut chroma = Vec::with_capacity(window_count);
 
     for (i, window) in windows.enumerate() {
         progress_callback(i as f32 / image.width() as f32);
 
-        let waveform = waveform.slice(window);
-        let spectrum = waveform.spectrum(spectrum::Window::Hann, fft_width);
+        let (levels, notes, window_chroma) = match &constant_q {
+            Some((transform, samples)) => {
+                let center = window.start + window_width / 2;
+                let magnitudes = transform
+                    .transform_at(samples, center as isize)
+                    .iter()
+                    .map(|bin| bin.norm())
+                    .collect::<Vec<_>>();
+
+                // The bins are the keys from A0 upwards, rows go from the highest key down like
+                // the piano roll
+                let levels = magnitudes
+                    .iter()
+                    .rev()
+                    .map(|&magnitude| spectrum::dbfs(magnitude))
+                    .collect::<Vec<_>>();
+
+                let threshold = spectrum::from_dbfs(options.release_threshold());
+                let notes = (0..magnitudes.len())
+                    .filter(|&bin| {
+                        let left = bin.checked_sub(1).map_or(0.0, |left| magnitudes[left]);
+                        let right = magnitudes.get(bin + 1).copied().unwrap_or(0.0);
+                        let magnitude = magnitudes[bin];
+
+                        magnitude >= threshold && magnitude > left && magnitude >= right
+                    })
+                    .filter_map(|bin| Some((PianoKey::new(bin as u8 + 1)?, magnitudes[bin])))
+                    .collect::<BTreeMap<_, _>>();
+
+                let components =
+                    (0..magnitudes.len()).map(|bin| (transform.frequency(bin), magnitudes[bin]));
+                let chroma = Chroma::from_components(components, ChromaOptions::default());
+
+                (levels, notes, chroma)
+            }
+            None => {
+                let waveform = waveform.slice(window);
+                let spectrum = waveform.spectrum(spectrum::Window::Hann, fft_width);
+
+                // Interpolate the peaks, buckets are too coarse to tell apart the lower keys
+                let peaks = spectrum.peaks(PeakOptions {
+                    interpolation: options.interpolation,
+                    threshold: spectrum::from_dbfs(options.release_threshold()),
+                    ..Default::default()
+                });
+
+                // Several peaks can round to the same key, which is as loud as the loudest
+                let mut notes = BTreeMap::<PianoKey, f32>::new();
+                for peak in peaks {
+                    if let Some(key) = PianoKey::from_concert_pitch(peak.frequency as f32) {
+                        let level = notes.entry(key).or_default();
+                        *level = level.max(peak.amplitude);
+                    }
+                }
+
+                (
+                    spectrum.dbfs_real().collect::<Vec<_>>(),
+                    notes,
+                    spectrum.chroma(ChromaOptions::default()),
+                )
+            }
+        };
+
+        chroma.push(window_chroma);
+        frames.push(notes);
 
         let width = image.width();
-        // let mut max = None;
-        for (pixel, (bucket, amplitude)) in image.pixels[i..]
-            .iter_mut()
-            .step_by(width)
-            .zip(spectrum.amplitudes_real().enumerate())
-        {
-            let color = colorous::VIRIDIS.eval_continuous(amplitude as f64);
+        for (pixel, level) in image.pixels[i..].iter_mut().step_by(width).zip(levels) {
+            let level = (1.0 - level / SPECTROGRAM_FLOOR).clamp(0.0, 1.0);
+            let color = colorous::VIRIDIS.eval_continuous(level as f64);
             *pixel = Color32::from_rgb(color.r, color.g, color.b);
+        }
+    }
 
-            // let max = max.get_or_insert((bucket, amplitude));
-            // if amplitude > max.1 {
-            //     *max = (bucket, amplitude)
-            // }
+    let keys = match options.monophonic {
+        Some(algorithm) => transcribe_monophonic(waveform, algorithm, &options),
+        None => {
+            let onsets = OnsetDetector::new(OnsetOptions {
+                function: options.onset_function,
+                ..Default::default()
+            })
+            .detect(waveform);
 
-            if amplitude < options.threshold {
-                continue;
-            }
+            segment(&frames, &onsets, &options, seconds_per_step)
+        }
+    };
+
+    // Frames quieter than a single note at the threshold have no chord
+    let min_energy = spectrum::from_dbfs(options.threshold).powi(2);
+    let harmony = Harmony::analyze(&chroma, seconds_per_step, min_energy);
+
+    (keys, image, harmony)
+}
 
-            let frequency = spectrum.freq_from_bucket(bucket) as f32;
-            let key = PianoKey::from_concert_pitch(frequency);
+/// A key that is pressed down while segmenting, with its times in seconds
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    start: f64,
+    /// The level in the last frame
+    level: f32,
+    /// The loudest level so far
+    peak: f32,
+}
+
+/// Turn the levels of the keys in every frame into notes
+///
+/// A key is pressed once its level reaches the threshold, and held until it falls below the
+/// release threshold. A held key that gets louder again at an onset is struck again, which
+/// splits repeated notes that never fall silent in between.
+fn segment(
+    frames: &[BTreeMap<PianoKey, f32>],
+    onsets: &[Onset],
+    options: &AnalysisOptions,
+    seconds_per_frame: f64,
+) -> BTreeMap<PianoKey, KeyPresses> {
+    let press = spectrum::from_dbfs(options.threshold);
+    let release = spectrum::from_dbfs(options.release_threshold());
+    let reattack = spectrum::from_dbfs(REATTACK_RISE);
+
+    // Overlapping windows reach past the start of the next frame, and a note heard in a frame
+    // may start anywhere in its window
+    let seconds_per_window = seconds_per_frame / options.step_fraction as f64;
+
+    let mut held = BTreeMap::<PianoKey, HeldKey>::new();
+    let mut released = Vec::<(PianoKey, HeldKey, f64)>::new();
+
+    for (frame, levels) in frames.iter().enumerate() {
+        let time = frame as f64 * seconds_per_frame;
+        let onset = onsets
+            .iter()
+            .map(|onset| onset.time as f64)
+            .find(|onset| (time..time + seconds_per_window).contains(onset));
+
+        for (key, note) in mem::take(&mut held) {
+            let level = levels.get(&key).copied().unwrap_or(0.0);
+
+            match onset {
+                _ if level < release => released.push((key, note, time)),
+                Some(onset) if level >= press && level >= note.level * reattack => {
+                    released.push((key, note, onset));
+                    held.insert(
+                        key,
+                        HeldKey {
+                            start: onset,
+                            level,
+                            peak: level,
+                        },
+                    );
+                }
+                _ => {
+                    held.insert(
+                        key,
+                        HeldKey {
+                            level,
+                            peak: note.peak.max(level),
+                            ..note
+                        },
+                    );
+                }
+            }
+        }
 
-            if let Some(key) = key {
-                keys.entry(key).or_default().add(KeyPress::new(
-                    (i as f64 * seconds_per_window * 1000.0).round() as u64,
-                    KeyDuration::from_secs_f64(seconds_per_window),
-                    amplitude,
-                ));
+        for (&key, &level) in levels {
+            if level >= press && !held.contains_key(&key) {
+                held.insert(
+                    key,
+                    HeldKey {
+                        start: onset.unwrap_or(time),
+                        level,
+                        peak: level,
+                    },
+                );
             }
         }
     }
 
-    (keys, image)
+    let end = frames.len() as f64 * seconds_per_frame;
+    released.extend(held.into_iter().map(|(key, note)| (key, note, end)));
+
+    let mut keys = BTreeMap::<PianoKey, KeyPresses>::new();
+    for (key, note, end) in released {
+        if end - note.start < options.min_duration as f64 {
+            continue;
+        }
+
+        let start = (note.start * 1000.0).round();
+        keys.entry(key).or_default().insert(KeyPress::new(
+            start as u64,
+            Duration::from_millis(((end * 1000.0).round() - start) as u64),
+            options.intensity(note.peak),
+        ));
+    }
+
+    keys
+}
+
+/// Follow the fundamental of the waveform, pressing one key at a time
+fn transcribe_monophonic(
+    waveform: &Waveform,
+    algorithm: PitchAlgorithm,
+    options: &AnalysisOptions,
+) -> BTreeMap<PianoKey, KeyPresses> {
+    let frame_width = options.window_width();
+    let hop = options.step();
+
+    let (lowest, highest) = match (PianoKey::all().next_back(), PianoKey::all().next()) {
+        (Some(lowest), Some(highest)) => (lowest, highest),
+        _ => unreachable!("there are 88 piano keys"),
+    };
+
+    let tracker = PitchTracker::new(PitchOptions {
+        algorithm,
+        min_frequency: lowest.concert_pitch(),
+        max_frequency: highest.concert_pitch(),
+        frame_width,
+        hop,
+        ..Default::default()
+    });
+
+    let samples = waveform.downmix_iter().collect::<Vec<_>>();
+    let frames = tracker
+        .track(waveform)
+        .into_iter()
+        .map(|frame| {
+            let pitch = frame
+                .pitch
+                .filter(|pitch| pitch.confidence >= MIN_CONFIDENCE)?;
+
+            PianoKey::from_concert_pitch(pitch.frequency)
+        })
+        .collect::<Vec<_>>();
+
+    // Frames start every hop, so consecutive frames of a note touch and are joined
+    let frame_start =
+        |frame: usize| (frame as f64 * hop as f64 / waveform.sample_rate() as f64 * 1000.0).round();
+
+    let mut keys = BTreeMap::<PianoKey, KeyPresses>::new();
+    let mut start = 0;
+    while start < frames.len() {
+        let key = frames[start];
+        let end = (start..frames.len())
+            .find(|&frame| frames[frame] != key)
+            .unwrap_or(frames.len());
+
+        if let Some(key) = key.filter(|_| end - start >= MIN_NOTE_FRAMES) {
+            // A sinusoid peaks at √2 times its RMS
+            let note = &samples[(start * hop).min(samples.len())
+                ..((end - 1) * hop + frame_width).min(samples.len())];
+            let rms = (note.iter().map(|sample| sample * sample).sum::<f32>()
+                / note.len().max(1) as f32)
+                .sqrt();
+
+            keys.entry(key).or_default().add(KeyPress::new(
+                frame_start(start) as u64,
+                Duration::from_millis((frame_start(end) - frame_start(start)) as u64),
+                options.intensity(rms * SQRT_2),
+            ));
+        }
+
+        start = end;
+    }
+
+    keys
 }
 
 // FIXME: better data representation?
@@ -144,6 +506,11 @@ impl KeyPress {
     pub fn intensity(&self) -> f32 {
         self.info.intensity
     }
+
+    /// The 7 bit MIDI velocity, from 1 at an intensity of 0 to 127 at an intensity of 1
+    pub fn velocity(&self) -> u8 {
+        (1.0 + self.intensity().clamp(0.0, 1.0) * 126.0).round() as u8
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -245,8 +612,105 @@ impl KeyPresses {
         self.key_list.insert(keypress.start, keypress.info);
     }
 
+    /// Add a key press as a note of its own, even if it touches the notes around it
+    pub fn insert(&mut self, keypress: KeyPress) {
+        self.key_list.insert(keypress.start, keypress.info);
+    }
+
     // FIXME: Does not verify duration
     pub fn remove(&mut self, keypress: &KeyPress) {
         self.key_list.remove(&keypress.start);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use spectrum::{Onset, OnsetFunction, PeakInterpolation};
+
+    use super::{segment, AnalysisBackend, AnalysisOptions};
+    use crate::key::PianoKey;
+
+    fn options() -> AnalysisOptions {
+        AnalysisOptions {
+            backend: AnalysisBackend::Fft,
+            fft_size: 14,
+            window_fraction: 0.5,
+            step_fraction: 1.0,
+            threshold: -20.0,
+            hysteresis: 10.0,
+            min_duration: 0.15,
+            onset_function: OnsetFunction::SpectralFlux,
+            interpolation: PeakInterpolation::Gaussian,
+            monophonic: None,
+        }
+    }
+
+    /// The notes of a single key as their start and duration in milliseconds
+    fn notes(levels: &[f32], onsets: &[f32]) -> Vec<(u128, u128)> {
+        notes_with(levels, onsets, &options())
+    }
+
+    fn notes_with(levels: &[f32], onsets: &[f32], options: &AnalysisOptions) -> Vec<(u128, u128)> {
+        let key = PianoKey::new(49).unwrap();
+
+        let frames = levels
+            .iter()
+            .map(|&level| BTreeMap::from([(key, level)]))
+            .collect::<Vec<_>>();
+        let onsets = onsets
+            .iter()
+            .map(|&time| Onset {
+                time,
+                strength: 1.0,
+            })
+            .collect::<Vec<_>>();
+
+        segment(&frames, &onsets, options, 0.1)
+            .get(&key)
+            .map(|presses| {
+                presses
+                    .iter()
+                    .map(|press| (press.start(), press.duration().as_millis()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn repeated_notes() {
+        // Struck at 0.1s and again at 0.45s while still ringing
+        let levels = [0.0, 0.5, 0.3, 0.2, 0.5, 0.3, 0.2, 0.0];
+
+        assert_eq!(notes(&levels, &[0.1, 0.45]), [(100, 350), (450, 250)]);
+        assert_eq!(notes(&levels, &[0.1]), [(100, 600)]);
+    }
+
+    #[test]
+    fn hysteresis() {
+        // Dips below the threshold of 0.1 but stays above the release threshold of about 0.03
+        let wobbling = [0.0, 0.2, 0.05, 0.2, 0.05, 0.2, 0.0];
+        assert_eq!(notes(&wobbling, &[]), [(100, 500)]);
+
+        // Never reaches the threshold
+        assert_eq!(notes(&[0.0, 0.05, 0.09, 0.05, 0.0], &[]), []);
+
+        // Too short to be a note
+        assert_eq!(notes(&[0.0, 0.5, 0.0, 0.0], &[0.1]), []);
+    }
+
+    #[test]
+    fn overlapping_windows() {
+        // Windows of 0.4s every 0.1s, so the note first heard in the frame at 0.2s may have
+        // started up to 0.4s later
+        let options = AnalysisOptions {
+            step_fraction: 0.25,
+            ..options()
+        };
+        let levels = [0.0, 0.0, 0.5, 0.5, 0.5, 0.5, 0.5, 0.0];
+
+        assert_eq!(notes_with(&levels, &[0.35], &options), [(350, 350)]);
+        assert_eq!(notes_with(&levels, &[0.65], &options), [(200, 500)]);
+    }
+}
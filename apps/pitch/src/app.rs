@@ -1,16 +1,26 @@
 use std::{
     collections::BTreeMap,
     path::PathBuf,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use atomic::Atomic;
-use audio::waveform::Waveform;
+use audio::{
+    decode::{AudioDecoder, ChannelSelection},
+    device::{output_settings_ui, DeviceInfo, DeviceSettings, OUTPUT_SETTINGS_KEY},
+    input::InputStream,
+    output::{AudioSink, PlaybackHandle, Sink},
+    waveform::Waveform,
+};
+use color_eyre::eyre::{eyre, WrapErr};
 use eframe::{
     egui::{
-        Button, CentralPanel, Context, Layout, ProgressBar, RichText, Slider, TextFormat,
+        Button, CentralPanel, Context, Layout, ProgressBar, RichText, Slider, TextEdit, TextFormat,
         TopBottomPanel, Ui, Visuals, Window,
     },
     emath::{Align, Align2},
@@ -18,19 +28,29 @@ use eframe::{
     epi::{self, App, Storage, APP_KEY},
 };
 use once_cell::sync::Lazy;
-use parking_lot::{RwLock, RwLockReadGuard};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use ritelinked::LinkedHashSet;
+use spectrum::{OnsetFunction, PeakInterpolation, PitchAlgorithm};
 use static_assertions::const_assert;
 
 use crate::{
-    analysis::{analyze, AnalysisOptions, KeyPress, KeyPresses},
-    decode::AudioDecoder,
+    analysis::{analyze, melody, AnalysisBackend, AnalysisOptions, KeyPress, KeyPresses},
+    evaluation::{Evaluation, EvaluationOptions},
+    harmony::{Harmony, Mode, MusicalKey},
     key::{Accidental, PianoKey},
     midi::{MidiPlayer, SongProgress},
     piano_roll::PianoRoll,
+    settings::midi_output_ui,
+    smf::{self, SmfFormat, SmfOptions, Song},
     ui_error::UiError,
 };
 
+/// How often the midi output ports are scanned for devices that were plugged in or out
+const MIDI_SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Counts the analyses, to tell them apart
+static ANALYSIS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Application {
     recently_opened_files: LinkedHashSet<PathBuf>,
 
@@ -38,24 +58,71 @@ pub struct Application {
     seconds_per_width: f32,
     key_height: f32,
     preference: Accidental,
+    /// The key to spell notes in, `None` to follow the key detected by the analysis
+    scale: Option<MusicalKey>,
+    /// The key of the last analysis, to notice when a new one is detected
+    detected_key: Option<MusicalKey>,
     spectrogram: bool,
 
+    /// Notes imported from a MIDI file, to compare the analysis to
+    reference: Option<Song>,
+    show_reference: bool,
+    evaluation_options: EvaluationOptions,
+    /// The evaluation of the analysis with the id against the reference with the options, kept
+    /// until any of them changes
+    evaluation: Option<(usize, EvaluationOptions, Evaluation)>,
+
     // FIXME: RWLock really useful at all?
     waveform: Arc<RwLock<Option<Waveform<'static>>>>,
     analysis: Arc<RwLock<Option<AudioAnalysis>>>,
     analysis_options: AnalysisOptions,
     status: Arc<Atomic<TaskProgress>>,
 
-    midi: MidiPlayer,
+    /// `None` if midi is not available
+    midi: Option<MidiPlayer>,
+    last_midi_scan: Instant,
     current_song: SongProgress,
 
+    /// `None` if no output device could be opened
+    audio_sink: Option<AudioSink>,
+    playback: Option<PlaybackHandle>,
+    output_settings: DeviceSettings,
+    output_devices: Vec<DeviceInfo>,
+
+    /// The microphone recording in progress, if any
+    recording: Option<InputStream>,
+    recorded_samples: Vec<f32>,
+
+    /// Whether the window to sing the notes is open
+    singing: bool,
+    lyrics: String,
+    language: String,
+    melody_source: MelodySource,
+
+    /// Whether the window to export the notes as a MIDI file is open
+    exporting: bool,
+    smf_options: SmfOptions,
+
     // Error reporting
     previous_error: Option<Box<dyn UiError>>,
+    background_error: Arc<Mutex<Option<Box<dyn UiError + Send>>>>,
 }
 
 struct AudioAnalysis {
+    /// Tells the analysis apart from the analyses before it
+    id: usize,
     notes: BTreeMap<PianoKey, KeyPresses>,
     spectrum: Option<TextureHandle>,
+    harmony: Harmony,
+}
+
+/// The notes that the lyrics are sung along
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MelodySource {
+    /// The notes of the analysis
+    Transcription,
+    /// The notes of the imported MIDI file
+    Reference,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -65,13 +132,17 @@ pub enum TaskProgress {
     Decoding(f32),
     Analyzing(f32),
     GeneratingSpectrogram,
+    Singing,
 }
 
 // Ensure that native atomic instructions are being used
 const_assert!(Atomic::<TaskProgress>::is_lock_free());
 
 impl Application {
-    pub fn new(recently_opened_files: LinkedHashSet<PathBuf>) -> Self {
+    pub fn new(
+        recently_opened_files: LinkedHashSet<PathBuf>,
+        output_settings: DeviceSettings,
+    ) -> Self {
         let test_pattern = PianoKey::all()
             .enumerate()
             .map(|(index, key)| {
@@ -95,26 +166,57 @@ impl Application {
 
         Self {
             previous_error: None,
+            background_error: Default::default(),
 
             recently_opened_files,
 
-            midi: MidiPlayer::new(crate::NAME),
+            midi: MidiPlayer::new(crate::NAME, &output_settings)
+                .map_err(|error| tracing::warn!(%error, "unable to open midi output"))
+                .ok(),
+            last_midi_scan: Instant::now(),
             current_song: SongProgress::new(),
 
+            audio_sink: AudioSink::with_device(&output_settings)
+                .or_else(|error| {
+                    tracing::warn!(%error, "unable to open the saved audio output");
+
+                    AudioSink::new()
+                })
+                .map_err(|error| tracing::warn!(%error, "unable to open audio output"))
+                .ok(),
+            playback: None,
+            output_settings,
+            output_devices: AudioSink::devices(),
+
+            recording: None,
+            recorded_samples: Vec::new(),
+
+            singing: false,
+            lyrics: String::new(),
+            language: "en-US".to_string(),
+            melody_source: MelodySource::Transcription,
+
+            exporting: false,
+            smf_options: SmfOptions::default(),
+
             seconds_per_width: 30.0,
             key_height: 10.0,
             preference: Accidental::Flat,
+            scale: None,
+            detected_key: None,
             spectrogram: true,
 
-            analysis_options: AnalysisOptions {
-                threshold: 100.0,
-                fft_size: 14,
-                window_fraction: 0.5,
-                step_fraction: 1.0,
-            },
+            reference: None,
+            show_reference: true,
+            evaluation_options: EvaluationOptions::default(),
+            evaluation: None,
+
+            analysis_options: AnalysisOptions::default(),
             analysis: Arc::new(RwLock::new(Some(AudioAnalysis {
+                id: ANALYSIS_COUNT.fetch_add(1, Ordering::SeqCst),
                 notes: test_pattern,
                 spectrum: None,
+                harmony: Harmony::default(),
             }))),
             waveform: Default::default(),
             status: Arc::new(Atomic::new(TaskProgress::None)),
@@ -128,7 +230,7 @@ impl Application {
     }
 
     fn open_file_inner(&mut self, path: PathBuf, ctx: Context) -> Result<(), Box<dyn UiError>> {
-        let (decoder, path) = AudioDecoder::create_for_file(path)?;
+        let decoder = AudioDecoder::open(&path)?;
 
         // Add to recently opened files if decoder created successfully
         self.recently_opened_files.insert(path);
@@ -136,6 +238,7 @@ impl Application {
         let status = self.status.clone();
         let waveform = self.waveform.clone();
         let analysis = self.analysis.clone();
+        let background_error = self.background_error.clone();
 
         thread::Builder::new()
             .name("file-decode".to_string())
@@ -143,13 +246,23 @@ impl Application {
                 status.store(TaskProgress::Decoding(0.0), Ordering::SeqCst);
                 ctx.request_repaint();
 
-                let new_waveform = decoder.decode(&|progress| {
-                    status.store(TaskProgress::Decoding(progress), Ordering::SeqCst);
+                let new_waveform = decoder.decode(ChannelSelection::All, |progress| {
+                    status.store(
+                        TaskProgress::Decoding(progress.fraction().unwrap_or_default()),
+                        Ordering::SeqCst,
+                    );
                     ctx.request_repaint();
                 });
 
-                *waveform.write() = Some(new_waveform);
-                *analysis.write() = None;
+                match new_waveform {
+                    Ok(new_waveform) => {
+                        *waveform.write() = Some(new_waveform);
+                        *analysis.write() = None;
+                    }
+                    Err(error) => {
+                        *background_error.lock() = Some(Box::new(error));
+                    }
+                }
 
                 status.store(TaskProgress::None, Ordering::SeqCst);
                 ctx.request_repaint();
@@ -159,6 +272,132 @@ impl Application {
         Ok(())
     }
 
+    fn import_midi(&mut self, path: PathBuf) {
+        match smf::import(&path) {
+            Ok(song) => {
+                if song.out_of_range > 0 {
+                    tracing::warn!(
+                        out_of_range = song.out_of_range,
+                        "left out the notes outside the piano"
+                    );
+                }
+
+                self.reference = Some(song);
+                self.evaluation = None;
+            }
+            Err(error) => self.previous_error = Some(Box::new(error)),
+        }
+    }
+
+    fn start_recording(&mut self) {
+        match InputStream::new(ChannelSelection::All, Duration::from_secs(1)) {
+            Ok(recording) => {
+                self.recorded_samples.clear();
+                self.recording = Some(recording);
+            }
+            Err(error) => self.previous_error = Some(Box::new(error)),
+        }
+    }
+
+    /// Replace the waveform with everything recorded so far and analyze it
+    fn stop_recording(&mut self, ctx: Context) {
+        let mut recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => return,
+        };
+
+        recording.read(&mut self.recorded_samples);
+
+        if self.recorded_samples.is_empty() {
+            tracing::warn!("nothing was recorded");
+
+            return;
+        }
+
+        *self.waveform.write() = Some(Waveform::new_interleaved(
+            std::mem::take(&mut self.recorded_samples),
+            recording.channels(),
+            recording.sample_rate(),
+        ));
+        *self.analysis.write() = None;
+
+        self.analyze_waveform(ctx);
+    }
+
+    /// Replace the waveform with the lyrics sung along the analyzed or the reference notes
+    fn sing(&self, ctx: Context) {
+        let notes = match self.melody_source {
+            MelodySource::Transcription => match self.analysis.read().as_ref() {
+                Some(analysis) => melody(&analysis.notes),
+                None => return,
+            },
+            MelodySource::Reference => match &self.reference {
+                Some(reference) => melody(&reference.notes),
+                None => return,
+            },
+        };
+
+        let status = self.status.clone();
+        let waveform = self.waveform.clone();
+        let background_error = self.background_error.clone();
+        let lyrics = self.lyrics.clone();
+        let language = self.language.clone();
+
+        thread::Builder::new()
+            .name("sing".to_string())
+            .spawn(move || {
+                status.store(TaskProgress::Singing, Ordering::SeqCst);
+                ctx.request_repaint();
+
+                // The engine can not be sent between threads, so it is created on this one
+                let sung = tts::load_language(&language)
+                    .map_err(|error| eyre!(error))
+                    .and_then(tts::setup_tts)
+                    .and_then(|mut engine| tts::sing::sing(&mut engine, &lyrics, &notes))
+                    .wrap_err("unable to sing the lyrics");
+
+                match sung {
+                    Ok(sung) => *waveform.write() = Some(sung),
+                    Err(error) => *background_error.lock() = Some(Box::new(error)),
+                }
+
+                status.store(TaskProgress::None, Ordering::SeqCst);
+                ctx.request_repaint();
+            })
+            .expect("unable to spawn singing thread");
+    }
+
+    fn reopen_audio_sink(&mut self) {
+        // Stop playback before dropping the old output stream
+        self.playback = None;
+        self.audio_sink = None;
+
+        match AudioSink::with_device(&self.output_settings) {
+            Ok(audio_sink) => self.audio_sink = Some(audio_sink),
+            Err(error) => self.previous_error = Some(Box::new(error)),
+        }
+
+        if let Some(midi) = &self.midi {
+            midi.reopen_synth(&self.output_settings);
+        }
+    }
+
+    fn rescan_midi(&mut self) {
+        self.last_midi_scan = Instant::now();
+
+        if let Some(Err(error)) = self.midi.as_mut().map(MidiPlayer::rescan) {
+            self.previous_error = Some(Box::new(error));
+        }
+    }
+
+    /// Spell the notes with the accidentals of the key signature, keeping the preference for
+    /// keys without any
+    fn spell_in(&mut self, key: Option<MusicalKey>) {
+        if let Some(accidental) = key.and_then(|key| key.accidental()) {
+            self.preference = accidental;
+        }
+    }
+
     fn analyze_waveform(&self, ctx: Context) {
         let status = self.status.clone();
         let waveform = self.waveform.clone();
@@ -181,7 +420,7 @@ impl Application {
                     }
                 };
 
-                let (notes, image) = analyze(waveform, analysis_options, &|progress| {
+                let (notes, image, harmony) = analyze(waveform, analysis_options, &|progress| {
                     status.store(TaskProgress::Analyzing(progress), Ordering::SeqCst);
                     ctx.request_repaint();
                 });
@@ -208,7 +447,12 @@ impl Application {
                         Some(ctx.load_texture("fft-spectrum", image))
                     };
 
-                *analysis.write() = Some(AudioAnalysis { notes, spectrum });
+                *analysis.write() = Some(AudioAnalysis {
+                    id: ANALYSIS_COUNT.fetch_add(1, Ordering::SeqCst),
+                    notes,
+                    spectrum,
+                    harmony,
+                });
 
                 status.store(TaskProgress::None, Ordering::SeqCst);
                 ctx.request_repaint();
@@ -253,18 +497,52 @@ impl Application {
         // Open dropped files
         // TODO: support multiple and web
         if let Some(dropped_file) = ui.input_mut().raw.dropped_files.pop() {
-            self.open_file(
-                dropped_file
-                    .path
-                    .expect("drag and drop not supported on web platform yet"),
-                ui.ctx().clone(),
-            )
+            let path = dropped_file
+                .path
+                .expect("drag and drop not supported on web platform yet");
+
+            // MIDI files are references to compare to, anything else is audio
+            let is_midi = path.extension().is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi")
+            });
+            if is_midi {
+                self.import_midi(path);
+            } else {
+                self.open_file(path, ui.ctx().clone())
+            }
         }
     }
 }
 
 impl App for Application {
     fn update(&mut self, ctx: &Context, frame: &mut epi::Frame) {
+        // Pick up any errors that happened on the background threads
+        if let Some(error) = self.background_error.lock().take() {
+            self.previous_error = Some(error);
+        }
+
+        // Pick up any errors of the midi thread, and notice midi devices being plugged in or out
+        if let Some(error) = self.midi.as_ref().and_then(MidiPlayer::take_error) {
+            self.previous_error = Some(Box::new(error));
+        }
+        if self.last_midi_scan.elapsed() >= MIDI_SCAN_INTERVAL {
+            self.rescan_midi();
+        }
+
+        // Follow the key of a new analysis, unless a scale was chosen
+        let detected_key = self
+            .analysis
+            .read()
+            .as_ref()
+            .and_then(|analysis| analysis.harmony.key);
+        if detected_key != self.detected_key {
+            self.detected_key = detected_key;
+
+            if self.scale.is_none() {
+                self.spell_in(detected_key);
+            }
+        }
+
         if let Some(error) = self.previous_error.take() {
             Window::new("Error")
                 .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
@@ -294,6 +572,7 @@ impl App for Application {
                 TaskProgress::Analyzing(progress) => Some(("Analyzing", progress)),
                 // TODO: Better display for this
                 TaskProgress::GeneratingSpectrogram => Some(("Generating Spectrogram", 0.5)),
+                TaskProgress::Singing => Some(("Singing", 0.5)),
             };
 
             if let Some((step, progress)) = analysis {
@@ -311,6 +590,131 @@ impl App for Application {
             }
         }
 
+        // Keep the ring buffer of the recording from filling up
+        if let Some(recording) = &mut self.recording {
+            recording.read(&mut self.recorded_samples);
+            ctx.request_repaint();
+        }
+
+        if self.singing {
+            let mut open = true;
+
+            Window::new("Sing")
+                .open(&mut open)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.label("Lyrics, one syllable per note (mark syllables with hyphens)");
+                    ui.add(TextEdit::multiline(&mut self.lyrics).hint_text("twin-kle twin-kle"));
+
+                    ui.horizontal(|ui| {
+                        ui.label("Language");
+                        ui.text_edit_singleline(&mut self.language);
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Melody");
+                        ui.selectable_value(
+                            &mut self.melody_source,
+                            MelodySource::Transcription,
+                            "Transcription",
+                        );
+                        ui.selectable_value(
+                            &mut self.melody_source,
+                            MelodySource::Reference,
+                            "Reference",
+                        );
+                    });
+
+                    let (has_notes, hint) = match self.melody_source {
+                        MelodySource::Transcription => (
+                            self.analysis
+                                .read()
+                                .as_ref()
+                                .is_some_and(|analysis| !analysis.notes.is_empty()),
+                            "Sing along the analyzed notes",
+                        ),
+                        MelodySource::Reference => (
+                            self.reference
+                                .as_ref()
+                                .is_some_and(|reference| !reference.notes.is_empty()),
+                            "Sing along the notes of an imported MIDI file",
+                        ),
+                    };
+
+                    if ui
+                        .add_enabled(has_notes, Button::new("Sing"))
+                        .on_disabled_hover_text(hint)
+                        .clicked()
+                    {
+                        self.sing(ctx.clone());
+                    }
+                });
+
+            self.singing = open;
+        }
+
+        if self.exporting {
+            let mut open = true;
+
+            Window::new("Export MIDI")
+                .open(&mut open)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Format:");
+                        for format in SmfFormat::ALL {
+                            ui.selectable_value(
+                                &mut self.smf_options.format,
+                                format,
+                                format.to_string(),
+                            );
+                        }
+                    });
+                    ui.add(
+                        Slider::new(&mut self.smf_options.tempo, 20.0..=300.0)
+                            .text("Tempo")
+                            .suffix(" bpm"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("Ticks per quarter note:");
+                        for ppq in [96, 192, 480, 960] {
+                            ui.selectable_value(&mut self.smf_options.ppq, ppq, ppq.to_string());
+                        }
+                    });
+
+                    let has_notes = self
+                        .analysis
+                        .read()
+                        .as_ref()
+                        .is_some_and(|analysis| !analysis.notes.is_empty());
+
+                    if ui
+                        .add_enabled(has_notes, Button::new("Export…"))
+                        .on_disabled_hover_text("Export the analyzed notes")
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("MIDI", &["mid", "midi"])
+                            .set_file_name("notes.mid")
+                            .save_file()
+                        {
+                            let exported = match self.analysis.read().as_ref() {
+                                Some(analysis) => {
+                                    smf::export(&path, &analysis.notes, self.smf_options)
+                                }
+                                None => Ok(()),
+                            };
+
+                            if let Err(error) = exported {
+                                self.previous_error = Some(Box::new(error));
+                            }
+                        }
+                    }
+                });
+
+            self.exporting = open;
+        }
+
         TopBottomPanel::top("nav_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.menu_button("File", |ui| {
@@ -321,6 +725,37 @@ impl App for Application {
                             self.open_file(path, ctx.clone());
                         }
                     }
+                    if self.recording.is_some() {
+                        if ui.button("Stop Recording").clicked() {
+                            ui.close_menu();
+
+                            self.stop_recording(ctx.clone());
+                        }
+                    } else if ui.button("Record…").clicked() {
+                        ui.close_menu();
+
+                        self.start_recording();
+                    }
+                    if ui.button("Sing…").clicked() {
+                        ui.close_menu();
+
+                        self.singing = true;
+                    }
+                    if ui.button("Import MIDI…").clicked() {
+                        ui.close_menu();
+
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("MIDI", &["mid", "midi"])
+                            .pick_file()
+                        {
+                            self.import_midi(path);
+                        }
+                    }
+                    if ui.button("Export MIDI…").clicked() {
+                        ui.close_menu();
+
+                        self.exporting = true;
+                    }
                     ui.add_enabled_ui(!self.recently_opened_files.is_empty(), |ui| {
                         ui.menu_button("Open Recent", |ui| {
                             let mut selected_file = None;
@@ -380,6 +815,32 @@ impl App for Application {
                         });
                     });
                 });
+                ui.menu_button("Settings", |ui| {
+                    if output_settings_ui(ui, &mut self.output_settings, &self.output_devices) {
+                        ui.close_menu();
+
+                        self.reopen_audio_sink();
+                    }
+
+                    ui.add_enabled_ui(self.midi.is_some(), |ui| {
+                        ui.menu_button("MIDI Output", |ui| {
+                            if let Some(midi) = &mut self.midi {
+                                if let Err(error) = midi_output_ui(ui, midi) {
+                                    ui.close_menu();
+
+                                    self.previous_error = Some(Box::new(error));
+                                }
+                            }
+                        });
+                    });
+
+                    ui.separator();
+
+                    if ui.button("Rescan Devices").clicked() {
+                        self.output_devices = AudioSink::devices();
+                        self.rescan_midi();
+                    }
+                });
                 ui.menu_button("View", |ui| {
                     ui.menu_button("Accidental Preference", |ui| {
                         // TODO: add font with the flat+sharp chars
@@ -388,8 +849,41 @@ impl App for Application {
 
                         ui.separator();
 
-                        // TODO: Scales
-                        ui.add_enabled_ui(false, |ui| ui.menu_button("Scale", |_ui| {}))
+                        ui.menu_button("Scale", |ui| {
+                            let detected = match self.detected_key {
+                                Some(key) => format!("Detected ({key})"),
+                                None => "Detected".to_string(),
+                            };
+                            if ui
+                                .selectable_value(&mut self.scale, None, detected)
+                                .clicked()
+                            {
+                                self.spell_in(self.detected_key);
+                            }
+
+                            ui.separator();
+
+                            ui.horizontal(|ui| {
+                                for mode in Mode::ALL {
+                                    ui.vertical(|ui| {
+                                        for key in
+                                            MusicalKey::all().filter(|key| key.mode() == mode)
+                                        {
+                                            if ui
+                                                .selectable_value(
+                                                    &mut self.scale,
+                                                    Some(key),
+                                                    key.to_string(),
+                                                )
+                                                .clicked()
+                                            {
+                                                self.spell_in(Some(key));
+                                            }
+                                        }
+                                    });
+                                }
+                            });
+                        });
                     });
 
                     ui.menu_button("Theme", |ui| {
@@ -429,11 +923,52 @@ impl App for Application {
                         if ui.button("Unload").clicked() {
                             *self.waveform.write() = None;
                             *self.analysis.write() = None;
+
+                            if let Some(audio_sink) = &self.audio_sink {
+                                audio_sink.clear();
+                            }
                         }
 
                         let waveform = self.waveform.read();
                         let waveform = waveform.as_ref();
 
+                        // Drop the handle once the sink is done with the waveform
+                        if self
+                            .playback
+                            .as_ref()
+                            .is_some_and(PlaybackHandle::is_finished)
+                        {
+                            self.playback = None;
+                        }
+
+                        ui.horizontal(|ui| match (&self.playback, &self.audio_sink, waveform) {
+                            (Some(playback), _, _) => {
+                                if playback.is_paused() {
+                                    if ui.button("Resume").clicked() {
+                                        playback.resume();
+                                    }
+                                } else if ui.button("Pause").clicked() {
+                                    playback.pause();
+                                }
+
+                                if ui.button("Stop").clicked() {
+                                    playback.stop();
+                                }
+                            }
+                            (None, Some(audio_sink), Some(waveform)) => {
+                                if ui.button("Play").clicked() {
+                                    let ctx = ctx.clone();
+
+                                    self.playback = Some(
+                                        audio_sink.queue(waveform, move |_| ctx.request_repaint()),
+                                    );
+                                }
+                            }
+                            (None, _, _) => {
+                                ui.add_enabled(false, Button::new("Play"));
+                            }
+                        });
+
                         ui.label(format!(
                             "Duration: {:.2}s",
                             waveform.map(|w| w.duration()).unwrap_or(f32::NAN)
@@ -442,6 +977,10 @@ impl App for Application {
                             "Sample Rate: {} Hz",
                             waveform.map(|w| w.sample_rate()).unwrap_or_default()
                         ));
+                        ui.label(format!(
+                            "Channels: {}",
+                            waveform.map(|w| w.channels()).unwrap_or_default()
+                        ));
                         ui.label(format!(
                             "Samples: {}",
                             waveform.map(|w| w.len()).unwrap_or_default()
@@ -455,7 +994,7 @@ impl App for Application {
                         ui.set_enabled(waveform.is_some());
                         ui.horizontal(|ui| {
                             ui.add(
-                                Slider::new(&mut self.analysis_options.fft_size, 1..=14)
+                                Slider::new(&mut self.analysis_options.fft_size, 1..=20)
                                     .text("FFT Width")
                                     .prefix("2^")
                                     .suffix(" samples"),
@@ -500,10 +1039,83 @@ impl App for Application {
                             ));
                         });
 
-                        ui.add(
-                            Slider::new(&mut self.analysis_options.threshold, 0.0..=1000.0)
-                                .text("Note threshold"),
+                        ui.add_enabled(
+                            self.analysis_options.monophonic.is_none(),
+                            Slider::new(&mut self.analysis_options.threshold, -100.0..=0.0)
+                                .text("Note threshold")
+                                .suffix(" dBFS"),
+                        );
+                        ui.add_enabled(
+                            self.analysis_options.monophonic.is_none(),
+                            Slider::new(&mut self.analysis_options.hysteresis, 0.0..=40.0)
+                                .text("Release hysteresis")
+                                .suffix(" dB"),
                         );
+                        ui.add_enabled(
+                            self.analysis_options.monophonic.is_none(),
+                            Slider::new(&mut self.analysis_options.min_duration, 0.0..=1.0)
+                                .text("Minimum duration")
+                                .suffix(" s"),
+                        );
+
+                        ui.add_enabled_ui(self.analysis_options.monophonic.is_none(), |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Onsets:");
+                                for function in OnsetFunction::ALL {
+                                    ui.selectable_value(
+                                        &mut self.analysis_options.onset_function,
+                                        function,
+                                        function.to_string(),
+                                    );
+                                }
+                            });
+                        });
+
+                        ui.add_enabled_ui(self.analysis_options.monophonic.is_none(), |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Backend:");
+                                for backend in AnalysisBackend::ALL {
+                                    ui.selectable_value(
+                                        &mut self.analysis_options.backend,
+                                        backend,
+                                        backend.to_string(),
+                                    );
+                                }
+                            });
+                        });
+
+                        ui.add_enabled_ui(
+                            self.analysis_options.monophonic.is_none()
+                                && self.analysis_options.backend == AnalysisBackend::Fft,
+                            |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Peak interpolation:");
+                                    for interpolation in PeakInterpolation::ALL {
+                                        ui.selectable_value(
+                                            &mut self.analysis_options.interpolation,
+                                            interpolation,
+                                            interpolation.to_string(),
+                                        );
+                                    }
+                                });
+                            },
+                        );
+
+                        ui.horizontal(|ui| {
+                            ui.label("Notes:");
+                            ui.selectable_value(
+                                &mut self.analysis_options.monophonic,
+                                None,
+                                "Polyphonic",
+                            );
+                            for algorithm in PitchAlgorithm::ALL {
+                                ui.selectable_value(
+                                    &mut self.analysis_options.monophonic,
+                                    Some(algorithm),
+                                    format!("Monophonic ({algorithm})"),
+                                );
+                            }
+                        });
 
                         drop(waveform);
 
@@ -512,6 +1124,7 @@ impl App for Application {
                         }
                     });
 
+                    let analysis_id = analysis.read().as_ref().map(|analysis| analysis.id);
                     let notes = ui
                         .vertical(|ui| {
                             ui.heading("Analysis");
@@ -551,6 +1164,10 @@ impl App for Application {
                                 ui.label(format!("Loaded {} notes", notes_count));
                             }
 
+                            if let Some(key) = self.detected_key {
+                                ui.label(format!("Key: {key}"));
+                            }
+
                             ui.horizontal(|ui| match self.current_song.upgrade() {
                                 Some(progress) => {
                                     if ui.button("Stop Playing").clicked() {
@@ -558,9 +1175,13 @@ impl App for Application {
                                     }
                                 }
                                 None => {
-                                    if ui.button("Play Notes").clicked() {
-                                        self.current_song =
-                                            self.midi.play_song(&notes, ctx.clone());
+                                    if ui
+                                        .add_enabled(self.midi.is_some(), Button::new("Play Notes"))
+                                        .clicked()
+                                    {
+                                        if let Some(midi) = &self.midi {
+                                            self.current_song = midi.play_song(&notes, ctx.clone());
+                                        }
                                     }
                                 }
                             });
@@ -569,9 +1190,89 @@ impl App for Application {
                         })
                         .inner;
 
+                    ui.vertical(|ui| {
+                        ui.heading("Reference");
+
+                        ui.set_enabled(self.reference.is_some());
+
+                        match &self.reference {
+                            Some(reference) => {
+                                ui.label(format!(
+                                    "Loaded {} notes",
+                                    reference
+                                        .notes
+                                        .values()
+                                        .map(|key_presses| key_presses.len())
+                                        .sum::<usize>()
+                                ));
+                                if reference.out_of_range > 0 {
+                                    ui.label(format!(
+                                        "Left out {} notes outside the piano",
+                                        reference.out_of_range
+                                    ));
+                                }
+
+                                // Score the analysis against the reference, matching the notes
+                                // again only once either of them or the options change
+                                if let Some(id) = analysis_id.filter(|_| !notes.is_empty()) {
+                                    ui.add(
+                                        Slider::new(
+                                            &mut self.evaluation_options.onset_tolerance,
+                                            0.01..=0.2,
+                                        )
+                                        .text("Onset tolerance")
+                                        .suffix("s"),
+                                    );
+
+                                    let options = self.evaluation_options;
+                                    let evaluation = match self.evaluation {
+                                        Some((cached_id, cached_options, evaluation))
+                                            if cached_id == id && cached_options == options =>
+                                        {
+                                            evaluation
+                                        }
+                                        _ => {
+                                            let evaluation =
+                                                Evaluation::new(&reference.notes, &notes, options);
+                                            self.evaluation = Some((id, options, evaluation));
+
+                                            evaluation
+                                        }
+                                    };
+                                    ui.label(evaluation.to_string());
+                                }
+                            }
+                            None => {
+                                ui.label("Import a MIDI file to compare to");
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            if ui
+                                .add_enabled(self.midi.is_some(), Button::new("Play Reference"))
+                                .clicked()
+                            {
+                                if let (Some(reference), Some(midi)) = (&self.reference, &self.midi)
+                                {
+                                    if let Some(progress) = self.current_song.upgrade() {
+                                        progress.cancel();
+                                    }
+
+                                    self.current_song =
+                                        midi.play_song(&reference.notes, ctx.clone());
+                                }
+                            }
+                            if ui.button("Unload").clicked() {
+                                self.reference = None;
+                                self.evaluation = None;
+                            }
+                        });
+                    });
+
                     ui.vertical(|ui| {
                         ui.heading("Visualization");
                         ui.checkbox(&mut self.spectrogram, "Show Spectrogram");
+                        ui.checkbox(&mut self.show_reference, "Show Reference");
                         ui.add(
                             Slider::new(&mut self.seconds_per_width, 1.0..=100.0).text("Scale X"),
                         );
@@ -592,14 +1293,35 @@ impl App for Application {
                     None
                 };
 
+                let chords = analysis
+                    .as_ref()
+                    .map(|analysis| analysis.harmony.chords.as_slice())
+                    .unwrap_or_default();
+
+                // Follow whichever of the notes or the decoded audio is playing
+                let cursor = self
+                    .current_song
+                    .upgrade()
+                    .map(|progress| progress.time())
+                    .or_else(|| {
+                        self.playback.as_ref().map(|playback| {
+                            playback.position() as f32 / playback.sample_rate() as f32
+                        })
+                    });
+
                 ui.add(PianoRoll::new(
-                    &self.midi,
+                    self.midi.as_ref(),
                     self.preference,
-                    self.current_song.upgrade().map(|progress| progress.time()),
+                    cursor,
                     self.key_height,
                     self.seconds_per_width,
                     &notes,
+                    self.reference
+                        .as_ref()
+                        .filter(|_| self.show_reference)
+                        .map(|reference| &reference.notes),
                     spectrum,
+                    chords,
                 ));
             }
 
@@ -609,6 +1331,7 @@ impl App for Application {
 
     fn save(&mut self, storage: &mut dyn Storage) {
         epi::set_value(storage, APP_KEY, &self.recently_opened_files);
+        epi::set_value(storage, OUTPUT_SETTINGS_KEY, &self.output_settings);
     }
 
     fn persist_native_window(&self) -> bool {
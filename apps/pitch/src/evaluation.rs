@@ -0,0 +1,451 @@
+//! Note-level evaluation of a transcription against reference notes, as done by mir_eval
+//!
+//! A transcribed note matches a reference note of the same key if it starts within the onset
+//! tolerance of it and, unless only onsets are evaluated, ends within the offset tolerance of
+//! it. Every note is matched at most once, and the largest possible set of matches is counted.
+//! Precision is the fraction of transcribed notes that are matched, recall the fraction of
+//! reference notes that are matched.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use audio::decode::{AudioDecoder, ChannelSelection};
+use color_eyre::eyre::{bail, ensure, eyre, WrapErr};
+
+use crate::{
+    analysis::{analyze, AnalysisOptions, KeyPress, KeyPresses},
+    key::PianoKey,
+    smf,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvaluationOptions {
+    /// How far the start of a note may be from the start of the reference, in seconds
+    pub onset_tolerance: f32,
+    /// How far the end of a note may be from the end of the reference, as a fraction of the
+    /// duration of the reference, `None` to only evaluate onsets
+    pub offset_ratio: Option<f32>,
+    /// The offset tolerance of short notes, in seconds
+    pub offset_min_tolerance: f32,
+}
+
+impl Default for EvaluationOptions {
+    fn default() -> Self {
+        Self {
+            onset_tolerance: 0.05,
+            offset_ratio: Some(0.2),
+            offset_min_tolerance: 0.05,
+        }
+    }
+}
+
+impl EvaluationOptions {
+    fn matches(&self, reference: &KeyPress, estimate: &KeyPress) -> bool {
+        let distance =
+            |reference: u128, estimate: u128| (reference as f32 - estimate as f32).abs() / 1000.0;
+        let end = |key_press: &KeyPress| key_press.start() + key_press.duration().as_millis();
+
+        let onset_matches = distance(reference.start(), estimate.start()) <= self.onset_tolerance;
+        let offset_matches = self.offset_ratio.is_none_or(|ratio| {
+            let tolerance = (ratio * reference.duration_secs()).max(self.offset_min_tolerance);
+
+            distance(end(reference), end(estimate)) <= tolerance
+        });
+
+        onset_matches && offset_matches
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Scores {
+    pub reference_notes: usize,
+    pub estimated_notes: usize,
+    pub matched_notes: usize,
+    pub precision: f32,
+    pub recall: f32,
+    /// The harmonic mean of the precision and the recall
+    pub f_measure: f32,
+    /// How much the matched notes overlap, their shared time over their combined time
+    pub average_overlap_ratio: f32,
+}
+
+impl Display for Scores {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "F1 {:.3} (precision {:.3}, recall {:.3}, overlap {:.3}), {} of {} notes matched",
+            self.f_measure,
+            self.precision,
+            self.recall,
+            self.average_overlap_ratio,
+            self.matched_notes,
+            self.reference_notes
+        )
+    }
+}
+
+/// The scores of a transcription with and without its offsets
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Evaluation {
+    /// Notes match if they start together
+    pub onsets: Scores,
+    /// Notes match if they start and end together, using the offset ratio of the options
+    pub offsets: Scores,
+}
+
+impl Evaluation {
+    pub fn new(
+        reference: &BTreeMap<PianoKey, KeyPresses>,
+        estimate: &BTreeMap<PianoKey, KeyPresses>,
+        options: EvaluationOptions,
+    ) -> Self {
+        Self {
+            onsets: evaluate(
+                reference,
+                estimate,
+                EvaluationOptions {
+                    offset_ratio: None,
+                    ..options
+                },
+            ),
+            offsets: evaluate(reference, estimate, options),
+        }
+    }
+}
+
+impl Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Onsets: {}", self.onsets)?;
+        write!(f, "Onsets and offsets: {}", self.offsets)
+    }
+}
+
+/// Score the estimated notes against the reference notes
+pub fn evaluate(
+    reference: &BTreeMap<PianoKey, KeyPresses>,
+    estimate: &BTreeMap<PianoKey, KeyPresses>,
+    options: EvaluationOptions,
+) -> Scores {
+    let count =
+        |notes: &BTreeMap<PianoKey, KeyPresses>| notes.values().map(KeyPresses::len).sum::<usize>();
+    let (reference_notes, estimated_notes) = (count(reference), count(estimate));
+
+    // Notes of different keys never match, so every key is matched on its own
+    let mut overlap_ratios = Vec::new();
+    for (key, reference_presses) in reference {
+        let estimated_presses = match estimate.get(key) {
+            Some(estimated_presses) => estimated_presses.iter().collect::<Vec<_>>(),
+            None => continue,
+        };
+        let reference_presses = reference_presses.iter().collect::<Vec<_>>();
+
+        let candidates = reference_presses
+            .iter()
+            .map(|reference| {
+                (0..estimated_presses.len())
+                    .filter(|&estimate| options.matches(reference, &estimated_presses[estimate]))
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+
+        for (reference, estimate) in maximum_matching(&candidates, estimated_presses.len()) {
+            let (reference, estimate) = (reference_presses[reference], estimated_presses[estimate]);
+
+            let overlap = reference.end_secs().min(estimate.end_secs())
+                - reference.start_secs().max(estimate.start_secs());
+            let span = reference.end_secs().max(estimate.end_secs())
+                - reference.start_secs().min(estimate.start_secs());
+            overlap_ratios.push(if span > 0.0 {
+                overlap.max(0.0) / span
+            } else {
+                1.0
+            });
+        }
+    }
+
+    let matched_notes = overlap_ratios.len();
+    let ratio = |count: usize, total: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            count as f32 / total as f32
+        }
+    };
+    let precision = ratio(matched_notes, estimated_notes);
+    let recall = ratio(matched_notes, reference_notes);
+
+    Scores {
+        reference_notes,
+        estimated_notes,
+        matched_notes,
+        precision,
+        recall,
+        f_measure: if precision + recall > 0.0 {
+            2.0 * precision * recall / (precision + recall)
+        } else {
+            0.0
+        },
+        average_overlap_ratio: ratio(1, matched_notes) * overlap_ratios.iter().sum::<f32>(),
+    }
+}
+
+/// Transcribe an audio file and score it against the notes of a MIDI file, which works without
+/// the user interface
+pub fn evaluate_files(
+    audio: &Path,
+    reference: &Path,
+    analysis_options: AnalysisOptions,
+    options: EvaluationOptions,
+) -> color_eyre::Result<Evaluation> {
+    let waveform = AudioDecoder::open(audio)
+        .wrap_err("unable to open the audio file")?
+        .decode(ChannelSelection::All, |_| {})
+        .wrap_err("unable to decode the audio file")?;
+    let reference = smf::import(reference)?;
+
+    let (notes, _, _) = analyze(&waveform, analysis_options, &|_| {});
+
+    Ok(Evaluation::new(&reference.notes, &notes, options))
+}
+
+/// Change the analysis options with command line flags, each followed by its value:
+/// `--fft-size <exponent>` for an FFT width of 2^exponent samples, `--threshold <dBFS>` and
+/// `--step-fraction <fraction>`
+pub fn parse_analysis_flags(
+    flags: &[String],
+    mut options: AnalysisOptions,
+) -> color_eyre::Result<AnalysisOptions> {
+    for pair in flags.chunks(2) {
+        let flag = pair[0].as_str();
+        let value = pair
+            .get(1)
+            .ok_or_else(|| eyre!("the flag {flag} is missing its value"))?;
+
+        match flag {
+            "--fft-size" => {
+                options.fft_size = value
+                    .parse()
+                    .wrap_err_with(|| format!("the FFT size {value} is not an exponent"))?;
+                ensure!(
+                    (1..=20).contains(&options.fft_size),
+                    "the FFT size must be an exponent between 1 and 20"
+                );
+            }
+            "--threshold" => {
+                options.threshold = value
+                    .parse()
+                    .wrap_err_with(|| format!("the threshold {value} is not a number"))?;
+                ensure!(
+                    (-100.0..=0.0).contains(&options.threshold),
+                    "the threshold must be between -100 and 0 dBFS"
+                );
+            }
+            "--step-fraction" => {
+                options.step_fraction = value
+                    .parse()
+                    .wrap_err_with(|| format!("the step fraction {value} is not a number"))?;
+                ensure!(
+                    options.step_fraction > 0.0 && options.step_fraction <= 1.0,
+                    "the step fraction must be above 0 and at most 1"
+                );
+            }
+            _ => bail!("unknown flag {flag}"),
+        }
+    }
+
+    Ok(options)
+}
+
+/// The largest set of pairs of references and estimates in which every reference and every
+/// estimate appears at most once, given the estimates that each reference may be paired with
+fn maximum_matching(candidates: &[Vec<usize>], estimates: usize) -> Vec<(usize, usize)> {
+    // Try to match every reference, moving the references matched before to other estimates
+    // if that makes room for it
+    fn augment(
+        reference: usize,
+        candidates: &[Vec<usize>],
+        matches: &mut [Option<usize>],
+        visited: &mut [bool],
+    ) -> bool {
+        for &estimate in &candidates[reference] {
+            if visited[estimate] {
+                continue;
+            }
+            visited[estimate] = true;
+
+            if matches[estimate].is_none_or(|other| augment(other, candidates, matches, visited)) {
+                matches[estimate] = Some(reference);
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    let mut matches = vec![None; estimates];
+    for reference in 0..candidates.len() {
+        augment(
+            reference,
+            candidates,
+            &mut matches,
+            &mut vec![false; estimates],
+        );
+    }
+
+    matches
+        .into_iter()
+        .enumerate()
+        .filter_map(|(estimate, reference)| Some((reference?, estimate)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use audio::waveform::Waveform;
+
+    use super::{evaluate, maximum_matching, parse_analysis_flags, Evaluation, EvaluationOptions};
+    use crate::{
+        analysis::{analyze, AnalysisOptions, KeyPress, KeyPresses},
+        key::PianoKey,
+    };
+
+    /// Notes of a single key given as their start and duration in milliseconds
+    fn key_presses(notes: &[(u64, u64)]) -> BTreeMap<PianoKey, KeyPresses> {
+        let mut key_presses = KeyPresses::new();
+        for &(start, duration) in notes {
+            key_presses.insert(KeyPress::new(start, Duration::from_millis(duration), 1.0));
+        }
+
+        BTreeMap::from([(PianoKey::new(49).unwrap(), key_presses)])
+    }
+
+    #[test]
+    fn matching() {
+        // Moving the first reference to its second candidate makes room for the second one
+        assert_eq!(
+            maximum_matching(&[vec![0, 1], vec![0]], 2),
+            [(1, 0), (0, 1)]
+        );
+        assert_eq!(maximum_matching(&[vec![0], vec![0]], 1), [(0, 0)]);
+        assert_eq!(maximum_matching(&[vec![], vec![]], 2), []);
+    }
+
+    #[test]
+    fn tolerances() {
+        let reference = key_presses(&[(1000, 500), (2000, 100), (3000, 1000)]);
+        let estimate = key_presses(&[
+            // Starts just within the onset tolerance, ends within a fifth of the duration
+            (1050, 500),
+            // Ends too late for the offset tolerance of at least 50ms
+            (1990, 200),
+            // Too late for the onset tolerance
+            (3051, 1000),
+            // Not in the reference at all
+            (5000, 100),
+        ]);
+
+        let evaluation = Evaluation::new(&reference, &estimate, EvaluationOptions::default());
+
+        assert_eq!(evaluation.onsets.matched_notes, 2);
+        assert_eq!(evaluation.onsets.precision, 0.5);
+        assert!((evaluation.onsets.recall - 2.0 / 3.0).abs() < 1e-6);
+        assert!((evaluation.onsets.f_measure - 4.0 / 7.0).abs() < 1e-6);
+
+        assert_eq!(evaluation.offsets.matched_notes, 1);
+        // 450ms shared out of 550ms
+        assert!((evaluation.offsets.average_overlap_ratio - 450.0 / 550.0).abs() < 1e-4);
+
+        // Other keys are never matched
+        let other_key = BTreeMap::from([(
+            PianoKey::new(50).unwrap(),
+            estimate[&PianoKey::new(49).unwrap()].clone(),
+        )]);
+        let scores = evaluate(&reference, &other_key, EvaluationOptions::default());
+        assert_eq!((scores.matched_notes, scores.f_measure), (0, 0.0));
+    }
+
+    /// Transcribes synthesized notes, so changes to the analysis that make it worse are noticed
+    #[test]
+    fn synthesized_notes() {
+        let sample_rate = 44_100;
+        // A4, C#5 and E5 one after the other, then all three together
+        let keys = [49, 53, 56].map(|key| PianoKey::new(key).unwrap());
+        let notes = [
+            (0.5, 0.5, &keys[..1]),
+            (1.25, 0.5, &keys[1..2]),
+            (2.0, 0.5, &keys[2..]),
+            (2.75, 1.0, &keys[..]),
+        ];
+
+        let mut samples = vec![0.0; 5 * sample_rate as usize];
+        let mut reference = BTreeMap::<PianoKey, KeyPresses>::new();
+        for &(start, duration, keys) in &notes {
+            for key in keys {
+                let tone = Waveform::sine_wave(key.concert_pitch(), duration, sample_rate);
+                let first = (start * sample_rate as f32) as usize;
+
+                for (sample, tone) in samples[first..].iter_mut().zip(tone.samples()) {
+                    *sample += 0.25 * tone;
+                }
+
+                reference.entry(*key).or_default().insert(KeyPress::new(
+                    (start * 1000.0) as u64,
+                    Duration::from_secs_f32(duration),
+                    1.0,
+                ));
+            }
+        }
+
+        let waveform = Waveform::new(samples, sample_rate);
+        // A finer step than the default, so the ends of the notes are found as well
+        let options = AnalysisOptions {
+            step_fraction: 0.25,
+            ..Default::default()
+        };
+        let (estimate, _, _) = analyze(&waveform, options, &|_| {});
+
+        let evaluation = Evaluation::new(&reference, &estimate, EvaluationOptions::default());
+        assert!(evaluation.onsets.f_measure >= 0.9, "{evaluation}");
+        assert!(evaluation.offsets.f_measure >= 0.9, "{evaluation}");
+    }
+
+    #[test]
+    fn analysis_flags() {
+        let flags = |flags: &[&str]| {
+            let flags = flags
+                .iter()
+                .map(|flag| flag.to_string())
+                .collect::<Vec<_>>();
+
+            parse_analysis_flags(&flags, AnalysisOptions::default())
+        };
+
+        let options = flags(&[
+            "--fft-size",
+            "12",
+            "--threshold",
+            "-30",
+            "--step-fraction",
+            "0.25",
+        ])
+        .unwrap();
+        assert_eq!(options.fft_width(), 4096);
+        assert_eq!(options.threshold, -30.0);
+        assert_eq!(options.step_fraction, 0.25);
+
+        assert_eq!(
+            flags(&[]).unwrap().fft_size,
+            AnalysisOptions::default().fft_size
+        );
+        assert!(flags(&["--fft-size"]).is_err());
+        assert!(flags(&["--fft-size", "16384"]).is_err());
+        assert!(flags(&["--step-fraction", "0"]).is_err());
+        assert!(flags(&["--window", "0.5"]).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use spectrum::{Chroma, PITCH_CLASSES};
+
+use crate::{
+    analysis::KeyStart,
+    key::{Accidental, PianoKey},
+};
+
+/// The Krumhansl-Kessler probe tone ratings of each pitch class in a major key, from the tonic
+const MAJOR_PROFILE: [f32; PITCH_CLASSES] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+/// The Krumhansl-Kessler probe tone ratings of each pitch class in a minor key, from the tonic
+const MINOR_PROFILE: [f32; PITCH_CLASSES] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// Frames that match no chord template better than this have no chord
+const MIN_CHORD_CORRELATION: f32 = 0.5;
+/// Chords are voted on by this many frames on either side, which removes short glitches
+const CHORD_SMOOTHING: usize = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 2] = [Self::Major, Self::Minor];
+
+    /// The semitones above the root of the third of a triad in this mode
+    fn third(&self) -> usize {
+        match self {
+            Mode::Major => 4,
+            Mode::Minor => 3,
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Major => write!(f, "major"),
+            Mode::Minor => write!(f, "minor"),
+        }
+    }
+}
+
+/// The key a song is in, such as E♭ major
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct MusicalKey {
+    /// The pitch class of the tonic in semitones from C
+    tonic: usize,
+    mode: Mode,
+}
+
+impl MusicalKey {
+    pub fn new(tonic: usize, mode: Mode) -> Self {
+        Self {
+            tonic: tonic % PITCH_CLASSES,
+            mode,
+        }
+    }
+
+    /// All 24 major and minor keys, ordered by their tonic
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..PITCH_CLASSES).flat_map(|tonic| Mode::ALL.map(|mode| Self::new(tonic, mode)))
+    }
+
+    /// Find the key whose Krumhansl-Kessler profile correlates best with the chroma of a whole
+    /// song, `None` if it is silent
+    pub fn estimate(chroma: &Chroma) -> Option<Self> {
+        if chroma.total() <= 0.0 {
+            return None;
+        }
+
+        Self::all().max_by(|a, b| {
+            chroma
+                .correlation(&a.profile())
+                .total_cmp(&chroma.correlation(&b.profile()))
+        })
+    }
+
+    pub fn tonic(&self) -> usize {
+        self.tonic
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The accidentals of the key signature, `None` for C major and A minor which have none
+    pub fn accidental(&self) -> Option<Accidental> {
+        // Minor keys share the signature of the major key a minor third above
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % PITCH_CLASSES,
+        };
+
+        // Walk the circle of fifths, where every fifth up adds a sharp and every fifth down a
+        // flat
+        match major_tonic * 7 % PITCH_CLASSES {
+            0 => None,
+            1..=6 => Some(Accidental::Sharp),
+            _ => Some(Accidental::Flat),
+        }
+    }
+
+    /// The profile rotated so that it starts at C
+    fn profile(&self) -> [f32; PITCH_CLASSES] {
+        let profile = match self.mode {
+            Mode::Major => MAJOR_PROFILE,
+            Mode::Minor => MINOR_PROFILE,
+        };
+
+        let mut rotated = [0.0; PITCH_CLASSES];
+        for (interval, rating) in profile.into_iter().enumerate() {
+            rotated[(self.tonic + interval) % PITCH_CLASSES] = rating;
+        }
+
+        rotated
+    }
+}
+
+impl Display for MusicalKey {
+    /// Spelled with the accidentals of its own key signature
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tonic = pitch_class_name(self.tonic, self.accidental().unwrap_or(Accidental::Sharp));
+
+        write!(f, "{tonic} {}", self.mode)
+    }
+}
+
+/// A major or minor triad
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Chord {
+    /// The pitch class of the root in semitones from C
+    root: usize,
+    mode: Mode,
+}
+
+impl Chord {
+    pub fn new(root: usize, mode: Mode) -> Self {
+        Self {
+            root: root % PITCH_CLASSES,
+            mode,
+        }
+    }
+
+    /// Find the triad that best matches the chroma of a frame, `None` if the frame is quieter
+    /// than `min_energy` or matches no triad well
+    pub fn recognize(chroma: &Chroma, min_energy: f32) -> Option<Self> {
+        if chroma.total() < min_energy {
+            return None;
+        }
+
+        (0..PITCH_CLASSES)
+            .flat_map(|root| Mode::ALL.map(|mode| Self::new(root, mode)))
+            .map(|chord| (chord, chroma.correlation(&chord.template())))
+            .filter(|&(_, correlation)| correlation >= MIN_CHORD_CORRELATION)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(chord, _)| chord)
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The chord symbol, such as `Bb` or `F#m`
+    pub fn name(&self, preference: Accidental) -> String {
+        let root = pitch_class_name(self.root, preference);
+
+        match self.mode {
+            Mode::Major => root,
+            Mode::Minor => format!("{root}m"),
+        }
+    }
+
+    /// The root, third and fifth of the triad
+    fn template(&self) -> [f32; PITCH_CLASSES] {
+        let mut template = [0.0; PITCH_CLASSES];
+        for interval in [0, self.mode.third(), 7] {
+            template[(self.root + interval) % PITCH_CLASSES] = 1.0;
+        }
+
+        template
+    }
+}
+
+/// A chord held over consecutive frames
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ChordSpan {
+    /// The start in milliseconds, like [`KeyPress::start`](crate::analysis::KeyPress::start)
+    pub start: KeyStart,
+    pub duration: Duration,
+    pub chord: Chord,
+}
+
+impl ChordSpan {
+    pub fn start_secs(&self) -> f32 {
+        self.start as f32 / 1000.0
+    }
+
+    pub fn duration_secs(&self) -> f32 {
+        self.duration.as_secs_f32()
+    }
+}
+
+/// The key and chords of an analysed song
+#[derive(Debug, Default, Clone)]
+pub struct Harmony {
+    pub key: Option<MusicalKey>,
+    pub chords: Vec<ChordSpan>,
+}
+
+impl Harmony {
+    /// Estimate the key from the chroma of every frame, and recognize the chords frame by frame
+    pub fn analyze(frames: &[Chroma], seconds_per_frame: f64, min_energy: f32) -> Self {
+        let key = MusicalKey::estimate(&frames.iter().copied().sum());
+
+        let chords = frames
+            .iter()
+            .map(|chroma| Chord::recognize(chroma, min_energy))
+            .collect::<Vec<_>>();
+        let chords = smooth(&chords);
+
+        let frame_start = |frame: usize| (frame as f64 * seconds_per_frame * 1000.0).round();
+
+        // Join the runs of frames with the same chord
+        let mut spans = Vec::new();
+        let mut start = 0;
+        while start < chords.len() {
+            let chord = chords[start];
+            let end = (start..chords.len())
+                .find(|&frame| chords[frame] != chord)
+                .unwrap_or(chords.len());
+
+            if let Some(chord) = chord {
+                spans.push(ChordSpan {
+                    start: frame_start(start) as KeyStart,
+                    duration: Duration::from_millis((frame_start(end) - frame_start(start)) as u64),
+                    chord,
+                });
+            }
+
+            start = end;
+        }
+
+        Self { key, chords: spans }
+    }
+}
+
+/// Replace the chord of every frame with the most common chord around it, keeping its own on
+/// ties
+fn smooth(chords: &[Option<Chord>]) -> Vec<Option<Chord>> {
+    (0..chords.len())
+        .map(|frame| {
+            let neighbourhood = &chords[frame.saturating_sub(CHORD_SMOOTHING)
+                ..(frame + CHORD_SMOOTHING + 1).min(chords.len())];
+
+            neighbourhood
+                .iter()
+                .copied()
+                .max_by_key(|&candidate| {
+                    let votes = neighbourhood
+                        .iter()
+                        .filter(|&&chord| chord == candidate)
+                        .count();
+
+                    (votes, candidate == chords[frame])
+                })
+                .unwrap_or(chords[frame])
+        })
+        .collect()
+}
+
+/// The name of a pitch class without an octave, such as `Eb`
+fn pitch_class_name(pitch_class: usize, preference: Accidental) -> String {
+    // Middle C is key 40, so the pitch classes of its octave are all on the piano
+    let note = PianoKey::new(40 + pitch_class as u8).map(|key| key.as_note(preference));
+
+    match note.as_ref().map(|note| (note.letter(), note.accidental())) {
+        Some((letter, Some(accidental))) => format!("{letter}{accidental}"),
+        Some((letter, None)) => letter.to_string(),
+        None => unreachable!("the octave of middle C is on the piano"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use spectrum::Chroma;
+
+    use super::{Chord, Harmony, Mode::*, MusicalKey};
+    use crate::key::Accidental::*;
+
+    fn chroma(pitch_classes: &[(usize, f32)]) -> Chroma {
+        let mut energy = [0.0; 12];
+        for &(pitch_class, value) in pitch_classes {
+            energy[pitch_class] = value;
+        }
+
+        Chroma::new(energy)
+    }
+
+    #[test]
+    fn key_signatures() {
+        assert_eq!(MusicalKey::new(0, Major).accidental(), None);
+        assert_eq!(MusicalKey::new(9, Minor).accidental(), None);
+        assert_eq!(MusicalKey::new(7, Major).accidental(), Some(Sharp));
+        assert_eq!(MusicalKey::new(4, Minor).accidental(), Some(Sharp));
+        assert_eq!(MusicalKey::new(5, Major).accidental(), Some(Flat));
+        assert_eq!(MusicalKey::new(2, Minor).accidental(), Some(Flat));
+
+        assert_eq!(MusicalKey::new(3, Major).to_string(), "Eb major");
+        assert_eq!(MusicalKey::new(6, Minor).to_string(), "F# minor");
+    }
+
+    #[test]
+    fn estimate_key() {
+        // The notes of a scale, weighted towards the tonic triad
+        let scale = |tonic: usize, third: usize, sixth: usize, seventh: usize| {
+            let notes = [
+                (0, 2.0),
+                (2, 1.0),
+                (third, 2.0),
+                (5, 1.0),
+                (7, 2.0),
+                (sixth, 1.0),
+                (seventh, 1.0),
+            ];
+
+            chroma(&notes.map(|(interval, weight)| ((tonic + interval) % 12, weight)))
+        };
+
+        for tonic in 0..12 {
+            let major = scale(tonic, 4, 9, 11);
+            let minor = scale(tonic, 3, 8, 10);
+
+            assert_eq!(
+                MusicalKey::estimate(&major),
+                Some(MusicalKey::new(tonic, Major))
+            );
+            assert_eq!(
+                MusicalKey::estimate(&minor),
+                Some(MusicalKey::new(tonic, Minor))
+            );
+        }
+
+        assert_eq!(MusicalKey::estimate(&Chroma::default()), None);
+    }
+
+    #[test]
+    fn recognize_chords() {
+        let c_major = chroma(&[(0, 1.0), (4, 0.8), (7, 0.9), (2, 0.1)]);
+        let a_minor = chroma(&[(9, 1.0), (0, 0.7), (4, 0.8)]);
+
+        assert_eq!(Chord::recognize(&c_major, 0.1), Some(Chord::new(0, Major)));
+        assert_eq!(Chord::recognize(&a_minor, 0.1), Some(Chord::new(9, Minor)));
+        assert_eq!(Chord::recognize(&c_major, 10.0), None);
+
+        assert_eq!(Chord::new(10, Major).name(Flat), "Bb");
+        assert_eq!(Chord::new(1, Minor).name(Sharp), "C#m");
+    }
+
+    #[test]
+    fn chord_spans() {
+        let c_major = chroma(&[(0, 1.0), (4, 1.0), (7, 1.0)]);
+        let g_major = chroma(&[(7, 1.0), (11, 1.0), (2, 1.0)]);
+        let f_major = chroma(&[(5, 1.0), (9, 1.0), (0, 1.0)]);
+
+        // A single frame of G among C is a glitch, five frames of F are a change of chord
+        let frames = [
+            [c_major; 4].as_slice(),
+            &[g_major],
+            &[c_major; 4],
+            &[f_major; 5],
+        ]
+        .concat();
+        let harmony = Harmony::analyze(&frames, 0.1, 0.1);
+
+        let spans = harmony
+            .chords
+            .iter()
+            .map(|span| (span.start, span.duration.as_millis(), span.chord))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            spans,
+            [
+                (0, 900, Chord::new(0, Major)),
+                (900, 500, Chord::new(5, Major))
+            ]
+        );
+        assert_eq!(harmony.key, Some(MusicalKey::new(0, Major)));
+    }
+}
@@ -170,7 +170,7 @@ impl PianoKey {
         let twelfth_root = 2.0f32.powf(1.0 / 12.0);
 
         // Raise to the power of keys away from A4
-        twelfth_root.powi(self.number() as i32 - 49)
+        440.0 * twelfth_root.powi(self.number() as i32 - 49)
     }
 
     pub fn number(&self) -> u8 {
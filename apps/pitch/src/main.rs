@@ -2,20 +2,32 @@
 #![forbid(unsafe_code)]
 // #![warn(clippy::unwrap_used, clippy::expect_used)]
 
+use std::path::Path;
+
+use audio::device::OUTPUT_SETTINGS_KEY;
 use color_eyre::eyre::Context;
 use eframe::{NativeOptions, APP_KEY};
 use ritelinked::LinkedHashSet;
 use tracing::info;
 use util::install_tracing;
 
-use crate::app::Application;
+use crate::{
+    analysis::AnalysisOptions,
+    app::Application,
+    evaluation::{evaluate_files, parse_analysis_flags, EvaluationOptions},
+};
 
 mod analysis;
 mod app;
 mod decode;
+mod evaluation;
+mod harmony;
 mod key;
 mod midi;
 mod piano_roll;
+mod settings;
+mod smf;
+mod synth;
 mod ui_error;
 
 pub const NAME: &str = "Pitch";
@@ -25,19 +37,40 @@ pub fn main() -> color_eyre::Result<()> {
 
     install_tracing().wrap_err("failed to install tracing_subscriber")?;
 
+    // Score the transcription of a file without opening a window:
+    // `pitch --evaluate <audio file> <reference MIDI file> [--fft-size <exponent>]
+    //     [--threshold <dBFS>] [--step-fraction <fraction>]`
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let [flag, audio, reference, flags @ ..] = args.as_slice() {
+        if flag == "--evaluate" {
+            let evaluation = evaluate_files(
+                Path::new(audio),
+                Path::new(reference),
+                parse_analysis_flags(flags, AnalysisOptions::default())?,
+                EvaluationOptions::default(),
+            )?;
+            println!("{evaluation}");
+
+            return Ok(());
+        }
+    }
+
     info!("Starting Application");
 
     eframe::run_native(
         NAME,
         NativeOptions::default(),
         Box::new(|cc| {
-            let recently_opened_files = if let Some(storage) = cc.storage {
-                eframe::get_value(storage, APP_KEY).unwrap_or_default()
+            let (recently_opened_files, output_settings) = if let Some(storage) = cc.storage {
+                (
+                    eframe::get_value(storage, APP_KEY).unwrap_or_default(),
+                    eframe::get_value(storage, OUTPUT_SETTINGS_KEY).unwrap_or_default(),
+                )
             } else {
-                LinkedHashSet::new()
+                (LinkedHashSet::new(), Default::default())
             };
 
-            Box::new(Application::new(recently_opened_files))
+            Box::new(Application::new(recently_opened_files, output_settings))
         }),
     )
 }
@@ -1,5 +1,8 @@
 use std::{
     collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt::{self, Display},
+    io, mem,
     sync::{
         atomic::{AtomicBool, AtomicUsize},
         Arc, Weak,
@@ -11,51 +14,132 @@ use std::{
 use async_executor::Executor;
 use async_io::Timer;
 use atomic::{Atomic, Ordering};
-use eframe::egui::Context;
+use audio::device::DeviceSettings;
+use eframe::{
+    egui::{Context, RichText, Ui},
+    epaint::Color32,
+};
 use flume::{Receiver, RecvError, Sender};
 use futures_lite::future;
-use midir::{MidiOutput, MidiOutputConnection};
-use tracing::{debug, info};
+use midir::{
+    ConnectErrorKind, InitError, MidiOutput, MidiOutputConnection, PortInfoError, SendError,
+};
+use parking_lot::Mutex;
+use tracing::{debug, info, warn};
 
 use crate::{
     analysis::{KeyPress, KeyPresses},
     key::PianoKey,
+    synth::{SynthOptions, SynthOutput},
+    ui_error::UiError,
 };
 
 pub struct MidiPlayer {
     sender: Sender<MidiThreadCommand>,
     executor: Arc<Executor<'static>>,
+
+    /// Lists the output ports, as the output of the midi thread is taken by its connection
+    scanner: MidiOutput,
+    ports: Vec<String>,
+    /// The port chosen to play on, which is reconnected to when it is plugged in again
+    port: Option<String>,
+    /// The port the midi thread is connected to
+    connected: Arc<Mutex<Option<String>>>,
+    errors: Receiver<MidiError>,
+    /// Reports the errors of playing songs and of the synth, along with the errors of the midi
+    /// thread
+    error_sender: Sender<MidiError>,
+
+    /// Plays the notes while no port is connected, `None` if there is no audio output
+    synth: Option<SynthOutput>,
 }
 
 pub enum MidiConnection {
-    Disconnected { output: MidiOutput },
-    Connected { connection: MidiOutputConnection },
+    Disconnected {
+        output: MidiOutput,
+    },
+    Connected {
+        connection: MidiOutputConnection,
+        port: String,
+    },
 }
 
-impl MidiPlayer {
-    const CONN_NAME: &'static str = "piano-roll";
+#[derive(Debug)]
+pub enum MidiError {
+    /// The midi system could not be initialized
+    Init(InitError),
+    /// The name of an output port could not be read
+    PortInfo(PortInfoError),
+    /// There is no output port with the name
+    UnknownPort(String),
+    /// The output port with the name could not be connected to
+    Connect(String, ConnectErrorKind),
+    /// A message could not be sent to the output port with the name, which was disconnected
+    Send(String, SendError),
+    /// The thread that plays the notes could not be started
+    SpawnThread(io::Error),
+    /// The thread that plays the notes has stopped
+    ThreadStopped,
+    /// The built-in synth could not open an output device
+    Synth(color_eyre::Report),
+}
 
-    pub fn new(name: &str) -> Self {
-        let midi_output = MidiOutput::new(name).expect("unable to enumerate midi devices");
+impl Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiError::Init(error) => write!(f, "{error}"),
+            MidiError::PortInfo(error) => write!(f, "unable to read the output ports: {error}"),
+            MidiError::UnknownPort(port) => write!(f, "there is no output port named {port}"),
+            MidiError::Connect(port, error) => write!(f, "unable to connect to {port}: {error}"),
+            MidiError::Send(port, error) => write!(f, "unable to send to {port}: {error}"),
+            MidiError::SpawnThread(error) => write!(f, "unable to start the midi thread: {error}"),
+            MidiError::ThreadStopped => write!(f, "the midi thread has stopped"),
+            MidiError::Synth(error) => write!(f, "the synth has no audio output: {error}"),
+        }
+    }
+}
 
-        // TODO: expose and implement selection
-        let connection = match midi_output.ports().as_slice() {
-            // Connect if there is only one port available
-            [port] => {
-                let port_name = midi_output.port_name(port).unwrap();
+impl Error for MidiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MidiError::Init(error) => Some(error),
+            MidiError::PortInfo(error) => Some(error),
+            MidiError::Send(_, error) => Some(error),
+            MidiError::SpawnThread(error) => Some(error),
+            MidiError::UnknownPort(_)
+            | MidiError::Connect(_, _)
+            | MidiError::ThreadStopped
+            | MidiError::Synth(_) => None,
+        }
+    }
+}
 
-                debug!(%port_name, "Connecting to the only available output port");
+impl UiError for MidiError {
+    fn ui_error(&self, ui: &mut Ui) {
+        ui.label(RichText::new("MIDI Error").heading().color(Color32::RED));
+        ui.label(self.to_string());
+    }
+}
 
-                MidiConnection::Connected {
-                    connection: midi_output.connect(port, Self::CONN_NAME).unwrap(),
-                }
-            }
-            _ => MidiConnection::Disconnected {
-                output: midi_output,
-            },
-        };
+impl MidiPlayer {
+    const CONN_NAME: &'static str = "piano-roll";
+
+    /// Open the midi output, with the synth playing on the audio output with the settings
+    pub fn new(name: &str, output_settings: &DeviceSettings) -> Result<Self, MidiError> {
+        let scanner = MidiOutput::new(name).map_err(MidiError::Init)?;
+        let midi_output = MidiOutput::new(name).map_err(MidiError::Init)?;
 
         let (sender, recv) = flume::unbounded();
+        let (error_sender, errors) = flume::unbounded();
+
+        let synth = SynthOutput::new(
+            output_settings,
+            SynthOptions::default(),
+            error_sender.clone(),
+        )
+        .map_err(|error| warn!(%error, "Unable to start the synth"))
+        .ok();
+        let connected = Arc::new(Mutex::new(None));
 
         let executor = Arc::new(Executor::new());
 
@@ -68,26 +152,164 @@ impl MidiPlayer {
                     future::block_on(executor.run(future::pending::<()>()));
                 }
             })
-            .unwrap();
+            .map_err(MidiError::SpawnThread)?;
+
+        executor
+            .spawn(midi_thread(
+                MidiConnection::Disconnected {
+                    output: midi_output,
+                },
+                synth.clone(),
+                recv,
+                error_sender.clone(),
+                connected.clone(),
+            ))
+            .detach();
+
+        let mut player = Self {
+            sender,
+            executor,
+            scanner,
+            ports: Vec::new(),
+            port: None,
+            connected,
+            errors,
+            error_sender,
+            synth,
+        };
+        player.rescan()?;
+
+        // Connect if there is only one port available
+        if let [port] = player.ports.as_slice() {
+            let port = port.clone();
+
+            debug!(%port, "Connecting to the only available output port");
+
+            if let Err(error) = player.connect(&port) {
+                warn!(%error, "Unable to connect to the only available output port");
+            }
+        }
+
+        Ok(player)
+    }
+
+    /// The names of the output ports found by the last scan
+    pub fn ports(&self) -> &[String] {
+        &self.ports
+    }
+
+    /// The output port that notes are played on, if any
+    pub fn connected_port(&self) -> Option<String> {
+        self.connected.lock().clone()
+    }
+
+    /// Look for output ports that were plugged in or out, reconnecting to the chosen port when
+    /// it appears again
+    pub fn rescan(&mut self) -> Result<(), MidiError> {
+        self.ports = self
+            .scanner
+            .ports()
+            .iter()
+            .map(|port| self.scanner.port_name(port))
+            .collect::<Result<_, _>>()
+            .map_err(MidiError::PortInfo)?;
+
+        let port = match &self.port {
+            Some(port) => port.clone(),
+            None => return Ok(()),
+        };
+        let available = self.ports.contains(&port);
+
+        match self.connected_port() {
+            None if available => {
+                info!(%port, "Reconnecting to the output port");
+
+                // A port may show up before it can be opened, so keep trying on the next scans
+                // instead of reporting every attempt
+                if let Err(error) = self.connect_thread(&port) {
+                    warn!(%error, "Unable to reconnect to the output port yet");
+                }
+
+                Ok(())
+            }
+            Some(_) if !available => {
+                info!(%port, "The output port was unplugged");
+
+                // Keep the port, to connect once it is plugged in again
+                self.send(MidiThreadCommand::Disconnect)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Play on the output port with the name, found by the last scan
+    pub fn connect(&mut self, port: &str) -> Result<(), MidiError> {
+        let result = self.connect_thread(port);
+
+        // Only ports that could be connected to are reconnected to later
+        if result.is_ok() {
+            self.port = Some(port.to_string());
+        }
+
+        result
+    }
+
+    /// Connect the midi thread to the port, waiting until it has tried
+    fn connect_thread(&self, port: &str) -> Result<(), MidiError> {
+        let (reply, result) = flume::bounded(1);
+        self.send(MidiThreadCommand::Connect(port.to_string(), reply))?;
+
+        result.recv().map_err(|_| MidiError::ThreadStopped)?
+    }
 
-        executor.spawn(midi_thread(connection, recv)).detach();
+    /// Stop playing on the connected output port
+    pub fn disconnect(&mut self) -> Result<(), MidiError> {
+        self.port = None;
 
-        Self { sender, executor }
+        self.send(MidiThreadCommand::Disconnect)
     }
 
-    pub fn play_piano(&self, key: PianoKey, duration: Duration) {
+    /// If the notes are played by the built-in synth while no port is connected
+    pub fn has_synth(&self) -> bool {
+        self.synth.as_ref().is_some_and(SynthOutput::is_available)
+    }
+
+    /// Play the synth on another audio output
+    pub fn reopen_synth(&self, output_settings: &DeviceSettings) {
+        if let Some(synth) = &self.synth {
+            synth.reopen(output_settings);
+        }
+    }
+
+    /// An error the midi thread or the synth ran into while playing notes
+    pub fn take_error(&self) -> Option<MidiError> {
+        self.errors.try_recv().ok()
+    }
+
+    fn send(&self, command: MidiThreadCommand) -> Result<(), MidiError> {
         self.sender
-            .send(MidiThreadCommand::PlayNote(
-                MidiNote::from_piano_key(key),
-                duration,
-            ))
-            .unwrap();
+            .send(command)
+            .map_err(|_| MidiError::ThreadStopped)
+    }
+
+    pub fn play_piano(
+        &self,
+        key: PianoKey,
+        duration: Duration,
+        velocity: u8,
+    ) -> Result<(), MidiError> {
+        self.send(MidiThreadCommand::PlayNote(
+            MidiNote::from_piano_key(key),
+            duration,
+            velocity,
+        ))
     }
 
     #[must_use]
     pub fn play_song(&self, notes: &BTreeMap<PianoKey, KeyPresses>, ctx: Context) -> SongProgress {
         let song_start = Instant::now();
         let sender = self.sender.clone();
+        let errors = self.error_sender.clone();
 
         let mut deadlines = BTreeMap::<Instant, Vec<(PianoKey, KeyPress)>>::new();
         for (key, key_presses) in notes {
@@ -115,13 +337,21 @@ impl MidiPlayer {
                         break;
                     }
 
-                    for (key, key_press) in keys {
+                    let sent = keys.iter().try_for_each(|(key, key_press)| {
                         sender
                             .send(MidiThreadCommand::PlayNote(
                                 MidiNote::from_piano_key(*key),
                                 key_press.duration(),
+                                key_press.velocity(),
                             ))
-                            .unwrap();
+                            .map_err(|_| MidiError::ThreadStopped)
+                    });
+
+                    // Nobody is left to play the rest of the song
+                    if let Err(error) = sent {
+                        let _ = errors.send(error);
+
+                        break;
                     }
 
                     progress.notes.fetch_add(keys.len(), Ordering::SeqCst);
@@ -163,7 +393,85 @@ impl SongProgressInner {
     }
 }
 
-async fn midi_thread(mut connection: MidiConnection, thread_commands: Receiver<MidiThreadCommand>) {
+impl MidiConnection {
+    /// Connect to the output port with the name, closing any previous connection
+    fn connect(self, port: &str) -> (Self, Result<(), MidiError>) {
+        let output = self.close();
+
+        let found = output
+            .ports()
+            .into_iter()
+            .find(|candidate| output.port_name(candidate).is_ok_and(|name| name == port));
+        let found = match found {
+            Some(found) => found,
+            None => {
+                return (
+                    MidiConnection::Disconnected { output },
+                    Err(MidiError::UnknownPort(port.to_string())),
+                )
+            }
+        };
+
+        match output.connect(&found, MidiPlayer::CONN_NAME) {
+            Ok(connection) => (
+                MidiConnection::Connected {
+                    connection,
+                    port: port.to_string(),
+                },
+                Ok(()),
+            ),
+            Err(error) => {
+                let kind = error.kind();
+
+                (
+                    MidiConnection::Disconnected {
+                        output: error.into_inner(),
+                    },
+                    Err(MidiError::Connect(port.to_string(), kind)),
+                )
+            }
+        }
+    }
+
+    fn close(self) -> MidiOutput {
+        match self {
+            MidiConnection::Disconnected { output } => output,
+            MidiConnection::Connected { connection, .. } => connection.close(),
+        }
+    }
+
+    fn port(&self) -> Option<&str> {
+        match self {
+            MidiConnection::Disconnected { .. } => None,
+            MidiConnection::Connected { port, .. } => Some(port),
+        }
+    }
+
+    /// Send the command, which is played by the synth while disconnected
+    fn send(&mut self, command: MidiCommand, synth: Option<&SynthOutput>) -> Result<(), MidiError> {
+        match self {
+            MidiConnection::Disconnected { .. } => {
+                match synth {
+                    Some(synth) => synth.play(command),
+                    None => info!(?command, "Midi disconnected.. ignoring command"),
+                }
+
+                Ok(())
+            }
+            MidiConnection::Connected { connection, port } => connection
+                .send(command.to_bytes().as_slice())
+                .map_err(|error| MidiError::Send(port.clone(), error)),
+        }
+    }
+}
+
+async fn midi_thread(
+    mut connection: MidiConnection,
+    synth: Option<SynthOutput>,
+    thread_commands: Receiver<MidiThreadCommand>,
+    errors: Sender<MidiError>,
+    connected: Arc<Mutex<Option<String>>>,
+) {
     use futures_lite::prelude::*;
 
     #[derive(Debug)]
@@ -197,54 +505,102 @@ async fn midi_thread(mut connection: MidiConnection, thread_commands: Receiver<M
         };
 
         // Poll both futures
-        match future::or(commands_fut, deadline_timer).await {
+        let sent = match future::or(commands_fut, deadline_timer).await {
             MidiAction::ChannelClosed => return,
-            MidiAction::NewCommand(MidiThreadCommand::PlayNote(note, duration)) => {
-                match &mut connection {
-                    MidiConnection::Disconnected { .. } => {
-                        info!(?note, ?duration, "Midi disconnected.. ignoring note");
-                    }
-                    MidiConnection::Connected { connection } => {
-                        connection
-                            .send(MidiCommand::NoteOn(note, 0b01111111).to_bytes().as_slice())
-                            .unwrap();
-
-                        let deadline = Instant::now() + duration;
-
-                        // Add the key to the deadlines
-                        note_off_deadlines.entry(deadline).or_default().insert(note);
-
-                        // Remove any previous deadlines
-                        if let Some((&instant, _)) = note_off_deadlines
-                            .range(..deadline)
-                            .find(|(_, notes)| notes.contains(&note))
-                        {
-                            note_off_deadlines.entry(instant).or_default().remove(&note);
-                        }
-                    }
+            MidiAction::NewCommand(MidiThreadCommand::PlayNote(note, duration, velocity)) => {
+                debug!(?note, ?duration, velocity, "Playing note");
+
+                let sent = connection.send(MidiCommand::NoteOn(note, velocity), synth.as_ref());
+
+                let deadline = Instant::now() + duration;
+
+                // Add the key to the deadlines
+                note_off_deadlines.entry(deadline).or_default().insert(note);
+
+                // Remove any previous deadlines
+                if let Some((&instant, _)) = note_off_deadlines
+                    .range(..deadline)
+                    .find(|(_, notes)| notes.contains(&note))
+                {
+                    note_off_deadlines.entry(instant).or_default().remove(&note);
                 }
+
+                sent
             }
-            MidiAction::NoteOffWake(deadline, notes) => match &mut connection {
-                MidiConnection::Disconnected { .. } => {
-                    info!(?notes, "Midi disconnected.. ignoring note off");
+            MidiAction::NewCommand(MidiThreadCommand::Connect(port, reply)) => {
+                // Release the sounding notes, so they do not hang on the previous port, which
+                // may already be gone and is left anyway
+                if let Err(error) =
+                    silence(&mut connection, synth.as_ref(), &mut note_off_deadlines)
+                {
+                    warn!(%error, "Unable to release the notes on the previous output port");
                 }
-                MidiConnection::Connected { connection } => {
-                    note_off_deadlines.remove(&deadline);
 
-                    for note in notes {
-                        connection
-                            .send(MidiCommand::NoteOff(note, 0b01111111).to_bytes().as_slice())
-                            .unwrap();
-                    }
+                let (new_connection, result) = connection.connect(&port);
+                connection = new_connection;
+                *connected.lock() = connection.port().map(str::to_string);
+
+                if let Err(error) = &result {
+                    warn!(%error, "Unable to connect to the midi output port");
                 }
-            },
+                // The player may have stopped waiting for the reply
+                let _ = reply.send(result);
+
+                // The player is told about connection errors through the reply
+                Ok(())
+            }
+            MidiAction::NewCommand(MidiThreadCommand::Disconnect) => {
+                let silenced = silence(&mut connection, synth.as_ref(), &mut note_off_deadlines);
+
+                connection = MidiConnection::Disconnected {
+                    output: connection.close(),
+                };
+                *connected.lock() = None;
+
+                silenced
+            }
+            MidiAction::NoteOffWake(deadline, notes) => {
+                note_off_deadlines.remove(&deadline);
+
+                notes.into_iter().try_for_each(|note| {
+                    connection.send(MidiCommand::NoteOff(note, 0b01111111), synth.as_ref())
+                })
+            }
+        };
+
+        // The port is most likely gone, so stop playing on it until it is connected again
+        if let Err(error) = sent {
+            warn!(%error, "Disconnecting from the midi output port");
+
+            connection = MidiConnection::Disconnected {
+                output: connection.close(),
+            };
+            *connected.lock() = None;
+
+            // Nobody listens for errors once the player is dropped
+            let _ = errors.send(error);
         }
     }
 }
 
+/// Release every note that is still sounding
+fn silence(
+    connection: &mut MidiConnection,
+    synth: Option<&SynthOutput>,
+    note_off_deadlines: &mut BTreeMap<Instant, HashSet<MidiNote>>,
+) -> Result<(), MidiError> {
+    mem::take(note_off_deadlines)
+        .into_values()
+        .flatten()
+        .try_for_each(|note| connection.send(MidiCommand::NoteOff(note, 0b01111111), synth))
+}
+
 #[derive(Debug)]
 pub enum MidiThreadCommand {
-    PlayNote(MidiNote, Duration),
+    PlayNote(MidiNote, Duration, u8), // 7 bit velocity
+    /// Connect to the output port with the name, replying whether that worked
+    Connect(String, Sender<Result<(), MidiError>>),
+    Disconnect,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -285,6 +641,11 @@ impl MidiNote {
         Self::new(key.number() + 20)
     }
 
+    /// The piano key of the note, `None` for notes below A0 or above C8
+    pub fn as_piano_key(&self) -> Option<PianoKey> {
+        self.0.checked_sub(20).and_then(PianoKey::new)
+    }
+
     pub const fn as_u8(&self) -> u8 {
         self.0
     }
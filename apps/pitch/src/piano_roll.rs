@@ -11,11 +11,15 @@ use eframe::{
 
 use crate::{
     analysis::KeyPresses,
+    harmony::ChordSpan,
     key::{Accidental, MusicalNote, PianoKey},
     midi::MidiPlayer,
 };
 
-pub struct PianoRoll<'player, 'keys, 'spectrum> {
+/// The height of the lane above the keys that shows the chords
+const CHORD_LANE_HEIGHT: f32 = 20.0;
+
+pub struct PianoRoll<'player, 'keys, 'spectrum, 'chords> {
     // TODO: scales?
     preference: Accidental,
 
@@ -24,36 +28,43 @@ pub struct PianoRoll<'player, 'keys, 'spectrum> {
 
     cursor: Option<f32>,
 
-    midi: &'player MidiPlayer,
+    midi: Option<&'player MidiPlayer>,
 
     keys: &'keys BTreeMap<PianoKey, KeyPresses>,
+    /// Notes to compare the keys to, drawn behind them
+    reference: Option<&'keys BTreeMap<PianoKey, KeyPresses>>,
     spectrum: Option<&'spectrum TextureHandle>,
+    chords: &'chords [ChordSpan],
 }
 
-impl<'player, 'keys, 'spectrum> PianoRoll<'player, 'keys, 'spectrum> {
+impl<'player, 'keys, 'spectrum, 'chords> PianoRoll<'player, 'keys, 'spectrum, 'chords> {
     // TODO: builder
     pub fn new(
-        midi: &'player MidiPlayer,
+        midi: Option<&'player MidiPlayer>,
         preference: Accidental,
         cursor: Option<f32>,
         key_height: f32,
         seconds_per_width: f32,
         keys: &'keys BTreeMap<PianoKey, KeyPresses>,
+        reference: Option<&'keys BTreeMap<PianoKey, KeyPresses>>,
         spectrum: Option<&'spectrum TextureHandle>,
+        chords: &'chords [ChordSpan],
     ) -> Self {
         Self {
             key_height,
             keys,
+            reference,
             midi,
             preference,
             seconds_per_width,
             cursor,
             spectrum,
+            chords,
         }
     }
 }
 
-impl PianoRoll<'_, '_, '_> {
+impl PianoRoll<'_, '_, '_, '_> {
     fn layout_key(fonts: &Fonts, note: &MusicalNote, height: f32) -> Arc<Galley> {
         let mut job = LayoutJob::default();
 
@@ -218,10 +229,15 @@ impl PianoRoll<'_, '_, '_> {
                             keypress.duration_secs()
                         ));
                         ui.label(format!("Intensity: {}", keypress.intensity()));
+                        ui.label(format!("Velocity: {}", keypress.velocity()));
                     });
 
-                if response.clicked() {
-                    self.midi.play_piano(key, keypress.duration())
+                if let Some(midi) = self.midi.filter(|_| response.clicked()) {
+                    if let Err(error) =
+                        midi.play_piano(key, keypress.duration(), keypress.velocity())
+                    {
+                        tracing::warn!(%error, "unable to play the note");
+                    }
                 }
 
                 [
@@ -242,6 +258,62 @@ impl PianoRoll<'_, '_, '_> {
         })
     }
 
+    fn draw_reference<'s>(
+        &'s self,
+        ui: &'s Ui,
+        drawing_window: Rect,
+        margin: Vec2,
+    ) -> impl Iterator<Item = Shape> + 's {
+        self.reference
+            .into_iter()
+            .flatten()
+            .flat_map(move |(&key, key_presses)| {
+                let y = (PianoKey::all().len() as u8 - key.number()) as f32 * self.key_height;
+
+                key_presses.iter().map(move |keypress| {
+                    let rect = Rect::from_min_size(
+                        Pos2::new(keypress.start_secs() * self.seconds_per_width, y),
+                        Vec2::new(
+                            keypress.duration_secs() * self.seconds_per_width,
+                            self.key_height,
+                        ),
+                    )
+                    .translate(drawing_window.min.to_vec2() + margin);
+
+                    let response = ui
+                        .interact(
+                            rect,
+                            Id::new(("reference", key, keypress.start())),
+                            Sense::hover(),
+                        )
+                        .on_hover_ui_at_pointer(|ui| {
+                            let note = key.as_note(Accidental::Sharp);
+
+                            let galley = Self::layout_key(&ui.fonts(), &note, 20.0);
+                            ui.label(galley);
+
+                            ui.label(format!(
+                                "Reference: {:.2}s-{:.2}s ({:.2}s)",
+                                keypress.start_secs(),
+                                keypress.end_secs(),
+                                keypress.duration_secs()
+                            ));
+                            ui.label(format!("Velocity: {}", keypress.velocity()));
+                        });
+
+                    Shape::rect_filled(
+                        rect,
+                        Rounding::same(2.0),
+                        if response.hovered() {
+                            Color32::LIGHT_BLUE.linear_multiply(0.6)
+                        } else {
+                            Color32::LIGHT_BLUE.linear_multiply(0.3)
+                        },
+                    )
+                })
+            })
+    }
+
     fn draw_time_ui<'s>(
         &'s self,
         ui: &'s Ui,
@@ -272,6 +344,38 @@ impl PianoRoll<'_, '_, '_> {
         })
     }
 
+    fn draw_chords<'s>(
+        &'s self,
+        ui: &'s Ui,
+        drawing_window: Rect,
+        margin: Vec2,
+    ) -> impl Iterator<Item = Shape> + 's {
+        self.chords.iter().flat_map(move |span| {
+            // The lane sits above the time labels, at the top of the margin
+            let rect = Rect::from_min_size(
+                Pos2::new(span.start_secs() * self.seconds_per_width, 0.0),
+                Vec2::new(
+                    span.duration_secs() * self.seconds_per_width,
+                    CHORD_LANE_HEIGHT,
+                ),
+            )
+            .translate(drawing_window.min.to_vec2() + Vec2::new(margin.x, 0.0))
+            .shrink2(Vec2::new(1.0, CHORD_LANE_HEIGHT * 0.05));
+
+            [
+                Shape::rect_filled(rect, Rounding::same(2.0), Color32::DARK_BLUE),
+                Shape::text(
+                    &ui.fonts(),
+                    rect.left_center() + Vec2::new(2.0, 0.0),
+                    Align2::LEFT_CENTER,
+                    span.chord.name(self.preference),
+                    FontId::monospace(CHORD_LANE_HEIGHT * 0.6),
+                    Color32::WHITE,
+                ),
+            ]
+        })
+    }
+
     fn draw_cursor(&self, drawing_window: Rect, margin: Vec2, size: Vec2) -> Option<Shape> {
         self.cursor.map(|time| {
             // TODO: extract x and y coords into own function to reduce boilerplate
@@ -287,9 +391,9 @@ impl PianoRoll<'_, '_, '_> {
     }
 }
 
-impl PianoRoll<'_, '_, '_> {}
+impl PianoRoll<'_, '_, '_, '_> {}
 
-impl Widget for PianoRoll<'_, '_, '_> {
+impl Widget for PianoRoll<'_, '_, '_, '_> {
     fn ui(self, ui: &mut Ui) -> Response {
         Frame::canvas(ui.style())
             .show(ui, |ui| {
@@ -332,11 +436,19 @@ impl Widget for PianoRoll<'_, '_, '_> {
                         ));
 
                         // TODO: padding around text relative to text size (em)
-                        let margin = Vec2::new(left_margin + 5.0, time_text_size);
+                        let chord_lane_height = if self.chords.is_empty() {
+                            0.0
+                        } else {
+                            CHORD_LANE_HEIGHT
+                        };
+                        let margin =
+                            Vec2::new(left_margin + 5.0, chord_lane_height + time_text_size);
 
                         shapes.extend(self.draw_key_lines_ui(drawing_window, margin, size));
                         shapes.extend(self.draw_time_ui(ui, drawing_window, margin, size));
+                        shapes.extend(self.draw_chords(ui, drawing_window, margin));
 
+                        shapes.extend(self.draw_reference(ui, drawing_window, margin));
                         shapes.extend(self.draw_notes(ui, drawing_window, margin));
                         shapes.extend(self.draw_cursor(drawing_window, margin, size));
                         if let Some(spectrum) = self.spectrum {
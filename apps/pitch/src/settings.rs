@@ -0,0 +1,43 @@
+use eframe::egui::Ui;
+
+use crate::midi::{MidiError, MidiPlayer};
+
+/// Show the midi output ports to choose from
+pub fn midi_output_ui(ui: &mut Ui, midi: &mut MidiPlayer) -> Result<(), MidiError> {
+    let connected = midi.connected_port();
+
+    // Without a port the notes are played by the synth, if there is an audio output for it
+    let disconnected = if midi.has_synth() {
+        "Built-in Synth"
+    } else {
+        "Disconnected"
+    };
+    if ui
+        .selectable_label(connected.is_none(), disconnected)
+        .clicked()
+    {
+        midi.disconnect()?;
+    }
+
+    ui.separator();
+
+    if midi.ports().is_empty() {
+        ui.label("No output ports found");
+    }
+
+    let mut selected = None;
+    for port in midi.ports() {
+        if ui
+            .selectable_label(connected.as_ref() == Some(port), port)
+            .clicked()
+        {
+            selected = Some(port.clone());
+        }
+    }
+
+    // Connect once the ports are no longer borrowed
+    match selected {
+        Some(port) => midi.connect(&port),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,777 @@
+//! Standard MIDI Files, to open the transcribed notes in other music software
+//!
+//! A file starts with a header chunk and holds one or more track chunks of events, each one
+//! after a delta time in ticks since the event before it. The ticks are fractions of a quarter
+//! note, so their length in seconds depends on the tempo, which is set by a meta event. Format 0
+//! files have a single track holding everything, format 1 files have a first track with the
+//! tempo followed by the tracks of notes.
+//!
+//! Files from other programs may change the tempo along the way and leave out the status byte
+//! of events that repeat the status of the event before them, which is called running status.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::{self, Display},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+
+use crate::{
+    analysis::{KeyPress, KeyPresses},
+    key::PianoKey,
+    midi::{MidiCommand, MidiNote},
+};
+
+/// The velocity of every note off event, as the analysis can not tell how keys are released
+const RELEASE_VELOCITY: u8 = 64;
+
+const META: u8 = 0xFF;
+const META_TRACK_NAME: u8 = 0x03;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+const SYSEX: u8 = 0xF0;
+const SYSEX_ESCAPE: u8 = 0xF7;
+
+/// The length of a quarter note until the first tempo event, 120 quarter notes per minute
+const DEFAULT_MICROS_PER_QUARTER: u32 = 500_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmfFormat {
+    /// Format 0, the tempo and the notes in a single track
+    SingleTrack,
+    /// Format 1, a track with the tempo followed by a track with the notes
+    MultiTrack,
+}
+
+impl Display for SmfFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SingleTrack => write!(f, "Single Track"),
+            Self::MultiTrack => write!(f, "Multi Track"),
+        }
+    }
+}
+
+impl SmfFormat {
+    pub const ALL: [SmfFormat; 2] = [Self::SingleTrack, Self::MultiTrack];
+
+    pub fn number(&self) -> u16 {
+        match self {
+            Self::SingleTrack => 0,
+            Self::MultiTrack => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmfOptions {
+    pub format: SmfFormat,
+    /// The tempo in quarter notes per minute
+    pub tempo: f32,
+    /// The amount of ticks in a quarter note
+    pub ppq: u16,
+}
+
+impl Default for SmfOptions {
+    fn default() -> Self {
+        Self {
+            format: SmfFormat::MultiTrack,
+            tempo: 120.0,
+            ppq: 480,
+        }
+    }
+}
+
+impl SmfOptions {
+    /// The length of a quarter note in microseconds, as stored in the tempo meta event
+    pub fn micros_per_quarter(&self) -> u32 {
+        (60_000_000.0 / self.tempo).round() as u32
+    }
+
+    /// The amount of ticks in the given amount of milliseconds
+    pub fn ticks(&self, millis: u128) -> u32 {
+        (millis as f64 * self.ppq as f64 * 1000.0 / self.micros_per_quarter() as f64).round() as u32
+    }
+}
+
+/// Encode the notes as a standard MIDI file on the first channel
+pub fn write(notes: &BTreeMap<PianoKey, KeyPresses>, options: SmfOptions) -> Vec<u8> {
+    assert!(
+        (1..=0x7FFF).contains(&options.ppq),
+        "the ticks per quarter note must be between 1 and 32767"
+    );
+    assert!(
+        options.tempo.is_finite() && (1..=0xFF_FFFF).contains(&options.micros_per_quarter()),
+        "the tempo must be above 3.6 quarter notes per minute"
+    );
+
+    let mut tempo = Track::default();
+    tempo.meta(
+        0,
+        META_TEMPO,
+        &options.micros_per_quarter().to_be_bytes()[1..],
+    );
+
+    // Note offs come before note ons at the same tick, so touching notes of a key stay apart
+    let mut events = Vec::<(u32, bool, [u8; 3])>::new();
+    for (&key, key_presses) in notes {
+        let note = MidiNote::from_piano_key(key);
+
+        for key_press in key_presses.iter() {
+            let start = options.ticks(key_press.start());
+            let end = options.ticks(key_press.start() + key_press.duration().as_millis());
+
+            events.push((
+                start,
+                true,
+                MidiCommand::NoteOn(note, key_press.velocity()).to_bytes(),
+            ));
+            events.push((
+                end.max(start + 1),
+                false,
+                MidiCommand::NoteOff(note, RELEASE_VELOCITY).to_bytes(),
+            ));
+        }
+    }
+    events.sort_by_key(|&(tick, note_on, _)| (tick, note_on));
+
+    let mut tracks = match options.format {
+        SmfFormat::SingleTrack => vec![tempo],
+        SmfFormat::MultiTrack => {
+            tempo.end(0);
+
+            vec![tempo, Track::default()]
+        }
+    };
+
+    if let Some(track) = tracks.last_mut() {
+        track.meta(0, META_TRACK_NAME, crate::NAME.as_bytes());
+
+        let mut tick = 0;
+        for (event_tick, _, event) in events {
+            track.event(event_tick - tick, &event);
+            tick = event_tick;
+        }
+        track.end(0);
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend(b"MThd");
+    bytes.extend(6u32.to_be_bytes());
+    bytes.extend(options.format.number().to_be_bytes());
+    bytes.extend((tracks.len() as u16).to_be_bytes());
+    bytes.extend(options.ppq.to_be_bytes());
+    for track in tracks {
+        bytes.extend(b"MTrk");
+        bytes.extend((track.0.len() as u32).to_be_bytes());
+        bytes.extend(track.0);
+    }
+
+    bytes
+}
+
+/// Write the notes to a standard MIDI file at the path, see [`write`]
+pub fn export(
+    path: &Path,
+    notes: &BTreeMap<PianoKey, KeyPresses>,
+    options: SmfOptions,
+) -> color_eyre::Result<()> {
+    fs::write(path, write(notes, options))
+        .wrap_err_with(|| format!("unable to write the MIDI file {}", path.display()))
+}
+
+/// The notes read from a standard MIDI file
+#[derive(Debug, Default)]
+pub struct Song {
+    pub notes: BTreeMap<PianoKey, KeyPresses>,
+    /// The amount of notes outside the 88 keys of a piano, which were left out
+    pub out_of_range: usize,
+}
+
+/// Decode the notes of every channel of every track of a standard MIDI file
+pub fn read(bytes: &[u8]) -> color_eyre::Result<Song> {
+    let mut file = Reader::new(bytes);
+
+    if file.take(4)? != b"MThd" {
+        bail!("not a MIDI file");
+    }
+    let mut header = Reader::new(file.chunk()?);
+    let format = header.u16()?;
+    let _tracks = header.u16()?;
+    let division = header.u16()?;
+
+    if format > 1 {
+        bail!("MIDI files of format {format} are not supported");
+    }
+
+    let timing = if division & 0x8000 == 0 {
+        Timing::Metrical(division)
+    } else {
+        // The frames per second are stored negated, 29 stands for the 29.97 of NTSC
+        let frames_per_second = match ((division >> 8) as u8 as i8).checked_neg() {
+            Some(24) => 24.0,
+            Some(25) => 25.0,
+            Some(29) => 29.97,
+            Some(30) => 30.0,
+            _ => bail!("the SMPTE frame rate of the header is not supported"),
+        };
+
+        Timing::Timecode(frames_per_second * (division & 0xFF) as f64)
+    };
+    if timing == Timing::Metrical(0) || timing == Timing::Timecode(0.0) {
+        bail!("the ticks must have a length");
+    }
+
+    let mut tempo_changes = Vec::new();
+    let mut notes = Vec::new();
+    while !file.is_empty() {
+        let id = file.take(4)?;
+        let chunk = file.chunk()?;
+
+        // Chunks of unknown types are to be skipped
+        if id == b"MTrk" {
+            read_track(chunk, &mut tempo_changes, &mut notes)?;
+        }
+    }
+
+    // Tempo changes of every track apply to all of them
+    tempo_changes.sort_by_key(|&(tick, _)| tick);
+    let millis = |tick: u64| {
+        let seconds = match timing {
+            Timing::Metrical(ppq) => {
+                let mut seconds = 0.0;
+                let (mut tempo_tick, mut micros_per_quarter) = (0, DEFAULT_MICROS_PER_QUARTER);
+
+                for &(change_tick, change) in tempo_changes
+                    .iter()
+                    .take_while(|&&(change_tick, _)| change_tick < tick)
+                {
+                    seconds += (change_tick - tempo_tick) as f64 * micros_per_quarter as f64
+                        / 1_000_000.0
+                        / ppq as f64;
+                    tempo_tick = change_tick;
+                    micros_per_quarter = change;
+                }
+
+                seconds
+                    + (tick - tempo_tick) as f64 * micros_per_quarter as f64
+                        / 1_000_000.0
+                        / ppq as f64
+            }
+            Timing::Timecode(ticks_per_second) => tick as f64 / ticks_per_second,
+        };
+
+        (seconds * 1000.0).round() as u64
+    };
+
+    let mut song = Song::default();
+    for note in notes {
+        let key = match note.note.as_piano_key() {
+            Some(key) => key,
+            None => {
+                song.out_of_range += 1;
+                continue;
+            }
+        };
+
+        let (start, end) = (millis(note.start), millis(note.end));
+        // The inverse of `KeyPress::velocity`
+        let intensity = (note.velocity.max(1) - 1) as f32 / 126.0;
+
+        song.notes.entry(key).or_default().insert(KeyPress::new(
+            start,
+            Duration::from_millis(end - start),
+            intensity,
+        ));
+    }
+
+    Ok(song)
+}
+
+/// Read the notes of a standard MIDI file at the path, see [`read`]
+pub fn import(path: &Path) -> color_eyre::Result<Song> {
+    let bytes = fs::read(path)
+        .wrap_err_with(|| format!("unable to open the MIDI file {}", path.display()))?;
+
+    read(&bytes).wrap_err_with(|| format!("unable to read the MIDI file {}", path.display()))
+}
+
+/// How long a tick is
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Timing {
+    /// A fraction of a quarter note, whose length is set by the tempo
+    Metrical(u16),
+    /// A fixed amount of ticks per second
+    Timecode(f64),
+}
+
+/// A note between a note on and a note off event, in ticks
+#[derive(Debug)]
+struct TrackNote {
+    note: MidiNote,
+    velocity: u8,
+    start: u64,
+    end: u64,
+}
+
+/// Add the tempo changes and the notes of a track chunk
+fn read_track(
+    chunk: &[u8],
+    tempo_changes: &mut Vec<(u64, u32)>,
+    notes: &mut Vec<TrackNote>,
+) -> color_eyre::Result<()> {
+    let mut track = Reader::new(chunk);
+
+    // The notes that are on, first pressed first, as a key may be pressed again before it is
+    // released
+    let mut pressed = HashMap::<(u8, MidiNote), VecDeque<(u64, u8)>>::new();
+    let mut running_status = None;
+    let mut tick = 0;
+
+    while !track.is_empty() {
+        tick += track.variable_length()? as u64;
+
+        let status = if track.peek()? & 0x80 != 0 {
+            track.u8()?
+        } else {
+            running_status.ok_or_else(|| eyre!("running status without an event before it"))?
+        };
+
+        match status {
+            META => {
+                running_status = None;
+
+                let kind = track.u8()?;
+                let length = track.variable_length()?;
+                let data = track.take(length as usize)?;
+
+                match (kind, data) {
+                    (META_END_OF_TRACK, _) => break,
+                    (META_TEMPO, &[high, middle, low]) => {
+                        tempo_changes.push((tick, u32::from_be_bytes([0, high, middle, low])))
+                    }
+                    _ => {}
+                }
+            }
+            SYSEX | SYSEX_ESCAPE => {
+                running_status = None;
+
+                let length = track.variable_length()?;
+                track.take(length as usize)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+
+                let channel = status & 0x0F;
+                match status >> 4 {
+                    // Program change and channel pressure have a single data byte
+                    0xC | 0xD => {
+                        track.u8()?;
+                    }
+                    kind => {
+                        let (note, velocity) = (track.u8()? & 0x7F, track.u8()? & 0x7F);
+                        let key = (channel, MidiNote::new(note));
+
+                        match kind {
+                            // A note on without velocity is a note off
+                            0x9 if velocity > 0 => {
+                                pressed.entry(key).or_default().push_back((tick, velocity))
+                            }
+                            0x8 | 0x9 => {
+                                if let Some((start, velocity)) =
+                                    pressed.get_mut(&key).and_then(VecDeque::pop_front)
+                                {
+                                    notes.push(TrackNote {
+                                        note: key.1,
+                                        velocity,
+                                        start,
+                                        end: tick,
+                                    });
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            status => bail!("unexpected status byte {status:#04x}"),
+        }
+    }
+
+    // Notes that are never released end with their track
+    for ((_, note), starts) in pressed {
+        notes.extend(starts.into_iter().map(|(start, velocity)| TrackNote {
+            note,
+            velocity,
+            start,
+            end: tick,
+        }));
+    }
+
+    Ok(())
+}
+
+/// Reads big endian numbers from the bytes of a file
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, count: usize) -> color_eyre::Result<&'a [u8]> {
+        if count > self.bytes.len() {
+            bail!("the file ends in the middle of a chunk");
+        }
+
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn peek(&self) -> color_eyre::Result<u8> {
+        self.bytes
+            .first()
+            .copied()
+            .ok_or_else(|| eyre!("the file ends in the middle of a chunk"))
+    }
+
+    fn u8(&mut self) -> color_eyre::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> color_eyre::Result<u16> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> color_eyre::Result<u32> {
+        Ok(u32::from_be_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+
+    /// The data of a chunk after its type
+    fn chunk(&mut self) -> color_eyre::Result<&'a [u8]> {
+        let length = self.u32()?;
+
+        self.take(length as usize)
+    }
+
+    /// A quantity of up to four bytes, see [`write_variable_length`]
+    fn variable_length(&mut self) -> color_eyre::Result<u32> {
+        let mut value = 0;
+
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | (byte & 0x7F) as u32;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        bail!("variable length quantities are at most four bytes long")
+    }
+}
+
+/// The events of a track chunk
+#[derive(Debug, Default)]
+struct Track(Vec<u8>);
+
+impl Track {
+    fn event(&mut self, delta: u32, event: &[u8]) {
+        write_variable_length(&mut self.0, delta);
+        self.0.extend(event);
+    }
+
+    fn meta(&mut self, delta: u32, kind: u8, data: &[u8]) {
+        self.event(delta, &[META, kind]);
+        write_variable_length(&mut self.0, data.len() as u32);
+        self.0.extend(data);
+    }
+
+    fn end(&mut self, delta: u32) {
+        self.meta(delta, META_END_OF_TRACK, &[]);
+    }
+}
+
+/// Append a quantity of up to 28 bits in groups of 7, most significant first, with the top bit
+/// set on every byte but the last
+fn write_variable_length(bytes: &mut Vec<u8>, value: u32) {
+    assert!(
+        value <= 0x0FFF_FFFF,
+        "variable length quantities are at most 28 bits"
+    );
+
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+
+    bytes.extend(groups.into_iter().rev());
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::{read, write, write_variable_length, SmfFormat, SmfOptions};
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::PianoKey,
+    };
+
+    /// A track as the absolute tick and bytes of each event
+    type Events = Vec<(u32, Vec<u8>)>;
+
+    fn read_u16(bytes: &[u8]) -> u16 {
+        u16::from_be_bytes([bytes[0], bytes[1]])
+    }
+
+    fn read_variable_length(bytes: &[u8], position: &mut usize) -> u32 {
+        let mut value = 0;
+        loop {
+            let byte = bytes[*position];
+            *position += 1;
+            value = value << 7 | (byte & 0x7F) as u32;
+
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    /// Split the file into its format, ticks per quarter note and tracks
+    fn parse(bytes: &[u8]) -> (u16, u16, Vec<Events>) {
+        assert_eq!(&bytes[..8], b"MThd\0\0\0\x06");
+        let (format, track_count, ppq) = (
+            read_u16(&bytes[8..]),
+            read_u16(&bytes[10..]),
+            read_u16(&bytes[12..]),
+        );
+
+        let mut tracks = Vec::new();
+        let mut chunk = 14;
+        for _ in 0..track_count {
+            assert_eq!(&bytes[chunk..chunk + 4], b"MTrk");
+            let length = u32::from_be_bytes(bytes[chunk + 4..chunk + 8].try_into().unwrap());
+            let end = chunk + 8 + length as usize;
+
+            let mut events = Vec::new();
+            let (mut position, mut tick) = (chunk + 8, 0);
+            while position < end {
+                tick += read_variable_length(bytes, &mut position);
+
+                let start = position;
+                if bytes[position] == 0xFF {
+                    position += 2;
+                    position += read_variable_length(bytes, &mut position) as usize;
+                } else {
+                    position += 3;
+                }
+                events.push((tick, bytes[start..position].to_vec()));
+            }
+
+            assert_eq!(position, end, "events must end with their chunk");
+            tracks.push(events);
+            chunk = end;
+        }
+        assert_eq!(chunk, bytes.len());
+
+        (format, ppq, tracks)
+    }
+
+    fn notes() -> BTreeMap<PianoKey, KeyPresses> {
+        let a4 = PianoKey::new(49).unwrap();
+        let c5 = PianoKey::new(52).unwrap();
+
+        let mut repeated = KeyPresses::new();
+        repeated.insert(KeyPress::new(0u64, Duration::from_millis(500), 1.0));
+        repeated.insert(KeyPress::new(500u64, Duration::from_millis(250), 0.0));
+
+        BTreeMap::from([
+            (a4, repeated),
+            (
+                c5,
+                KeyPresses::from([KeyPress::new(250u64, Duration::from_millis(1000), 0.5)]),
+            ),
+        ])
+    }
+
+    /// Every note as its key, start and duration in milliseconds and velocity
+    fn presses(notes: &BTreeMap<PianoKey, KeyPresses>) -> Vec<(u8, u128, u128, u8)> {
+        notes
+            .iter()
+            .flat_map(|(key, key_presses)| {
+                key_presses.iter().map(|press| {
+                    (
+                        key.number(),
+                        press.start(),
+                        press.duration().as_millis(),
+                        press.velocity(),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The note events of the notes above at 120 quarter notes per minute
+    fn note_events(ppq: u32) -> Events {
+        let quarter = |quarters: u32| quarters * ppq / 2;
+
+        vec![
+            (0, vec![0x90, 69, 127]),
+            (quarter(1), vec![0x90, 72, 64]),
+            (quarter(2), vec![0x80, 69, 64]),
+            (quarter(2), vec![0x90, 69, 1]),
+            (quarter(3), vec![0x80, 69, 64]),
+            (quarter(5), vec![0x80, 72, 64]),
+        ]
+    }
+
+    #[test]
+    fn variable_length_quantities() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (0x40, vec![0x40]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x81, 0x00]),
+            (0x2000, vec![0xC0, 0x00]),
+            (0x1F_FFFF, vec![0xFF, 0xFF, 0x7F]),
+            (0x0FFF_FFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ] {
+            let mut bytes = Vec::new();
+            write_variable_length(&mut bytes, value);
+
+            assert_eq!(bytes, expected, "{value:#x}");
+        }
+    }
+
+    #[test]
+    fn multi_track() {
+        let bytes = write(&notes(), SmfOptions::default());
+        let (format, ppq, tracks) = parse(&bytes);
+
+        assert_eq!((format, ppq), (1, 480));
+        assert_eq!(
+            tracks[0],
+            [
+                (0, vec![0xFF, 0x51, 3, 0x07, 0xA1, 0x20]),
+                (0, vec![0xFF, 0x2F, 0])
+            ]
+        );
+
+        let mut expected = vec![(0, b"\xFF\x03\x05Pitch".to_vec())];
+        expected.extend(note_events(480));
+        expected.push((1200, vec![0xFF, 0x2F, 0]));
+        assert_eq!(tracks[1], expected);
+        assert_eq!(tracks.len(), 2);
+    }
+
+    #[test]
+    fn single_track() {
+        let options = SmfOptions {
+            format: SmfFormat::SingleTrack,
+            tempo: 60.0,
+            ppq: 96,
+        };
+        let bytes = write(&notes(), options);
+        let (format, ppq, tracks) = parse(&bytes);
+
+        // Half the tempo, so every note takes half as many quarter notes
+        let mut expected = vec![
+            (0, vec![0xFF, 0x51, 3, 0x0F, 0x42, 0x40]),
+            (0, b"\xFF\x03\x05Pitch".to_vec()),
+        ];
+        expected.extend(note_events(48));
+        expected.push((120, vec![0xFF, 0x2F, 0]));
+
+        assert_eq!((format, ppq), (0, 96));
+        assert_eq!(tracks, [expected]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in SmfFormat::ALL {
+            for (tempo, ppq) in [(120.0, 480), (60.0, 96), (93.0, 960)] {
+                let options = SmfOptions { format, tempo, ppq };
+                let song = read(&write(&notes(), options)).unwrap();
+
+                assert_eq!(presses(&song.notes), presses(&notes()), "{options:?}");
+                assert_eq!(song.out_of_range, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn running_status_and_tempo_changes() {
+        #[rustfmt::skip]
+        let bytes = [
+            b"MThd\0\0\0\x06\0\x01\0\x02\0\x60".as_slice(),
+            // Half as fast after two quarter notes
+            b"MTrk\0\0\0\x0F",
+            &[0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20],
+            &[0x81, 0x40, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40],
+            b"MTrk\0\0\0\x25",
+            // C4 and E4, the second one and their releases without a status byte
+            &[0x00, 0x90, 60, 100],
+            &[0x00, 64, 100],
+            &[0x60, 60, 0],
+            &[0x60, 64, 0],
+            // G4 across the tempo change
+            &[0x00, 67, 80],
+            &[0x60, 0x80, 67, 64],
+            // A system exclusive message, then a note below the piano
+            &[0x00, 0xF0, 2, 0x7E, 0xF7],
+            &[0x00, 0x90, 0, 1],
+            &[0x60, 0x80, 0, 0],
+            &[0x00, 0xFF, 0x2F, 0],
+        ]
+        .concat();
+
+        let song = read(&bytes).unwrap();
+
+        assert_eq!(
+            presses(&song.notes),
+            [(40, 0, 500, 100), (44, 0, 1000, 100), (47, 1000, 1000, 80)]
+        );
+        assert_eq!(song.out_of_range, 1);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let bytes = write(&notes(), SmfOptions::default());
+
+        assert!(read(b"RIFF\0\0\0\0WAVE").is_err());
+        assert!(read(&bytes[..bytes.len() - 3]).is_err());
+        assert!(read(&[]).is_err());
+
+        // SMPTE timing with a frame rate of -128 and of the 60 that is not a SMPTE rate
+        for rate in [0x80, 0xC4] {
+            let mut bytes = bytes.clone();
+            bytes[12..14].copy_from_slice(&[rate, 40]);
+
+            assert!(read(&bytes).is_err());
+        }
+
+        // While 25 frames of 40 ticks are fine
+        let mut bytes = bytes;
+        bytes[12..14].copy_from_slice(&[0xE7, 40]);
+        assert!(read(&bytes).is_ok());
+    }
+}
@@ -0,0 +1,514 @@
+//! A software synthesizer, to hear the notes when no midi output port is connected
+//!
+//! Every note is played by a voice that reads a wavetable, which is built by adding up the
+//! harmonics of the note that lie below the Nyquist frequency so high notes do not alias. The
+//! loudness of a voice follows an ADSR envelope: it rises to full level during the attack, falls
+//! to the sustain level during the decay, holds it until the note is released and then fades
+//! out during the release.
+//!
+//! The synth runs on its own thread, as the output stream can not be moved between threads. It
+//! queues short blocks of samples on an [`AudioSink`] while notes are sounding, keeping a few
+//! blocks ahead of the output device.
+
+use std::{
+    f32::consts::TAU,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use audio::{
+    device::DeviceSettings,
+    output::{AudioSink, Sink},
+    waveform::Waveform,
+};
+use color_eyre::eyre::{eyre, WrapErr};
+use flume::{Receiver, RecvTimeoutError, Sender};
+use tracing::{debug, warn};
+
+use crate::midi::{MidiCommand, MidiError, MidiNote};
+
+/// The length of the wavetable of every voice, in samples
+const TABLE_SIZE: usize = 2048;
+
+/// The amount of notes that can sound at once, the oldest note is cut off to play another one
+const MAX_VOICES: usize = 32;
+
+/// The amount of frames rendered and queued at a time
+const BLOCK_FRAMES: usize = 512;
+
+/// The amount of blocks kept queued on the sink, which has to cover the buffer of the output
+/// device to play without gaps
+const QUEUED_BLOCKS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynthOptions {
+    /// The amplitudes of the harmonics, starting with the fundamental
+    pub harmonics: Vec<f32>,
+    /// Seconds to rise to full level
+    pub attack: f32,
+    /// Seconds to fall from full level to the sustain level
+    pub decay: f32,
+    /// The level held until the note is released, between 0 and 1
+    pub sustain: f32,
+    /// Seconds to fade out once the note is released
+    pub release: f32,
+    /// The level of a single note played at full velocity
+    pub gain: f32,
+}
+
+impl Default for SynthOptions {
+    fn default() -> Self {
+        Self {
+            harmonics: vec![1.0, 0.5, 0.3, 0.2, 0.12, 0.08, 0.05, 0.03],
+            attack: 0.005,
+            decay: 0.4,
+            sustain: 0.4,
+            release: 0.2,
+            gain: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    /// Fading out from the level the note was released at
+    Release(f32),
+    Done,
+}
+
+#[derive(Debug)]
+struct Voice {
+    note: MidiNote,
+    /// Holds one period of the note
+    table: Vec<f32>,
+    /// The position in the table
+    phase: f32,
+    /// How far the phase moves every sample
+    step: f32,
+    amplitude: f32,
+    stage: Stage,
+    level: f32,
+}
+
+/// The voices of the notes that are sounding, rendered into samples on demand
+#[derive(Debug)]
+pub struct Synth {
+    options: SynthOptions,
+    sample_rate: u32,
+    voices: Vec<Voice>,
+}
+
+impl Synth {
+    pub fn new(options: SynthOptions, sample_rate: u32) -> Self {
+        Self {
+            options,
+            sample_rate,
+            voices: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// If no note is sounding, including notes that are fading out
+    pub fn is_silent(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    /// Play the command as a midi device would, pitch bends are not supported
+    pub fn handle(&mut self, command: MidiCommand) {
+        match command {
+            // A note on without velocity is a note off
+            MidiCommand::NoteOn(note, 0) | MidiCommand::NoteOff(note, _) => self.note_off(note),
+            MidiCommand::NoteOn(note, velocity) => self.note_on(note, velocity),
+            MidiCommand::AllSoundOff => self.voices.clear(),
+            MidiCommand::PitchBendChange(_) => debug!(?command, "Ignoring pitch bend"),
+        }
+    }
+
+    /// Start playing the note with the 7 bit velocity
+    pub fn note_on(&mut self, note: MidiNote, velocity: u8) {
+        // Playing a note again releases the previous one
+        self.note_off(note);
+
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+
+        let frequency = 440.0 * 2f32.powf((note.as_u8() as f32 - 69.0) / 12.0);
+        let velocity = velocity.min(127) as f32 / 127.0;
+
+        self.voices.push(Voice {
+            note,
+            table: self.wavetable(frequency),
+            phase: 0.0,
+            step: frequency * TABLE_SIZE as f32 / self.sample_rate as f32,
+            // Velocity is felt as loudness, which grows faster than the amplitude
+            amplitude: self.options.gain * velocity * velocity,
+            stage: Stage::Attack,
+            level: 0.0,
+        });
+    }
+
+    /// Let the note fade out
+    pub fn note_off(&mut self, note: MidiNote) {
+        for voice in &mut self.voices {
+            if voice.note == note && !matches!(voice.stage, Stage::Release(_) | Stage::Done) {
+                voice.stage = Stage::Release(voice.level);
+            }
+        }
+    }
+
+    /// Render the next frames of all voices mixed together, in mono
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; frames];
+
+        let sample_rate = self.sample_rate as f32;
+        let options = &self.options;
+
+        // The amount the level changes every sample in each stage
+        let attack = 1.0 / (options.attack * sample_rate).max(1.0);
+        let decay = (1.0 - options.sustain) / (options.decay * sample_rate).max(1.0);
+        let release = 1.0 / (options.release * sample_rate).max(1.0);
+
+        for voice in &mut self.voices {
+            for sample in &mut samples {
+                voice.level = match voice.stage {
+                    Stage::Attack => voice.level + attack,
+                    Stage::Decay => voice.level - decay,
+                    Stage::Sustain => options.sustain,
+                    Stage::Release(from) => voice.level - from * release,
+                    Stage::Done => break,
+                };
+
+                voice.stage = match voice.stage {
+                    Stage::Attack if voice.level >= 1.0 => Stage::Decay,
+                    Stage::Decay if voice.level <= options.sustain => Stage::Sustain,
+                    Stage::Release(_) if voice.level <= 0.0 => Stage::Done,
+                    stage => stage,
+                };
+                voice.level = voice.level.clamp(0.0, 1.0);
+
+                // Interpolate between the samples of the table
+                let index = voice.phase as usize;
+                let fraction = voice.phase.fract();
+                let value = voice.table[index] * (1.0 - fraction)
+                    + voice.table[(index + 1) % TABLE_SIZE] * fraction;
+
+                *sample += value * voice.amplitude * voice.level;
+
+                voice.phase = (voice.phase + voice.step) % TABLE_SIZE as f32;
+            }
+        }
+
+        self.voices.retain(|voice| voice.stage != Stage::Done);
+
+        samples
+    }
+
+    /// One period of the note, made of the harmonics that can be played at the sample rate
+    fn wavetable(&self, frequency: f32) -> Vec<f32> {
+        let nyquist = self.sample_rate as f32 / 2.0;
+
+        let harmonics = self
+            .options
+            .harmonics
+            .iter()
+            .enumerate()
+            .map(|(index, &amplitude)| (index + 1, amplitude))
+            .take_while(|&(harmonic, _)| harmonic as f32 * frequency < nyquist)
+            .collect::<Vec<_>>();
+
+        // Keep the peak of the table at most 1, however many harmonics are added
+        let total = harmonics
+            .iter()
+            .map(|(_, amplitude)| amplitude.abs())
+            .sum::<f32>()
+            .max(f32::EPSILON);
+
+        (0..TABLE_SIZE)
+            .map(|index| {
+                let phase = index as f32 / TABLE_SIZE as f32;
+
+                harmonics
+                    .iter()
+                    .map(|&(harmonic, amplitude)| amplitude * (TAU * harmonic as f32 * phase).sin())
+                    .sum::<f32>()
+                    / total
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+enum SynthCommand {
+    Play(MidiCommand),
+    /// Play on another output device
+    Reopen(DeviceSettings),
+}
+
+/// Plays midi commands with a [`Synth`] on an output device
+#[derive(Debug, Clone)]
+pub struct SynthOutput {
+    sender: Sender<SynthCommand>,
+    /// If the synth has an output device to play on
+    available: Arc<AtomicBool>,
+}
+
+impl SynthOutput {
+    /// Start the synth on the output device, falling back to the default device
+    ///
+    /// Failing to open a device when the synth is reopened is reported on `errors`.
+    pub fn new(
+        settings: &DeviceSettings,
+        options: SynthOptions,
+        errors: Sender<MidiError>,
+    ) -> color_eyre::Result<Self> {
+        let (sender, commands) = flume::unbounded();
+        let (opened, result) = flume::bounded(1);
+        let available = Arc::new(AtomicBool::new(true));
+
+        let settings = settings.clone();
+        let thread_available = available.clone();
+        thread::Builder::new()
+            .name("synth".into())
+            .spawn(move || match open_sink(&settings) {
+                Ok(sink) => {
+                    let _ = opened.send(Ok(()));
+
+                    synth_thread(sink, commands, options, thread_available, errors);
+                }
+                Err(error) => {
+                    let _ = opened.send(Err(error));
+                }
+            })
+            .wrap_err("unable to start the synth thread")?;
+
+        result
+            .recv()
+            .map_err(|_| eyre!("the synth thread has stopped"))??;
+
+        Ok(Self { sender, available })
+    }
+
+    /// If the synth has an output device, which it loses when reopening it fails
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    /// Play the command, which is dropped if the synth has stopped
+    pub fn play(&self, command: MidiCommand) {
+        if self.sender.send(SynthCommand::Play(command)).is_err() {
+            warn!(?command, "The synth has stopped.. ignoring command");
+        }
+    }
+
+    /// Move the synth to another output device, cutting off the sounding notes
+    pub fn reopen(&self, settings: &DeviceSettings) {
+        if self
+            .sender
+            .send(SynthCommand::Reopen(settings.clone()))
+            .is_err()
+        {
+            warn!("The synth has stopped.. unable to reopen it");
+        }
+    }
+}
+
+/// Open the output device, falling back to the default device
+fn open_sink(settings: &DeviceSettings) -> color_eyre::Result<AudioSink> {
+    AudioSink::with_device(settings).or_else(|error| {
+        warn!(%error, "unable to open the audio output for the synth, using the default");
+
+        AudioSink::new()
+    })
+}
+
+fn synth_thread(
+    sink: AudioSink,
+    commands: Receiver<SynthCommand>,
+    options: SynthOptions,
+    available: Arc<AtomicBool>,
+    errors: Sender<MidiError>,
+) {
+    let mut synth = Synth::new(options.clone(), sink.sample_rate());
+    let mut sink = Some(sink);
+
+    loop {
+        // Sleep until the next command while there is nothing to play, instead of queueing
+        // silence, otherwise wake up in time to keep the queue filled
+        let block = Duration::from_secs_f32(BLOCK_FRAMES as f32 / synth.sample_rate() as f32);
+        let command = if synth.is_silent() {
+            commands.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            commands.recv_timeout(block / 2)
+        };
+
+        let command = match command {
+            Ok(command) => Some(command),
+            Err(RecvTimeoutError::Timeout) => None,
+            // Every output has been dropped
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        for command in command.into_iter().chain(commands.try_iter()) {
+            match command {
+                SynthCommand::Play(command) => synth.handle(command),
+                SynthCommand::Reopen(settings) => {
+                    // Close the old output stream before opening the new one
+                    drop(sink.take());
+
+                    sink = open_sink(&settings)
+                        .map_err(|error| {
+                            warn!(%error, "unable to reopen the synth");
+
+                            // Nobody is listening once the player has been dropped
+                            let _ = errors.send(MidiError::Synth(error));
+                        })
+                        .ok();
+                    available.store(sink.is_some(), Ordering::Relaxed);
+                    synth = Synth::new(options.clone(), sink.as_ref().map_or(1, Sink::sample_rate));
+                }
+            }
+        }
+
+        match &sink {
+            Some(sink) => {
+                while !synth.is_silent() && sink.queue_length() < QUEUED_BLOCKS {
+                    let samples = synth.render(BLOCK_FRAMES);
+
+                    sink.queue(&Waveform::new(samples, synth.sample_rate()), |_| {});
+                }
+            }
+            // There is nowhere to play the notes
+            None => synth.handle(MidiCommand::AllSoundOff),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Synth, SynthOptions, MAX_VOICES};
+    use crate::midi::{MidiCommand, MidiNote};
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    /// Plain sine waves that reach their sustain level right away
+    fn sine_options() -> SynthOptions {
+        SynthOptions {
+            harmonics: vec![1.0],
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.1,
+            gain: 1.0,
+        }
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn pitch() {
+        let mut synth = Synth::new(sine_options(), SAMPLE_RATE);
+
+        // A4
+        synth.note_on(MidiNote::new(69), 127);
+        let samples = synth.render(SAMPLE_RATE as usize);
+
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert!((439..=441).contains(&crossings), "{crossings}");
+
+        assert!((peak(&samples) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn velocity() {
+        let mut loud = Synth::new(sine_options(), SAMPLE_RATE);
+        loud.note_on(MidiNote::new(60), 127);
+
+        let mut soft = Synth::new(sine_options(), SAMPLE_RATE);
+        soft.handle(MidiCommand::NoteOn(MidiNote::new(60), 64));
+
+        let ratio = peak(&soft.render(4800)) / peak(&loud.render(4800));
+        assert!((0.2..0.3).contains(&ratio), "{ratio}");
+    }
+
+    #[test]
+    fn envelope() {
+        let options = SynthOptions {
+            attack: 0.01,
+            decay: 0.01,
+            sustain: 0.5,
+            ..sine_options()
+        };
+        let mut synth = Synth::new(options, SAMPLE_RATE);
+        assert!(synth.is_silent());
+
+        synth.note_on(MidiNote::new(69), 127);
+
+        // Rising during the attack, before falling to the sustain level
+        let attack = synth.render(240);
+        assert!(peak(&attack[..120]) < peak(&attack[120..]));
+        let decay = synth.render(960);
+        assert!(peak(&decay) > 0.9);
+        let sustain = synth.render(4800);
+        assert!((peak(&sustain) - 0.5).abs() < 0.01);
+
+        // A note on without velocity releases the note, which fades out within the release
+        synth.handle(MidiCommand::NoteOn(MidiNote::new(69), 0));
+        let release = synth.render(5280);
+        assert!(peak(&release[..480]) > peak(&release[4320..4800]));
+        assert!(synth.is_silent());
+        assert_eq!(peak(&synth.render(480)), 0.0);
+    }
+
+    #[test]
+    fn voices() {
+        let mut synth = Synth::new(SynthOptions::default(), SAMPLE_RATE);
+
+        // Playing the same note again replaces it
+        synth.note_on(MidiNote::new(60), 100);
+        synth.note_on(MidiNote::new(60), 100);
+        synth.render(SAMPLE_RATE as usize);
+        assert_eq!(synth.voices.len(), 1);
+
+        for note in 21..=108 {
+            synth.note_on(MidiNote::new(note), 100);
+        }
+        assert_eq!(synth.voices.len(), MAX_VOICES);
+        assert!(synth.render(480).iter().all(|sample| sample.is_finite()));
+
+        synth.handle(MidiCommand::AllSoundOff);
+        assert!(synth.is_silent());
+    }
+
+    #[test]
+    fn band_limited() {
+        let synth = Synth::new(SynthOptions::default(), 10_000);
+
+        // Only the fundamental of C8 lies below the Nyquist frequency, leaving a sine wave
+        let table = synth.wavetable(4186.0);
+        let expected = (0..table.len())
+            .map(|index| (std::f32::consts::TAU * index as f32 / table.len() as f32).sin());
+
+        assert!(table
+            .iter()
+            .zip(expected)
+            .all(|(sample, expected)| (sample - expected).abs() < 1e-4));
+    }
+}
@@ -1,5 +1,18 @@
-use eframe::egui::Ui;
+use eframe::{
+    egui::{RichText, Ui},
+    epaint::Color32,
+};
 
 pub trait UiError {
     fn ui_error(&self, ui: &mut Ui);
 }
+
+impl UiError for color_eyre::Report {
+    fn ui_error(&self, ui: &mut Ui) {
+        ui.label(RichText::new("Error").heading().color(Color32::RED));
+
+        for cause in self.chain() {
+            ui.label(cause.to_string());
+        }
+    }
+}
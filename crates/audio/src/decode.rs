@@ -0,0 +1,416 @@
+//! Decoding of compressed and container audio formats through symphonia
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, Track},
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use tracing::{info, warn};
+
+use crate::waveform::Waveform;
+
+#[derive(Debug)]
+pub enum CreateDecoderError {
+    OpenFile(PathBuf, io::Error),
+    UnsupportedAudioFormat(SymphoniaError),
+    NoSupportedAudioTrack,
+    /// There is no track with the requested id in the file
+    UnknownTrack(u32),
+    UnknownCodec(SymphoniaError),
+}
+
+impl Display for CreateDecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateDecoderError::OpenFile(path, error) => {
+                write!(f, "unable to open {}: {error}", path.display())
+            }
+            CreateDecoderError::UnsupportedAudioFormat(error) => {
+                write!(f, "unsupported audio format: {error}")
+            }
+            CreateDecoderError::NoSupportedAudioTrack => {
+                write!(f, "file contains no supported audio tracks")
+            }
+            CreateDecoderError::UnknownTrack(id) => write!(f, "file has no track with id {id}"),
+            CreateDecoderError::UnknownCodec(error) => write!(f, "unknown audio codec: {error}"),
+        }
+    }
+}
+
+impl Error for CreateDecoderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CreateDecoderError::OpenFile(_, error) => Some(error),
+            CreateDecoderError::UnsupportedAudioFormat(error)
+            | CreateDecoderError::UnknownCodec(error) => Some(error),
+            CreateDecoderError::NoSupportedAudioTrack | CreateDecoderError::UnknownTrack(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The container could not be read any further
+    Format(SymphoniaError),
+    /// A packet could not be decoded and the error was not recoverable
+    Decode(SymphoniaError),
+    /// The selected track did not contain any audio
+    NoPackets,
+    /// The requested channel does not exist in the decoded audio
+    ChannelOutOfRange { channel: u16, channels: u16 },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Format(error) => write!(f, "unable to read packet: {error}"),
+            DecodeError::Decode(error) => write!(f, "unable to decode packet: {error}"),
+            DecodeError::NoPackets => write!(f, "track contains no audio"),
+            DecodeError::ChannelOutOfRange { channel, channels } => {
+                write!(
+                    f,
+                    "channel {channel} does not exist in audio with {channels} channels"
+                )
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::Format(error) | DecodeError::Decode(error) => Some(error),
+            DecodeError::NoPackets | DecodeError::ChannelOutOfRange { .. } => None,
+        }
+    }
+}
+
+/// Which channels of decoded or recorded audio end up in the waveform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSelection {
+    /// Keep every channel
+    All,
+    /// Average all channels into one
+    Downmix,
+    /// Keep only the channel with the given index
+    Single(u16),
+}
+
+/// Information about a track in a media file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub id: u32,
+    /// The short name of the codec, if it is known to symphonia
+    pub codec: Option<&'static str>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub frames: Option<u64>,
+    pub language: Option<String>,
+}
+
+impl TrackInfo {
+    fn new(track: &Track) -> Self {
+        let params = &track.codec_params;
+
+        Self {
+            id: track.id,
+            codec: symphonia::default::get_codecs()
+                .get_codec(params.codec)
+                .map(|descriptor| descriptor.short_name),
+            channels: params.channels.map(|channels| channels.count() as u16),
+            sample_rate: params.sample_rate,
+            frames: params.n_frames,
+            language: track.language.clone(),
+        }
+    }
+
+    /// If this track can be decoded
+    pub fn is_supported(&self) -> bool {
+        self.codec.is_some()
+    }
+
+    /// The duration of this track in seconds, if it is known
+    pub fn duration(&self) -> Option<f32> {
+        Some(self.frames? as f32 / self.sample_rate? as f32)
+    }
+}
+
+/// How far along the decoder is through its track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeProgress {
+    pub frames: u64,
+    pub total_frames: Option<u64>,
+}
+
+impl DecodeProgress {
+    /// The progress as a fraction between 0 and 1, if the length of the track is known
+    pub fn fraction(&self) -> Option<f32> {
+        self.total_frames
+            .map(|total| (self.frames as f32 / total as f32).min(1.0))
+    }
+}
+
+pub struct AudioDecoder {
+    decoder: Box<dyn Decoder>,
+    format: Box<dyn FormatReader>,
+    tracks: Vec<TrackInfo>,
+    track: TrackInfo,
+}
+
+impl fmt::Debug for AudioDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioDecoder")
+            .field("tracks", &self.tracks)
+            .field("track", &self.track)
+            .finish()
+    }
+}
+
+impl AudioDecoder {
+    /// Open a file for decoding, selecting its first supported audio track
+    pub fn open(path: &Path) -> Result<Self, CreateDecoderError> {
+        let file = File::open(path)
+            .map_err(|error| CreateDecoderError::OpenFile(path.to_path_buf(), error))?;
+
+        Self::from_source(
+            Box::new(file),
+            path.extension().and_then(|extension| extension.to_str()),
+        )
+    }
+
+    /// Create a decoder over any media source, using the extension as a hint for the format
+    pub fn from_source(
+        source: Box<dyn MediaSource>,
+        extension: Option<&str>,
+    ) -> Result<Self, CreateDecoderError> {
+        let stream = MediaSourceStream::new(source, Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(extension) = extension {
+            hint.with_extension(extension);
+        }
+
+        let probe = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(CreateDecoderError::UnsupportedAudioFormat)?;
+
+        let format = probe.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(CreateDecoderError::NoSupportedAudioTrack)?;
+
+        let decoder = Self::make_decoder(track)?;
+
+        Ok(AudioDecoder {
+            tracks: format.tracks().iter().map(TrackInfo::new).collect(),
+            track: TrackInfo::new(track),
+            decoder,
+            format,
+        })
+    }
+
+    fn make_decoder(track: &Track) -> Result<Box<dyn Decoder>, CreateDecoderError> {
+        symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(CreateDecoderError::UnknownCodec)
+    }
+
+    /// All of the tracks in the file
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    /// The track that will be decoded
+    pub fn track(&self) -> &TrackInfo {
+        &self.track
+    }
+
+    /// Change the track that will be decoded
+    pub fn select_track(&mut self, id: u32) -> Result<(), CreateDecoderError> {
+        let track = self
+            .format
+            .tracks()
+            .iter()
+            .find(|track| track.id == id)
+            .ok_or(CreateDecoderError::UnknownTrack(id))?;
+
+        self.decoder = Self::make_decoder(track)?;
+        self.track = TrackInfo::new(track);
+
+        Ok(())
+    }
+
+    /// Decode the selected track into a waveform
+    pub fn decode(
+        mut self,
+        channels: ChannelSelection,
+        mut progress_callback: impl FnMut(DecodeProgress),
+    ) -> Result<Waveform<'static>, DecodeError> {
+        let mut spec = None;
+        let mut sample_buf = None;
+        let mut samples = Vec::new();
+
+        // The decode loop.
+        loop {
+            // Get the next packet from the media format.
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    info!("Reached end of file");
+                    break;
+                }
+                // The track list has been changed, which only happens for chained OGG
+                // physical streams. It is not unreasonable to consider this "the end."
+                Err(SymphoniaError::ResetRequired) => {
+                    info!("Track list changed, stopping decode");
+                    break;
+                }
+                Err(err) => return Err(DecodeError::Format(err)),
+            };
+
+            // Consume any new metadata that has been read since the last packet.
+            while !self.format.metadata().is_latest() {
+                // Pop the old head of the metadata queue.
+                self.format.metadata().pop();
+
+                // TODO: process metadata
+            }
+
+            // If the packet does not belong to the selected track, skip over it.
+            if packet.track_id() != self.track.id {
+                continue;
+            }
+
+            progress_callback(DecodeProgress {
+                frames: packet.ts(),
+                total_frames: self.track.frames,
+            });
+
+            // Decode the packet into audio samples.
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = spec.get_or_insert(*decoded.spec());
+
+                    let sample_buf = sample_buf.get_or_insert_with(|| {
+                        SampleBuffer::<f32>::new(decoded.capacity() as u64, *spec)
+                    });
+
+                    sample_buf.copy_interleaved_ref(decoded);
+
+                    samples.extend_from_slice(sample_buf.samples());
+                }
+                // The packet failed to decode due to an IO error or invalid data, skip the packet.
+                Err(SymphoniaError::IoError(err)) => {
+                    warn!(%err, "skipping packet that failed to read");
+                }
+                Err(SymphoniaError::DecodeError(err)) => {
+                    warn!(%err, "skipping packet with invalid data");
+                }
+                Err(err) => return Err(DecodeError::Decode(err)),
+            }
+        }
+
+        let spec = spec.ok_or(DecodeError::NoPackets)?;
+
+        let waveform = Waveform::new_interleaved(samples, spec.channels.count() as u16, spec.rate);
+
+        match channels {
+            ChannelSelection::All => Ok(waveform),
+            ChannelSelection::Downmix => Ok(waveform.downmix()),
+            ChannelSelection::Single(channel) if channel < waveform.channels() => Ok(
+                Waveform::new(waveform.channel(channel).collect(), waveform.sample_rate()),
+            ),
+            ChannelSelection::Single(channel) => Err(DecodeError::ChannelOutOfRange {
+                channel,
+                channels: waveform.channels(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error, io::Cursor};
+
+    use super::{AudioDecoder, ChannelSelection, DecodeError};
+    use crate::{wav::WavFormat, waveform::Waveform};
+
+    fn stereo_decoder() -> Result<AudioDecoder, Box<dyn Error>> {
+        let waveform = Waveform::from_planar(vec![vec![0.5; 64], vec![-0.25; 64]], 8_000);
+
+        let mut bytes = Vec::new();
+        waveform.write_wav(&mut bytes, WavFormat::Float32)?;
+
+        Ok(AudioDecoder::from_source(
+            Box::new(Cursor::new(bytes)),
+            Some("wav"),
+        )?)
+    }
+
+    #[test]
+    fn tracks() -> Result<(), Box<dyn Error>> {
+        let decoder = stereo_decoder()?;
+
+        assert_eq!(decoder.tracks().len(), 1);
+        assert_eq!(decoder.track().channels, Some(2));
+        assert_eq!(decoder.track().sample_rate, Some(8_000));
+        assert_eq!(decoder.track().frames, Some(64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn channel_selection() -> Result<(), Box<dyn Error>> {
+        let all = stereo_decoder()?.decode(ChannelSelection::All, |_| {})?;
+        assert_eq!(all.channels(), 2);
+        assert_eq!(all.len(), 64);
+
+        let downmix = stereo_decoder()?.decode(ChannelSelection::Downmix, |_| {})?;
+        assert_eq!(downmix.channels(), 1);
+        assert!(downmix.samples_iter().all(|sample| sample == 0.125));
+
+        let right = stereo_decoder()?.decode(ChannelSelection::Single(1), |_| {})?;
+        assert!(right.samples_iter().all(|sample| sample == -0.25));
+
+        assert!(matches!(
+            stereo_decoder()?.decode(ChannelSelection::Single(2), |_| {}),
+            Err(DecodeError::ChannelOutOfRange {
+                channel: 2,
+                channels: 2
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<(), Box<dyn Error>> {
+        let mut last = None;
+
+        stereo_decoder()?.decode(ChannelSelection::All, |progress| last = Some(progress))?;
+
+        assert_eq!(last.map(|progress| progress.total_frames), Some(Some(64)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! Finding and opening the audio devices of every host
+
+#[cfg(feature = "egui")]
+mod ui;
+
+#[cfg(feature = "egui")]
+pub use ui::{output_settings_ui, OUTPUT_SETTINGS_KEY};
+
+use color_eyre::eyre::{Context, ContextCompat};
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    BufferSize, Device, Host, SampleFormat, SampleRate, StreamConfig, SupportedBufferSize,
+    SupportedStreamConfig, SupportedStreamConfigRange,
+};
+use tracing::{debug, warn};
+
+/// A device that can be opened by [`AudioSink`](crate::output::AudioSink) or
+/// [`InputStream`](crate::input::InputStream)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// The name of the audio API that the device is accessed through
+    pub host: String,
+    pub name: String,
+    /// If this is the default device of its host
+    pub is_default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+impl DeviceInfo {
+    pub fn supports_sample_rate(&self, sample_rate: u32) -> bool {
+        self.configs
+            .iter()
+            .any(|config| (config.min_sample_rate..=config.max_sample_rate).contains(&sample_rate))
+    }
+
+    /// Devices that do not report their buffer sizes are assumed to support any size
+    pub fn supports_buffer_size(&self, buffer_size: u32) -> bool {
+        self.configs.iter().any(|config| match config.buffer_size {
+            Some((min, max)) => (min..=max).contains(&buffer_size),
+            None => true,
+        })
+    }
+}
+
+/// A range of stream configurations supported by a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// The smallest and largest buffer size in frames, if the host reports them
+    pub buffer_size: Option<(u32, u32)>,
+}
+
+impl From<SupportedStreamConfigRange> for ConfigRange {
+    fn from(range: SupportedStreamConfigRange) -> Self {
+        Self {
+            channels: range.channels(),
+            min_sample_rate: range.min_sample_rate().0,
+            max_sample_rate: range.max_sample_rate().0,
+            buffer_size: match *range.buffer_size() {
+                SupportedBufferSize::Range { min, max } => Some((min, max)),
+                SupportedBufferSize::Unknown => None,
+            },
+        }
+    }
+}
+
+/// Which device to open and how it is configured
+///
+/// Every setting left as `None` uses the default of the host or device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSettings {
+    /// The name of the host, see [`DeviceInfo::host`]
+    pub host: Option<String>,
+    /// The name of the device, see [`DeviceInfo::name`]
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// The buffer size in frames
+    pub buffer_size: Option<u32>,
+}
+
+/// If a device records or plays audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Input,
+    Output,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        }
+    }
+
+    fn devices(self, host: &Host) -> color_eyre::Result<Vec<Device>> {
+        Ok(match self {
+            Direction::Input => host
+                .input_devices()
+                .wrap_err("unable to list input devices")?
+                .collect(),
+            Direction::Output => host
+                .output_devices()
+                .wrap_err("unable to list output devices")?
+                .collect(),
+        })
+    }
+
+    fn default_device(self, host: &Host) -> Option<Device> {
+        match self {
+            Direction::Input => host.default_input_device(),
+            Direction::Output => host.default_output_device(),
+        }
+    }
+
+    fn configs(self, device: &Device) -> color_eyre::Result<Vec<SupportedStreamConfigRange>> {
+        Ok(match self {
+            Direction::Input => device
+                .supported_input_configs()
+                .wrap_err("unable to list input configs")?
+                .collect(),
+            Direction::Output => device
+                .supported_output_configs()
+                .wrap_err("unable to list output configs")?
+                .collect(),
+        })
+    }
+
+    fn default_config(self, device: &Device) -> color_eyre::Result<SupportedStreamConfig> {
+        match self {
+            Direction::Input => device
+                .default_input_config()
+                .wrap_err("no default input config"),
+            Direction::Output => device
+                .default_output_config()
+                .wrap_err("no default output config"),
+        }
+    }
+}
+
+/// List the devices of every available host
+///
+/// Hosts and devices that fail to be queried are skipped.
+pub(crate) fn devices(direction: Direction) -> Vec<DeviceInfo> {
+    let mut devices = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(err) => {
+                warn!(%err, host = host_id.name(), "unable to initialize audio host");
+
+                continue;
+            }
+        };
+
+        let default_device = direction
+            .default_device(&host)
+            .and_then(|device| device.name().ok());
+
+        let host_devices = match direction.devices(&host) {
+            Ok(host_devices) => host_devices,
+            Err(err) => {
+                warn!(%err, host = host_id.name(), "unable to list devices");
+
+                continue;
+            }
+        };
+
+        for device in host_devices {
+            let name = match device.name() {
+                Ok(name) => name,
+                Err(err) => {
+                    warn!(%err, host = host_id.name(), "unable to get device name");
+
+                    continue;
+                }
+            };
+
+            let configs = match direction.configs(&device) {
+                Ok(configs) => configs.into_iter().map(ConfigRange::from).collect(),
+                Err(err) => {
+                    warn!(%err, device = %name, "unable to list device configs");
+
+                    Vec::new()
+                }
+            };
+
+            devices.push(DeviceInfo {
+                host: host_id.name().to_string(),
+                is_default: default_device.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+    }
+
+    devices
+}
+
+/// Find the device and stream configuration described by the settings
+pub(crate) fn open(
+    settings: &DeviceSettings,
+    direction: Direction,
+) -> color_eyre::Result<(Device, StreamConfig)> {
+    let host = match &settings.host {
+        Some(name) => {
+            let host_id = cpal::available_hosts()
+                .into_iter()
+                .find(|host_id| host_id.name() == name)
+                .wrap_err_with(|| format!("audio host {name} is not available"))?;
+
+            cpal::host_from_id(host_id)
+                .wrap_err_with(|| format!("unable to initialize audio host {name}"))?
+        }
+        None => cpal::default_host(),
+    };
+
+    let device = match &settings.device {
+        Some(name) => direction
+            .devices(&host)?
+            .into_iter()
+            .find(|device| device.name().is_ok_and(|device| &device == name))
+            .wrap_err_with(|| format!("no {} device named {name}", direction.name()))?,
+        None => direction
+            .default_device(&host)
+            .wrap_err_with(|| format!("no default {} device", direction.name()))?,
+    };
+
+    let default_config = direction.default_config(&device)?;
+
+    let mut config: StreamConfig = match settings.sample_rate {
+        Some(sample_rate) if sample_rate != default_config.sample_rate().0 => direction
+            .configs(&device)?
+            .into_iter()
+            .filter(|range| {
+                (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&sample_rate)
+            })
+            // Stay as close to the default config as possible
+            .max_by_key(|range| {
+                (
+                    range.sample_format() == SampleFormat::F32,
+                    range.channels() == default_config.channels(),
+                )
+            })
+            .wrap_err_with(|| format!("the device does not support {sample_rate} Hz"))?
+            .with_sample_rate(SampleRate(sample_rate))
+            .into(),
+        _ => default_config.into(),
+    };
+
+    if let Some(buffer_size) = settings.buffer_size {
+        config.buffer_size = BufferSize::Fixed(buffer_size);
+    }
+
+    debug!(?direction, ?config, "Opening stream");
+
+    Ok((device, config))
+}
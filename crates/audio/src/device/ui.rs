@@ -0,0 +1,92 @@
+//! Menus to choose the output device and its configuration, for apps built with egui
+
+use egui::Ui;
+
+use super::{DeviceInfo, DeviceSettings};
+
+/// The storage key the output settings are persisted under
+pub const OUTPUT_SETTINGS_KEY: &str = "output_settings";
+
+const SAMPLE_RATES: [u32; 6] = [22_050, 44_100, 48_000, 88_200, 96_000, 192_000];
+const BUFFER_SIZES: [u32; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
+/// Show the audio output settings menus, returning if any setting was changed
+pub fn output_settings_ui(
+    ui: &mut Ui,
+    settings: &mut DeviceSettings,
+    devices: &[DeviceInfo],
+) -> bool {
+    let mut new_settings = settings.clone();
+
+    ui.menu_button("Output Device", |ui| {
+        if ui
+            .selectable_label(new_settings.device.is_none(), "System Default")
+            .clicked()
+        {
+            new_settings.host = None;
+            new_settings.device = None;
+        }
+
+        ui.separator();
+
+        for device in devices {
+            let selected = new_settings.host.as_ref() == Some(&device.host)
+                && new_settings.device.as_ref() == Some(&device.name);
+
+            if ui
+                .selectable_label(selected, format!("{} ({})", device.name, device.host))
+                .clicked()
+            {
+                new_settings.host = Some(device.host.clone());
+                new_settings.device = Some(device.name.clone());
+            }
+        }
+    });
+
+    // Only offer the options that the selected device supports
+    let device = devices
+        .iter()
+        .find(|device| match (&settings.host, &settings.device) {
+            (Some(host), Some(name)) => &device.host == host && &device.name == name,
+            _ => device.is_default,
+        });
+
+    ui.menu_button("Sample Rate", |ui| {
+        ui.selectable_value(&mut new_settings.sample_rate, None, "Device Default");
+
+        for sample_rate in SAMPLE_RATES {
+            ui.add_enabled_ui(
+                device.is_none_or(|device| device.supports_sample_rate(sample_rate)),
+                |ui| {
+                    ui.selectable_value(
+                        &mut new_settings.sample_rate,
+                        Some(sample_rate),
+                        format!("{sample_rate} Hz"),
+                    )
+                },
+            );
+        }
+    });
+
+    ui.menu_button("Buffer Size", |ui| {
+        ui.selectable_value(&mut new_settings.buffer_size, None, "Device Default");
+
+        for buffer_size in BUFFER_SIZES {
+            ui.add_enabled_ui(
+                device.is_none_or(|device| device.supports_buffer_size(buffer_size)),
+                |ui| {
+                    ui.selectable_value(
+                        &mut new_settings.buffer_size,
+                        Some(buffer_size),
+                        format!("{buffer_size} samples"),
+                    )
+                },
+            );
+        }
+    });
+
+    let changed = new_settings != *settings;
+    *settings = new_settings;
+
+    changed
+}
@@ -1,29 +1,238 @@
-use color_eyre::eyre::{Context, ContextCompat};
+//! Recording from input devices
+//!
+//! An [`InputStream`] either collects the recorded samples in a lock-free ring buffer that can
+//! be read from any thread, or hands every chunk to a callback on the audio thread.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{self, Context};
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    SampleRate, StreamConfig, StreamError,
+    traits::{DeviceTrait, StreamTrait},
+    Device, Stream, StreamConfig, StreamError,
 };
-use tracing::error;
+use tracing::{error, warn};
+
+use crate::{
+    decode::ChannelSelection,
+    device::{self, DeviceInfo, DeviceSettings, Direction},
+    waveform::Waveform,
+};
+
+use self::ring::{ring_buffer, Consumer};
+
+mod ring;
+
+/// How often [`InputStream::record_for`] checks for new samples
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A running recording from an input device
+///
+/// Samples are interleaved and delivered as recorded, without any gain applied.
+pub struct InputStream {
+    /// `None` if the samples are delivered to a callback instead
+    consumer: Option<Consumer>,
+    sample_rate: u32,
+    channels: u16,
+
+    _input_stream: Stream,
+}
+
+impl std::fmt::Debug for InputStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InputStream")
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .finish()
+    }
+}
+
+impl InputStream {
+    /// Record from the default input device, see [`Self::with_device`]
+    pub fn new(channels: ChannelSelection, buffer: Duration) -> color_eyre::Result<Self> {
+        Self::with_device(&DeviceSettings::default(), channels, buffer)
+    }
+
+    /// List the input devices of every available host
+    ///
+    /// Hosts and devices that fail to be queried are skipped.
+    pub fn devices() -> Vec<DeviceInfo> {
+        device::devices(Direction::Input)
+    }
+
+    /// Record into a ring buffer that holds `buffer` worth of audio
+    ///
+    /// Frames that are recorded while the buffer is full are dropped, so it must be read
+    /// from more often than once per `buffer`.
+    pub fn with_device(
+        settings: &DeviceSettings,
+        channels: ChannelSelection,
+        buffer: Duration,
+    ) -> color_eyre::Result<Self> {
+        let (input_device, config, output_channels) = open(settings, channels)?;
+
+        let capacity = (buffer.as_secs_f64() * config.sample_rate.0 as f64).ceil() as usize
+            * output_channels as usize;
+        let (mut producer, consumer) = ring_buffer(capacity, output_channels);
+
+        let input_stream = build_stream(&input_device, &config, channels, move |samples| {
+            producer.push(samples)
+        })?;
+
+        Ok(Self {
+            consumer: Some(consumer),
+            sample_rate: config.sample_rate.0,
+            channels: output_channels,
+            _input_stream: input_stream,
+        })
+    }
+
+    /// Record by calling `callback` with every chunk of interleaved samples
+    ///
+    /// The callback runs on the audio thread, so it should not block.
+    pub fn with_callback(
+        settings: &DeviceSettings,
+        channels: ChannelSelection,
+        callback: impl FnMut(&[f32]) + Send + 'static,
+    ) -> color_eyre::Result<Self> {
+        let (input_device, config, output_channels) = open(settings, channels)?;
+
+        let input_stream = build_stream(&input_device, &config, channels, callback)?;
+
+        Ok(Self {
+            consumer: None,
+            sample_rate: config.sample_rate.0,
+            channels: output_channels,
+            _input_stream: input_stream,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The amount of channels after the [`ChannelSelection`] has been applied
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
 
-pub fn read_one_second() -> color_eyre::Result<(Vec<f32>, SampleRate)> {
-    let host = cpal::default_host();
+    /// Append every sample recorded since the last read to `output`, returning the amount of
+    /// frames read
+    ///
+    /// Streams created with [`Self::with_callback`] never have anything to read.
+    pub fn read(&mut self, output: &mut Vec<f32>) -> usize {
+        match &mut self.consumer {
+            Some(consumer) => consumer.pop(output) / self.channels as usize,
+            None => 0,
+        }
+    }
 
-    let input_device = host
-        .default_input_device()
-        .wrap_err("failed to get the default input device")?;
+    /// Everything recorded since the last read
+    pub fn read_waveform(&mut self) -> Waveform<'static> {
+        let mut samples = Vec::new();
+        self.read(&mut samples);
 
-    let config: StreamConfig = input_device
-        .default_input_config()
-        .wrap_err("failed to get default input config")?
-        .into();
+        Waveform::new_interleaved(samples, self.channels, self.sample_rate)
+    }
 
-    let (send, recv) = std::sync::mpsc::channel();
+    /// Throw away everything recorded since the last read
+    pub fn clear(&mut self) {
+        if let Some(consumer) = &mut self.consumer {
+            consumer.clear();
+        }
+    }
+
+    /// The amount of frames that were lost because the ring buffer was full
+    pub fn dropped(&self) -> usize {
+        self.consumer.as_ref().map_or(0, Consumer::dropped)
+    }
+
+    /// Block while recording the next `duration` worth of audio
+    ///
+    /// If the device stops delivering samples, the recording is cut short.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stream was created with [`Self::with_callback`].
+    pub fn record_for(&mut self, duration: Duration) -> Waveform<'static> {
+        assert!(
+            self.consumer.is_some(),
+            "streams that deliver to a callback can not be recorded from"
+        );
+
+        let frames = (duration.as_secs_f64() * self.sample_rate as f64).round() as usize;
+        let deadline = Instant::now() + duration + Duration::from_secs(1);
+
+        self.clear();
+
+        let mut samples = Vec::with_capacity(frames * self.channels as usize);
+        while samples.len() < frames * self.channels as usize {
+            if Instant::now() > deadline {
+                warn!(
+                    recorded = samples.len() / self.channels as usize,
+                    frames, "input device stopped delivering samples"
+                );
+
+                break;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+            self.read(&mut samples);
+        }
+
+        samples.truncate(frames * self.channels as usize);
+
+        Waveform::new_interleaved(samples, self.channels, self.sample_rate)
+    }
+}
+
+/// Record every channel of the default input device for the given duration
+pub fn record_for(duration: Duration) -> color_eyre::Result<Waveform<'static>> {
+    Ok(InputStream::new(ChannelSelection::All, Duration::from_secs(1))?.record_for(duration))
+}
+
+/// Find the input device, returning the amount of channels left after the selection
+fn open(
+    settings: &DeviceSettings,
+    channels: ChannelSelection,
+) -> color_eyre::Result<(Device, StreamConfig, u16)> {
+    let (input_device, config) = device::open(settings, Direction::Input)?;
+
+    let output_channels = match channels {
+        ChannelSelection::All => config.channels,
+        ChannelSelection::Downmix => 1,
+        ChannelSelection::Single(channel) if channel < config.channels => 1,
+        ChannelSelection::Single(channel) => eyre::bail!(
+            "channel {channel} is out of range for an input device with {} channels",
+            config.channels
+        ),
+    };
+
+    Ok((input_device, config, output_channels))
+}
+
+fn build_stream(
+    input_device: &Device,
+    config: &StreamConfig,
+    channels: ChannelSelection,
+    mut deliver: impl FnMut(&[f32]) + Send + 'static,
+) -> color_eyre::Result<Stream> {
+    let input_channels = config.channels as usize;
+    let mut selected = Vec::new();
 
     let input_stream = input_device
         .build_input_stream(
-            &config,
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                send.send(data.to_vec()).ok();
+            config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| match channels {
+                ChannelSelection::All => deliver(data),
+                channels => {
+                    selected.clear();
+                    select_channels(channels, input_channels, data, &mut selected);
+
+                    deliver(&selected);
+                }
             },
             |err: StreamError| {
                 error!(%err, "an error occurred on the input stream");
@@ -31,18 +240,51 @@ pub fn read_one_second() -> color_eyre::Result<(Vec<f32>, SampleRate)> {
         )
         .wrap_err("failed to build input stream")?;
 
-    input_stream.play()?;
+    input_stream
+        .play()
+        .wrap_err("failed to start the input stream")?;
+
+    Ok(input_stream)
+}
+
+/// Apply a channel selection to interleaved samples, appending the result to `output`
+fn select_channels(
+    channels: ChannelSelection,
+    input_channels: usize,
+    input: &[f32],
+    output: &mut Vec<f32>,
+) {
+    let frames = input.chunks_exact(input_channels);
+
+    match channels {
+        ChannelSelection::All => output.extend_from_slice(input),
+        ChannelSelection::Downmix => {
+            output.extend(frames.map(|frame| frame.iter().sum::<f32>() / input_channels as f32))
+        }
+        ChannelSelection::Single(channel) => {
+            output.extend(frames.map(|frame| frame[channel as usize]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::select_channels;
+    use crate::decode::ChannelSelection;
 
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    #[test]
+    fn channel_selection() {
+        let input = [0.5, -0.5, 1.0, 0.0];
 
-    drop(input_stream);
+        for (channels, expected) in [
+            (ChannelSelection::All, vec![0.5, -0.5, 1.0, 0.0]),
+            (ChannelSelection::Downmix, vec![0.0, 0.5]),
+            (ChannelSelection::Single(1), vec![-0.5, 0.0]),
+        ] {
+            let mut output = Vec::new();
+            select_channels(channels, 2, &input, &mut output);
 
-    Ok((
-        recv.iter()
-            .flatten()
-            .step_by(config.channels as usize)
-            .map(|x| x * 10.0)
-            .collect::<Vec<_>>(),
-        config.sample_rate,
-    ))
+            assert_eq!(output, expected, "{channels:?}");
+        }
+    }
 }
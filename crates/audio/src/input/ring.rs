@@ -0,0 +1,151 @@
+//! A single producer, single consumer ring buffer of samples that never blocks either side
+
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc,
+};
+
+struct Shared {
+    /// The bits of each `f32` sample
+    samples: Box<[AtomicU32]>,
+    /// The total amount of samples ever written, wrapping around
+    write: AtomicUsize,
+    /// The total amount of samples ever read, wrapping around
+    read: AtomicUsize,
+    /// The amount of frames that were thrown away because the buffer was full
+    dropped: AtomicUsize,
+}
+
+impl Shared {
+    fn slot(&self, index: usize) -> &AtomicU32 {
+        // The capacity is a power of two, so this stays correct when the counters wrap around
+        &self.samples[index & (self.samples.len() - 1)]
+    }
+}
+
+/// Create a ring buffer holding at least `capacity` samples, split into its two ends
+pub fn ring_buffer(capacity: usize, channels: u16) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity.max(1).next_power_of_two())
+            .map(|_| AtomicU32::new(0))
+            .collect(),
+        write: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        dropped: AtomicUsize::new(0),
+    });
+
+    (
+        Producer {
+            shared: shared.clone(),
+            channels: channels as usize,
+        },
+        Consumer { shared },
+    )
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+    channels: usize,
+}
+
+impl Producer {
+    /// Append as many whole frames as fit, dropping the rest
+    pub fn push(&mut self, samples: &[f32]) {
+        let shared = &self.shared;
+
+        let write = shared.write.load(Ordering::Relaxed);
+        let read = shared.read.load(Ordering::Acquire);
+
+        let free = shared.samples.len() - write.wrapping_sub(read);
+        let length = samples.len().min(free - free % self.channels);
+
+        for (offset, sample) in samples[..length].iter().enumerate() {
+            shared
+                .slot(write.wrapping_add(offset))
+                .store(sample.to_bits(), Ordering::Relaxed);
+        }
+
+        shared
+            .write
+            .store(write.wrapping_add(length), Ordering::Release);
+
+        if length < samples.len() {
+            shared
+                .dropped
+                .fetch_add((samples.len() - length) / self.channels, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    /// Move every available sample into `output`, returning how many were moved
+    pub fn pop(&mut self, output: &mut Vec<f32>) -> usize {
+        let shared = &self.shared;
+
+        let read = shared.read.load(Ordering::Relaxed);
+        let write = shared.write.load(Ordering::Acquire);
+        let length = write.wrapping_sub(read);
+
+        output.extend((0..length).map(|offset| {
+            f32::from_bits(
+                shared
+                    .slot(read.wrapping_add(offset))
+                    .load(Ordering::Relaxed),
+            )
+        }));
+
+        shared.read.store(write, Ordering::Release);
+
+        length
+    }
+
+    /// Throw away every available sample
+    pub fn clear(&mut self) {
+        let write = self.shared.write.load(Ordering::Acquire);
+
+        self.shared.read.store(write, Ordering::Release);
+    }
+
+    /// The amount of frames that did not fit into the buffer
+    pub fn dropped(&self) -> usize {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ring_buffer;
+
+    #[test]
+    fn push_pop() {
+        let (mut producer, mut consumer) = ring_buffer(8, 2);
+        let mut output = Vec::new();
+
+        producer.push(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(consumer.pop(&mut output), 4);
+        assert_eq!(output, [1.0, 2.0, 3.0, 4.0]);
+
+        // Wrap around the end of the buffer
+        producer.push(&[5.0, 6.0, 7.0, 8.0, 9.0, 10.0]);
+        assert_eq!(consumer.pop(&mut output), 6);
+        assert_eq!(output, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0]);
+        assert_eq!(consumer.dropped(), 0);
+    }
+
+    #[test]
+    fn drops_whole_frames() {
+        let (mut producer, mut consumer) = ring_buffer(4, 2);
+        let mut output = Vec::new();
+
+        producer.push(&[1.0, 2.0]);
+        producer.push(&[3.0, 4.0, 5.0, 6.0]);
+
+        assert_eq!(consumer.pop(&mut output), 4);
+        assert_eq!(output, [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(consumer.dropped(), 1);
+    }
+}
@@ -7,11 +7,17 @@
 )]
 
 #[cfg(feature = "io")]
-pub mod input;
+pub mod decode;
 
 #[cfg(feature = "io")]
-pub mod output;
+pub mod device;
+
+#[cfg(feature = "io")]
+pub mod input;
 
+pub mod output;
+pub mod resample;
+pub mod wav;
 pub mod waveform;
 
 #[cfg(feature = "cpal")]
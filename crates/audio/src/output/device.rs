@@ -0,0 +1,102 @@
+//! Playback through an output device using cpal
+
+use std::fmt::{self, Debug};
+
+use color_eyre::eyre::Context;
+use cpal::{
+    traits::{DeviceTrait, StreamTrait},
+    Stream,
+};
+use tracing::error;
+
+use super::{
+    queue::{PlaybackHandle, Queue},
+    AudioSinkProgress, Sink,
+};
+use crate::{
+    device::{self, DeviceInfo, DeviceSettings, Direction},
+    waveform::Waveform,
+};
+
+/// A [`Sink`] that plays through an output device
+pub struct AudioSink {
+    queue: Queue,
+
+    // Field (drop) ordering here is very important, the queue must be dropped
+    // before the stream can be dropped to prevent deadlocking
+    _output_stream: Stream,
+}
+
+impl Debug for AudioSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioSink").finish()
+    }
+}
+
+impl AudioSink {
+    /// Open the default output device with its default configuration
+    pub fn new() -> color_eyre::Result<Self> {
+        Self::with_device(&DeviceSettings::default())
+    }
+
+    /// List the output devices of every available host
+    ///
+    /// Hosts and devices that fail to be queried are skipped.
+    pub fn devices() -> Vec<DeviceInfo> {
+        device::devices(Direction::Output)
+    }
+
+    /// Open a specific output device, see [`Self::devices`] for the available ones
+    pub fn with_device(settings: &DeviceSettings) -> color_eyre::Result<Self> {
+        let (output_device, config) = device::open(settings, Direction::Output)?;
+
+        let (queue, mut player) = Queue::new(config.sample_rate.0, config.channels);
+
+        let output_stream = output_device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _info| {
+                    player.render(data);
+                },
+                |err| {
+                    error!(%err, "an error occurred on the output stream");
+                },
+            )
+            .wrap_err("failed to build output stream")?;
+
+        output_stream
+            .play()
+            .wrap_err("failed to start the output stream")?;
+
+        Ok(Self {
+            queue,
+            _output_stream: output_stream,
+        })
+    }
+}
+
+impl Sink for AudioSink {
+    fn sample_rate(&self) -> u32 {
+        self.queue.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.queue.channels()
+    }
+
+    fn queue_length(&self) -> usize {
+        self.queue.queue_length()
+    }
+
+    fn clear(&self) {
+        self.queue.clear();
+    }
+
+    fn queue(
+        &self,
+        waveform: &Waveform<'_>,
+        callback: impl Fn(AudioSinkProgress) + Send + 'static,
+    ) -> PlaybackHandle {
+        self.queue.queue(waveform, callback)
+    }
+}
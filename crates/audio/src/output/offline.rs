@@ -0,0 +1,240 @@
+//! Playback on a virtual clock, for rendering and testing without an output device
+
+use std::fmt::{self, Debug};
+
+use super::{
+    queue::{PlaybackHandle, Player, Queue},
+    AudioSinkProgress, Sink,
+};
+use crate::waveform::Waveform;
+
+/// A [`Sink`] that records everything it plays into a waveform
+///
+/// Time only passes when the sink is advanced, one buffer at a time, in the same way that an
+/// output device would request samples. This makes playback fully deterministic.
+pub struct OfflineSink {
+    queue: Queue,
+    player: Player,
+
+    buffer: Vec<f32>,
+    recording: Vec<f32>,
+}
+
+impl Debug for OfflineSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OfflineSink")
+            .field("sample_rate", &self.queue.sample_rate())
+            .field("channels", &self.queue.channels())
+            .field(
+                "recorded",
+                &(self.recording.len() / self.queue.channels() as usize),
+            )
+            .finish()
+    }
+}
+
+impl OfflineSink {
+    /// Create a sink whose output is requested `buffer_size` frames at a time
+    pub fn new(sample_rate: u32, channels: u16, buffer_size: usize) -> Self {
+        assert_ne!(channels, 0, "there must be at least one channel");
+        assert_ne!(buffer_size, 0, "buffers must hold at least one frame");
+
+        let (queue, player) = Queue::new(sample_rate, channels);
+
+        Self {
+            queue,
+            player,
+            buffer: vec![0.0; buffer_size * channels as usize],
+            recording: Vec::new(),
+        }
+    }
+
+    /// Request the given amount of buffers, as an output device would
+    pub fn advance(&mut self, buffers: usize) {
+        for _ in 0..buffers {
+            self.render_buffer();
+        }
+    }
+
+    /// Advance until every queued waveform has finished or the playing one is paused
+    pub fn run(&mut self) {
+        // The buffer after the last samples of a waveform lets it report that it has finished
+        while self.playing() {
+            if !self.render_buffer() {
+                break;
+            }
+        }
+    }
+
+    /// The amount of frames that have been played so far
+    pub fn elapsed(&self) -> usize {
+        self.recording.len() / self.queue.channels() as usize
+    }
+
+    /// Everything that has been played so far, including silence
+    pub fn recording(&self) -> Waveform<'static> {
+        Waveform::new_interleaved(
+            self.recording.clone(),
+            self.queue.channels(),
+            self.queue.sample_rate(),
+        )
+    }
+
+    /// Take the recording out of the sink, starting a new one
+    pub fn take_recording(&mut self) -> Waveform<'static> {
+        Waveform::new_interleaved(
+            std::mem::take(&mut self.recording),
+            self.queue.channels(),
+            self.queue.sample_rate(),
+        )
+    }
+
+    fn render_buffer(&mut self) -> bool {
+        let playing = self.player.render(&mut self.buffer);
+        self.recording.extend_from_slice(&self.buffer);
+
+        playing
+    }
+}
+
+impl Sink for OfflineSink {
+    fn sample_rate(&self) -> u32 {
+        self.queue.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.queue.channels()
+    }
+
+    fn queue_length(&self) -> usize {
+        self.queue.queue_length()
+    }
+
+    fn clear(&self) {
+        self.queue.clear();
+    }
+
+    fn queue(
+        &self,
+        waveform: &Waveform<'_>,
+        callback: impl Fn(AudioSinkProgress) + Send + 'static,
+    ) -> PlaybackHandle {
+        self.queue.queue(waveform, callback)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::OfflineSink;
+    use crate::{
+        output::{AudioSinkProgress, Sink},
+        waveform::Waveform,
+    };
+
+    #[test]
+    fn queue_order() {
+        let mut sink = OfflineSink::new(100, 1, 16);
+
+        sink.queue(&Waveform::new(vec![0.25; 40], 100), |_| {});
+        sink.queue(&Waveform::new(vec![0.5; 10], 100), |_| {});
+        assert_eq!(sink.queue_length(), 2);
+
+        sink.run();
+        assert!(!sink.playing());
+
+        let recording = sink.recording();
+        let expected = [[0.25; 40].as_slice(), &[0.5; 10]].concat();
+
+        assert_eq!(&recording.samples()[..50], expected.as_slice());
+        assert!(recording.samples()[50..]
+            .iter()
+            .all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn progress_callbacks() {
+        let mut sink = OfflineSink::new(100, 1, 16);
+        let (sender, receiver) = mpsc::channel();
+
+        let handle = sink.queue(&Waveform::new(vec![1.0; 40], 100), move |progress| {
+            sender.send(progress).ok();
+        });
+
+        sink.run();
+        assert!(handle.is_finished());
+        assert_eq!(handle.position(), 40);
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [
+                AudioSinkProgress::Samples(16),
+                AudioSinkProgress::Samples(32),
+                AudioSinkProgress::Samples(40),
+                AudioSinkProgress::Finished,
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_fan_out() {
+        let mut sink = OfflineSink::new(100, 3, 16);
+        let waveform = Waveform::new_interleaved(vec![0.1, 0.2, 0.3, 0.4], 2, 100);
+
+        sink.queue(&waveform, |_| {});
+        sink.run();
+
+        let recording = sink.recording();
+        assert_eq!(recording.channels(), 3);
+        assert_eq!(&recording.samples()[..6], &[0.1, 0.2, 0.1, 0.3, 0.4, 0.3]);
+    }
+
+    #[test]
+    fn resampling() {
+        let mut sink = OfflineSink::new(48_000, 1, 512);
+        let waveform = Waveform::sine_wave(1_000.0, 0.1, 44_100);
+
+        sink.queue(&waveform, |_| {});
+        sink.run();
+
+        // Resampling while playing matches resampling the whole waveform at once
+        let expected = waveform.resample(48_000);
+        let recording = sink.recording();
+
+        assert_eq!(&recording.samples()[..expected.len()], expected.samples());
+    }
+
+    #[test]
+    fn transport() {
+        let mut sink = OfflineSink::new(100, 1, 10);
+        let samples = (0..100).map(|n| n as f32).collect::<Vec<_>>();
+
+        let handle = sink.queue(&Waveform::new(samples, 100), |_| {});
+        sink.queue(&Waveform::new(vec![-1.0; 10], 100), |_| {});
+
+        sink.advance(1);
+        handle.pause();
+        sink.advance(1);
+        handle.resume();
+        handle.seek(50);
+        handle.set_gain(2.0);
+        sink.advance(1);
+        sink.clear();
+        sink.run();
+
+        let recording = sink.take_recording();
+        let expected = (0..10)
+            .map(|n| n as f32)
+            .chain([0.0; 10])
+            .chain((50..60).map(|n| n as f32 * 2.0))
+            .collect::<Vec<_>>();
+
+        assert!(handle.is_finished());
+        assert!(!sink.playing());
+        assert_eq!(&recording.samples()[..30], expected.as_slice());
+        assert!(recording.samples()[30..]
+            .iter()
+            .all(|&sample| sample == 0.0));
+    }
+}
@@ -0,0 +1,429 @@
+//! The queue of waveforms shared by every [`Sink`](super::Sink) implementation
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    mpsc::{self, Receiver, SendError, Sender, TryRecvError},
+    Arc, Once,
+};
+
+use tracing::{debug, trace, warn};
+
+use super::AudioSinkProgress;
+use crate::{
+    resample::{ResampleQuality, Resampler},
+    waveform::Waveform,
+};
+
+type AudioSinkCallback = Box<dyn Fn(AudioSinkProgress) + Send>;
+
+/// How many frames of a waveform are resampled at a time while it is playing
+const CHUNK_FRAMES: usize = 1024;
+
+/// Marks that no seek has been requested
+const NO_SEEK: usize = usize::MAX;
+
+/// State shared between a [`PlaybackHandle`] and the output stream
+#[derive(Debug)]
+struct PlaybackControl {
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    finished: AtomicBool,
+    /// The sample to jump to, or [`NO_SEEK`]
+    seek: AtomicUsize,
+    /// The bits of an `f32` multiplier
+    gain: AtomicU32,
+    /// The sample of the waveform that was last sent to the output device
+    position: AtomicUsize,
+}
+
+/// Controls a single waveform queued on a [`Sink`](super::Sink)
+///
+/// Dropping the handle does not stop the playback.
+#[derive(Debug, Clone)]
+pub struct PlaybackHandle {
+    control: Arc<PlaybackControl>,
+    len: usize,
+    sample_rate: u32,
+}
+
+impl PlaybackHandle {
+    /// Stop sending samples to the output device, keeping the current position
+    pub fn pause(&self) {
+        self.control.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Remove the waveform from the sink, letting the next queued waveform play
+    pub fn stop(&self) {
+        self.control.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Continue playing from the given sample, clamped to the length of the waveform
+    pub fn seek(&self, sample: usize) {
+        self.control
+            .seek
+            .store(sample.min(self.len), Ordering::SeqCst);
+    }
+
+    /// Set the linear multiplier applied to every sample
+    pub fn set_gain(&self, gain: f32) {
+        self.control.gain.store(gain.to_bits(), Ordering::SeqCst);
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.control.gain.load(Ordering::SeqCst))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.paused.load(Ordering::SeqCst)
+    }
+
+    /// If the sink is done with this waveform, either by finishing it or by it being stopped
+    pub fn is_finished(&self) -> bool {
+        self.control.finished.load(Ordering::SeqCst)
+    }
+
+    /// The sample of the waveform that is currently being played
+    pub fn position(&self) -> usize {
+        self.control.position.load(Ordering::SeqCst)
+    }
+
+    /// The length of the queued waveform in samples
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The sample rate of the queued waveform, which [`Self::position`] is measured in
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// A queued waveform, resampled to the stream's sample rate as it is being played
+struct Playback {
+    /// Already remixed to the stream's channel layout
+    waveform: Waveform<'static>,
+    /// The value of [`Queue::generation`] when this waveform was queued
+    generation: usize,
+    /// The amount of frames of the waveform that have been resampled so far
+    position: usize,
+    /// The frame that playback started from, after the last seek
+    start: usize,
+    /// Frames sent to the output device since `start`, at the stream's sample rate
+    played: u64,
+    sample_rate: u32,
+    /// `None` if the waveform already has the stream's sample rate
+    resampler: Option<Resampler>,
+    /// Interleaved samples at the stream's sample rate waiting to be played
+    pending: Vec<f32>,
+    control: Arc<PlaybackControl>,
+    callback: AudioSinkCallback,
+}
+
+impl Playback {
+    fn new(
+        waveform: Waveform<'static>,
+        generation: usize,
+        sample_rate: u32,
+        callback: AudioSinkCallback,
+    ) -> Self {
+        let resampler = (waveform.sample_rate() != sample_rate).then(|| {
+            Resampler::new(
+                waveform.sample_rate(),
+                sample_rate,
+                waveform.channels(),
+                ResampleQuality::Balanced,
+            )
+        });
+
+        Self {
+            waveform,
+            generation,
+            position: 0,
+            start: 0,
+            played: 0,
+            sample_rate,
+            resampler,
+            pending: Vec::new(),
+            control: Arc::new(PlaybackControl {
+                paused: AtomicBool::new(false),
+                stopped: AtomicBool::new(false),
+                finished: AtomicBool::new(false),
+                seek: AtomicUsize::new(NO_SEEK),
+                gain: AtomicU32::new(1.0f32.to_bits()),
+                position: AtomicUsize::new(0),
+            }),
+            callback,
+        }
+    }
+
+    fn handle(&self) -> PlaybackHandle {
+        PlaybackHandle {
+            control: self.control.clone(),
+            len: self.waveform.len(),
+            sample_rate: self.waveform.sample_rate(),
+        }
+    }
+
+    fn finished(&self, generation: usize) -> bool {
+        self.control.stopped.load(Ordering::SeqCst)
+            || self.generation != generation
+            || (self.position == self.waveform.len() && self.pending.is_empty())
+    }
+
+    /// Mark the playback as done and let its owner know
+    fn finish(&self) {
+        self.control.finished.store(true, Ordering::SeqCst);
+
+        (self.callback)(AudioSinkProgress::Finished);
+    }
+
+    /// The sample of the waveform that the output device has last been sent
+    fn played_sample(&self) -> usize {
+        let played = self.played * self.waveform.sample_rate() as u64 / self.sample_rate as u64;
+
+        (self.start + played as usize).min(self.waveform.len())
+    }
+
+    /// Copy as many samples as are available into `data`, returning how many were written
+    fn fill(&mut self, data: &mut [f32]) -> usize {
+        let channels = self.waveform.channels() as usize;
+
+        let seek = self.control.seek.swap(NO_SEEK, Ordering::SeqCst);
+        if seek != NO_SEEK {
+            self.position = seek;
+            self.start = seek;
+            self.played = 0;
+            self.pending.clear();
+
+            if let Some(resampler) = &mut self.resampler {
+                resampler.reset();
+            }
+        }
+
+        if self.control.paused.load(Ordering::SeqCst) {
+            return 0;
+        }
+
+        while self.pending.len() < data.len() && self.position < self.waveform.len() {
+            let end = (self.position + CHUNK_FRAMES).min(self.waveform.len());
+            let chunk = &self.waveform.samples()[self.position * channels..end * channels];
+
+            match &mut self.resampler {
+                Some(resampler) => {
+                    resampler.process(chunk, &mut self.pending);
+
+                    if end == self.waveform.len() {
+                        resampler.flush(&mut self.pending);
+                    }
+                }
+                None => self.pending.extend_from_slice(chunk),
+            }
+
+            self.position = end;
+        }
+
+        let length = data.len().min(self.pending.len());
+        let gain = f32::from_bits(self.control.gain.load(Ordering::SeqCst));
+
+        for (output, sample) in data.iter_mut().zip(self.pending.drain(..length)) {
+            *output = sample * gain;
+        }
+
+        self.played += (length / channels) as u64;
+        self.control
+            .position
+            .store(self.played_sample(), Ordering::SeqCst);
+
+        length
+    }
+}
+
+/// The half of the queue that waveforms are added to
+pub(crate) struct Queue {
+    // FIXME: channels are broken on web assembly due to lack of condvar support.
+    // TODO: use a mutex instead
+    sender: Sender<Playback>,
+    sample_rate: u32,
+    channels: u16,
+
+    queue_length: Arc<AtomicUsize>,
+    /// Incremented by [`Self::clear`], dropping every waveform queued before it
+    generation: Arc<AtomicUsize>,
+}
+
+impl Queue {
+    /// Create a queue for a stream with the given layout, along with the player that plays it
+    pub fn new(sample_rate: u32, channels: u16) -> (Self, Player) {
+        let (sender, receiver) = mpsc::channel();
+
+        let queue_length = Arc::new(AtomicUsize::new(0));
+        let generation = Arc::new(AtomicUsize::new(0));
+
+        let player = Player {
+            receiver,
+            playback: None,
+            channels,
+            queue_length: queue_length.clone(),
+            generation: generation.clone(),
+        };
+
+        let queue = Self {
+            sender,
+            sample_rate,
+            channels,
+            queue_length,
+            generation,
+        };
+
+        (queue, player)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn queue_length(&self) -> usize {
+        self.queue_length.load(Ordering::SeqCst)
+    }
+
+    pub fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn queue(
+        &self,
+        waveform: &Waveform<'_>,
+        callback: impl Fn(AudioSinkProgress) + Send + 'static,
+    ) -> PlaybackHandle {
+        // Remixing is cheap enough to do up front, resampling happens while playing
+        let playback = Playback::new(
+            waveform.remix(self.channels),
+            self.generation.load(Ordering::SeqCst),
+            self.sample_rate,
+            Box::new(callback),
+        );
+        let handle = playback.handle();
+
+        match self.sender.send(playback) {
+            Ok(()) => {
+                self.queue_length.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(SendError(playback)) => {
+                warn!("the output stream has closed, unable to queue waveform");
+
+                playback.finish();
+            }
+        }
+
+        handle
+    }
+}
+
+/// The half of the queue that fills the output buffers
+pub(crate) struct Player {
+    receiver: Receiver<Playback>,
+    playback: Option<Playback>,
+    channels: u16,
+
+    queue_length: Arc<AtomicUsize>,
+    generation: Arc<AtomicUsize>,
+}
+
+impl Player {
+    /// Fill an interleaved output buffer with the next samples of the queue
+    ///
+    /// When a waveform ends partway through the buffer, the next queued waveform continues
+    /// right after it. Returns if a waveform was playing, as opposed to the queue being empty
+    /// or paused.
+    pub fn render(&mut self, data: &mut [f32]) -> bool {
+        let mut written = 0;
+        let mut playing = false;
+
+        while self.next_playback() {
+            if let Some(playback) = &mut self.playback {
+                // The queued waveforms are already remixed to the stream's channel layout, so
+                // the interleaved samples can be copied straight into the buffer
+                written += playback.fill(&mut data[written..]);
+
+                // Run the callback
+                let sample = playback.played_sample();
+                (playback.callback)(AudioSinkProgress::Samples(sample));
+
+                if playback.control.paused.load(Ordering::SeqCst) {
+                    break;
+                }
+            }
+
+            playing = true;
+
+            if written == data.len() {
+                break;
+            }
+        }
+
+        data[written..].fill(0.0);
+
+        playing
+    }
+
+    /// Make sure that the current playback can be played, returning `false` if the queue is empty
+    fn next_playback(&mut self) -> bool {
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        // Skip over finished, stopped and cleared waveforms
+        while self
+            .playback
+            .as_ref()
+            .is_none_or(|playback| playback.finished(generation))
+        {
+            if let Some(finished) = self.playback.take() {
+                self.queue_length
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queue_length| {
+                        Some(queue_length.saturating_sub(1))
+                    })
+                    .ok();
+                finished.finish();
+            }
+
+            match self.receiver.try_recv() {
+                Ok(new_playback) => {
+                    assert_eq!(new_playback.waveform.channels(), self.channels);
+
+                    trace!("Received {} new samples", new_playback.waveform.len());
+
+                    self.playback = Some(new_playback);
+                }
+                Err(e) => {
+                    match e {
+                        TryRecvError::Empty => std::hint::spin_loop(),
+                        TryRecvError::Disconnected => {
+                            static ONCE: Once = Once::new();
+
+                            ONCE.call_once(|| {
+                                debug!(
+                                    "Sample channel has hung up, looping until the stream closes"
+                                );
+                            });
+                        }
+                    }
+
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,405 @@
+//! Band-limited sample rate conversion
+//!
+//! Resampling is done with a windowed sinc filter evaluated from a polyphase
+//! table. The filter is scaled down when decreasing the sample rate so that
+//! everything above the new nyquist frequency is removed instead of aliasing.
+
+use std::f64::consts::PI;
+
+use crate::waveform::Waveform;
+
+/// Trade-off between the speed and the accuracy of a [`Resampler`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Short filter, suitable for previews and real time playback on slow machines
+    Fast,
+    /// Good default choice
+    Balanced,
+    /// Long filter with a narrow transition band, for offline processing
+    Best,
+}
+
+impl ResampleQuality {
+    /// The amount of sinc lobes on either side of the filter's center
+    fn zero_crossings(self) -> usize {
+        match self {
+            ResampleQuality::Fast => 8,
+            ResampleQuality::Balanced => 16,
+            ResampleQuality::Best => 64,
+        }
+    }
+
+    /// The beta parameter of the Kaiser window applied to the sinc
+    fn kaiser_beta(self) -> f64 {
+        match self {
+            ResampleQuality::Fast => 6.0,
+            ResampleQuality::Balanced => 8.6,
+            ResampleQuality::Best => 12.0,
+        }
+    }
+
+    /// The fraction of the nyquist frequency that is kept
+    fn rolloff(self) -> f64 {
+        match self {
+            ResampleQuality::Fast => 0.85,
+            ResampleQuality::Balanced => 0.9,
+            ResampleQuality::Best => 0.95,
+        }
+    }
+
+    /// The maximum amount of filter phases to precompute
+    fn max_phases(self) -> u64 {
+        match self {
+            ResampleQuality::Fast => 256,
+            ResampleQuality::Balanced => 1024,
+            ResampleQuality::Best => 4096,
+        }
+    }
+}
+
+/// A streaming sample rate converter for interleaved audio
+///
+/// Output frame `n` is taken at the input time `n * from_rate / to_rate`, so
+/// resampling `len` input frames always produces exactly
+/// `ceil(len * to_rate / from_rate)` output frames once [`Self::flush`] has been called.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    from_rate: u64,
+    to_rate: u64,
+    channels: usize,
+
+    /// Taps on either side of the center of the filter
+    half_taps: usize,
+    /// Filter coefficients for `phases + 1` evenly spaced fractional positions
+    table: Vec<f32>,
+    phases: u64,
+
+    /// Interleaved input frames, starting `half_taps` frames before absolute frame `dropped`
+    buffer: Vec<f32>,
+    dropped: u64,
+
+    input_frames: u64,
+    output_frames: u64,
+    flushed: bool,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32, channels: u16, quality: ResampleQuality) -> Self {
+        assert!(
+            from_rate > 0 && to_rate > 0,
+            "sample rates must be non zero"
+        );
+        assert_ne!(channels, 0, "there must be at least one channel");
+
+        // Reduce the ratio so that the phase of each output frame repeats as often as possible
+        let divisor = gcd(from_rate as u64, to_rate as u64);
+        let (from_rate, to_rate) = (from_rate as u64 / divisor, to_rate as u64 / divisor);
+
+        // Lower the cutoff to the new nyquist frequency when downsampling, widening the
+        // filter to match so that it keeps the same amount of lobes
+        let scale = (to_rate as f64 / from_rate as f64).min(1.0);
+        let cutoff = scale * quality.rolloff();
+        let half_taps = (quality.zero_crossings() as f64 / scale).ceil() as usize;
+
+        // Use one phase per possible fractional position if there are few enough of them,
+        // otherwise interpolate between the closest precomputed phases
+        let phases = to_rate.min(quality.max_phases());
+
+        let beta = quality.kaiser_beta();
+        let mut table = Vec::with_capacity((phases as usize + 1) * half_taps * 2);
+        for phase in 0..=phases {
+            let fraction = phase as f64 / phases as f64;
+
+            let row = (0..half_taps * 2)
+                .map(|tap| {
+                    // Distance of this tap from the fractional position
+                    let t = tap as f64 - (half_taps as f64 - 1.0) - fraction;
+
+                    cutoff * sinc(cutoff * t) * kaiser(t / half_taps as f64, beta)
+                })
+                .collect::<Vec<_>>();
+
+            // Normalize each phase to unity gain at DC
+            let sum = row.iter().sum::<f64>();
+            table.extend(
+                row.into_iter()
+                    .map(|coefficient| (coefficient / sum) as f32),
+            );
+        }
+
+        let mut resampler = Self {
+            from_rate,
+            to_rate,
+            channels: channels as usize,
+            half_taps,
+            table,
+            phases,
+            buffer: Vec::new(),
+            dropped: 0,
+            input_frames: 0,
+            output_frames: 0,
+            flushed: false,
+        };
+        resampler.reset();
+
+        resampler
+    }
+
+    /// Forget all input, preparing the resampler for a new stream
+    pub fn reset(&mut self) {
+        // Everything before the start of the stream is silence
+        self.buffer.clear();
+        self.buffer.resize(self.half_taps * self.channels, 0.0);
+
+        self.dropped = 0;
+        self.input_frames = 0;
+        self.output_frames = 0;
+        self.flushed = false;
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    /// The amount of output frames that `input_frames` input frames will resample to
+    pub fn output_len(&self, input_frames: u64) -> u64 {
+        (input_frames * self.to_rate).div_ceil(self.from_rate)
+    }
+
+    /// The amount of input frames that have been passed to [`Self::process`]
+    pub fn input_frames(&self) -> u64 {
+        self.input_frames
+    }
+
+    /// The amount of output frames that have been produced
+    pub fn output_frames(&self) -> u64 {
+        self.output_frames
+    }
+
+    /// Resample a chunk of interleaved input, appending the frames that are ready to `output`
+    ///
+    /// Output lags behind the input by the length of the filter, call [`Self::flush`]
+    /// at the end of the stream to get the remaining frames.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        assert!(!self.flushed, "resampler must be reset after being flushed");
+        assert_eq!(
+            input.len() % self.channels,
+            0,
+            "input must contain whole frames"
+        );
+
+        self.buffer.extend_from_slice(input);
+        self.input_frames += (input.len() / self.channels) as u64;
+
+        self.run(self.output_len(self.input_frames), output);
+    }
+
+    /// Finish the stream, appending all remaining output frames to `output`
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        if self.flushed {
+            return;
+        }
+
+        // Everything after the end of the stream is silence
+        self.buffer
+            .resize(self.buffer.len() + self.half_taps * self.channels, 0.0);
+        self.flushed = true;
+
+        self.run(self.output_len(self.input_frames), output);
+    }
+
+    fn run(&mut self, target_frames: u64, output: &mut Vec<f32>) {
+        let taps = self.half_taps * 2;
+        let buffered_frames = (self.buffer.len() / self.channels) as u64;
+
+        while self.output_frames < target_frames {
+            let position = self.output_frames * self.from_rate;
+            let frame = position / self.to_rate;
+
+            // The first buffered frame needed by the filter, relative to the buffer
+            let start = frame + 1 - self.dropped;
+            if start + taps as u64 > buffered_frames {
+                break;
+            }
+
+            // Find the two closest phases and how far between them this frame is
+            let phase = (position % self.to_rate) * self.phases;
+            let (row, weight) = (
+                (phase / self.to_rate) as usize,
+                (phase % self.to_rate) as f32 / self.to_rate as f32,
+            );
+            let before = &self.table[row * taps..][..taps];
+            let after = &self.table[(row + 1) * taps..][..taps];
+
+            let window = &self.buffer[start as usize * self.channels..][..taps * self.channels];
+            for channel in 0..self.channels {
+                let sample = window
+                    .iter()
+                    .skip(channel)
+                    .step_by(self.channels)
+                    .zip(before.iter().zip(after))
+                    .map(|(sample, (before, after))| sample * (before + (after - before) * weight))
+                    .sum();
+
+                output.push(sample);
+            }
+
+            self.output_frames += 1;
+        }
+
+        // Drop the frames that no future output frame will need
+        let next_frame = self.output_frames * self.from_rate / self.to_rate;
+        let unneeded = (next_frame + 1 - self.dropped).min(buffered_frames);
+        self.buffer.drain(..unneeded as usize * self.channels);
+        self.dropped += unneeded;
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let remainder = a % b;
+
+        a = b;
+        b = remainder;
+    }
+
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Kaiser window for `x` in the range `-1..=1`
+fn kaiser(x: f64, beta: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+
+    bessel_i0(beta * (1.0 - x * x).sqrt()) / bessel_i0(beta)
+}
+
+/// The zeroth order modified bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+
+    for k in 1.. {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+
+    sum
+}
+
+impl Waveform<'_> {
+    /// Resample the whole waveform at once, see [`Resampler`] for the length of the output
+    #[must_use = "Waveform::resample_with() does not modify the provided waveform"]
+    pub fn resample_with(
+        &self,
+        new_sample_rate: u32,
+        quality: ResampleQuality,
+    ) -> Waveform<'static> {
+        if new_sample_rate == self.sample_rate() {
+            return self.to_owned();
+        }
+
+        let mut resampler = Resampler::new(
+            self.sample_rate(),
+            new_sample_rate,
+            self.channels(),
+            quality,
+        );
+
+        let mut samples = Vec::with_capacity(
+            resampler.output_len(self.len() as u64) as usize * self.channels() as usize,
+        );
+        resampler.process(self.samples(), &mut samples);
+        resampler.flush(&mut samples);
+
+        Waveform::new_interleaved(samples, self.channels(), new_sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ResampleQuality, Resampler};
+    use crate::waveform::Waveform;
+
+    fn rms(samples: impl ExactSizeIterator<Item = f32>) -> f32 {
+        let len = samples.len() as f32;
+
+        (samples.map(|sample| sample * sample).sum::<f32>() / len).sqrt()
+    }
+
+    #[test]
+    fn output_length() {
+        for (from, to, len) in [
+            (44_100, 16_000, 1000),
+            (16_000, 44_100, 1000),
+            (44_100, 48_000, 1),
+            (48_000, 44_100, 0),
+            (8_000, 8_001, 12_345),
+        ] {
+            let waveform = Waveform::new(vec![0.0; len], from);
+            let expected = (len as u64 * to as u64).div_ceil(from as u64);
+
+            assert_eq!(
+                waveform.resample(to).len() as u64,
+                expected,
+                "{len} frames from {from} to {to}"
+            );
+        }
+    }
+
+    #[test]
+    fn streaming_matches_whole() {
+        let waveform = Waveform::from_planar(
+            vec![
+                Waveform::sine_wave(440.0, 0.1, 44_100).as_samples(),
+                Waveform::sine_wave(660.0, 0.1, 44_100).as_samples(),
+            ],
+            44_100,
+        );
+        let whole = waveform.resample_with(16_000, ResampleQuality::Fast);
+
+        let mut resampler = Resampler::new(44_100, 16_000, 2, ResampleQuality::Fast);
+        let mut streamed = Vec::new();
+        for chunk in waveform.samples().chunks(2 * 37) {
+            resampler.process(chunk, &mut streamed);
+        }
+        resampler.flush(&mut streamed);
+
+        assert_eq!(whole.samples(), streamed.as_slice());
+    }
+
+    #[test]
+    fn keeps_passband() {
+        let waveform = Waveform::sine_wave(1_000.0, 0.5, 44_100);
+        let resampled = waveform.resample(16_000);
+
+        // Skip the edges where the filter runs into silence
+        let middle = resampled.slice(1_000..resampled.len() - 1_000);
+
+        assert!((rms(middle.samples_iter()) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn removes_aliases() {
+        // 10kHz is above the nyquist frequency of the new sample rate
+        let waveform = Waveform::sine_wave(10_000.0, 0.5, 44_100);
+        let resampled = waveform.resample(16_000);
+
+        let middle = resampled.slice(1_000..resampled.len() - 1_000);
+
+        assert!(rms(middle.samples_iter()) < 1e-3);
+    }
+}
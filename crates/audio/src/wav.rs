@@ -0,0 +1,409 @@
+//! Reading and writing of RIFF WAVE files
+//!
+//! Only uncompressed PCM (8, 16, 24 and 32 bit integer) and 32 bit IEEE float
+//! data is supported, which covers everything the tools in this workspace produce.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::waveform::Waveform;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The sample encoding used when writing a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavFormat {
+    fn format_tag(self) -> u16 {
+        match self {
+            WavFormat::Int16 | WavFormat::Int24 => WAVE_FORMAT_PCM,
+            WavFormat::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+        }
+    }
+
+    fn bits_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Int16 => 16,
+            WavFormat::Int24 => 24,
+            WavFormat::Float32 => 32,
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        self.bits_per_sample() as usize / 8
+    }
+}
+
+#[derive(Debug)]
+pub enum WavError {
+    Io(io::Error),
+    /// The file does not start with a `RIFF` header of the `WAVE` type
+    NotWave,
+    /// A required chunk was not found in the file
+    MissingChunk(&'static str),
+    /// The file uses a format tag other than PCM or IEEE float
+    UnsupportedFormat(u16),
+    /// The bit depth is not supported for the format of the file
+    UnsupportedBitDepth(u16),
+    /// The file contents do not match what its header describes
+    Malformed(&'static str),
+}
+
+impl Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::Io(error) => write!(f, "io error: {error}"),
+            WavError::NotWave => write!(f, "not a RIFF WAVE file"),
+            WavError::MissingChunk(chunk) => write!(f, "missing {chunk:?} chunk"),
+            WavError::UnsupportedFormat(tag) => write!(f, "unsupported format tag {tag:#06x}"),
+            WavError::UnsupportedBitDepth(bits) => write!(f, "unsupported bit depth {bits}"),
+            WavError::Malformed(reason) => write!(f, "malformed file: {reason}"),
+        }
+    }
+}
+
+impl Error for WavError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WavError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WavError {
+    fn from(error: io::Error) -> Self {
+        WavError::Io(error)
+    }
+}
+
+impl Waveform<'_> {
+    /// Encode this waveform as a WAV file
+    pub fn write_wav(&self, mut writer: impl Write, format: WavFormat) -> io::Result<()> {
+        let channels = self.channels();
+        let block_align = channels as usize * format.bytes_per_sample();
+        let data_len = u32::try_from(self.len() * block_align)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "waveform is too long"))?;
+
+        // RIFF header, the size excludes the 8 bytes of the chunk header itself but includes the
+        // pad byte after odd sized data
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(4 + (8 + 16) + (8 + data_len + data_len % 2)).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&format.format_tag().to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&self.sample_rate().to_le_bytes())?;
+        writer.write_all(&(self.sample_rate() * block_align as u32).to_le_bytes())?;
+        writer.write_all(&(block_align as u16).to_le_bytes())?;
+        writer.write_all(&format.bits_per_sample().to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&data_len.to_le_bytes())?;
+
+        for sample in self.samples_iter() {
+            match format {
+                WavFormat::Int16 => {
+                    writer.write_all(&(int_from_f32(sample, 16) as i16).to_le_bytes())?
+                }
+                WavFormat::Int24 => {
+                    writer.write_all(&int_from_f32(sample, 24).to_le_bytes()[..3])?
+                }
+                WavFormat::Float32 => writer.write_all(&sample.to_le_bytes())?,
+            }
+        }
+
+        // The data chunk always has an even length for 16 and 32 bit samples, but 24 bit
+        // samples with an odd amount of channels needs a pad byte
+        if data_len % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+
+        writer.flush()
+    }
+
+    /// Write this waveform to a WAV file at the given path, replacing it if it exists
+    pub fn save_wav(&self, path: impl AsRef<Path>, format: WavFormat) -> io::Result<()> {
+        self.write_wav(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl Waveform<'static> {
+    /// Decode a WAV file into a waveform
+    pub fn read_wav(mut reader: impl Read) -> Result<Self, WavError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotWave);
+        }
+
+        let mut fmt = None;
+        let mut data = None;
+
+        // Walk the chunks, ignoring any that are not needed
+        let mut chunks = &bytes[12..];
+        while chunks.len() >= 8 {
+            let id = &chunks[0..4];
+            let len = u32_at(chunks, 4) as usize;
+            let body = chunks.get(8..8 + len).ok_or(WavError::Malformed(
+                "chunk extends past the end of the file",
+            ))?;
+
+            match id {
+                b"fmt " => fmt = Some(FormatChunk::parse(body)?),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+
+            // Chunks are padded to an even length
+            chunks = chunks.get(8 + len + len % 2..).unwrap_or_default();
+        }
+
+        let fmt = fmt.ok_or(WavError::MissingChunk("fmt "))?;
+        let data = data.ok_or(WavError::MissingChunk("data"))?;
+
+        if data.len() % fmt.block_align() != 0 {
+            return Err(WavError::Malformed("data is not a whole number of frames"));
+        }
+
+        let samples = match (fmt.format_tag, fmt.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => data
+                .iter()
+                .map(|&byte| (byte as f32 - 128.0) / 128.0)
+                .collect(),
+            (WAVE_FORMAT_PCM, 16) => data
+                .chunks_exact(2)
+                .map(|bytes| f32_from_int(i16::from_le_bytes([bytes[0], bytes[1]]).into(), 16))
+                .collect(),
+            (WAVE_FORMAT_PCM, 24) => data
+                .chunks_exact(3)
+                // Place the sample in the top of an i32 so the sign is extended by the shift
+                .map(|bytes| i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+                .map(|sample| f32_from_int(sample, 24))
+                .collect(),
+            (WAVE_FORMAT_PCM, 32) => data
+                .chunks_exact(4)
+                .map(|bytes| f32_from_int(i32_at(bytes, 0), 32))
+                .collect(),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_bits(u32_at(bytes, 0)))
+                .collect(),
+            (WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT, bits) => {
+                return Err(WavError::UnsupportedBitDepth(bits))
+            }
+            (tag, _) => return Err(WavError::UnsupportedFormat(tag)),
+        };
+
+        Ok(Waveform::new_interleaved(
+            samples,
+            fmt.channels,
+            fmt.sample_rate,
+        ))
+    }
+
+    /// Read a WAV file from the given path
+    pub fn open_wav(path: impl AsRef<Path>) -> Result<Self, WavError> {
+        Self::read_wav(BufReader::new(File::open(path)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FormatChunk {
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+}
+
+impl FormatChunk {
+    fn parse(body: &[u8]) -> Result<Self, WavError> {
+        if body.len() < 16 {
+            return Err(WavError::Malformed("fmt chunk is too short"));
+        }
+
+        let mut format_tag = u16_at(body, 0);
+
+        // The extensible format stores the real format tag at the start of its sub-format GUID
+        if format_tag == WAVE_FORMAT_EXTENSIBLE {
+            if body.len() < 26 {
+                return Err(WavError::Malformed("extensible fmt chunk is too short"));
+            }
+
+            format_tag = u16_at(body, 24);
+        }
+
+        let fmt = FormatChunk {
+            format_tag,
+            channels: u16_at(body, 2),
+            sample_rate: u32_at(body, 4),
+            bits_per_sample: u16_at(body, 14),
+        };
+
+        if fmt.channels == 0 {
+            return Err(WavError::Malformed("file has no channels"));
+        }
+
+        if !matches!(fmt.bits_per_sample, 8 | 16 | 24 | 32) {
+            return Err(WavError::UnsupportedBitDepth(fmt.bits_per_sample));
+        }
+
+        Ok(fmt)
+    }
+
+    fn block_align(&self) -> usize {
+        self.channels as usize * (self.bits_per_sample as usize / 8)
+    }
+}
+
+/// Convert a sample to a signed integer of the given bit depth, using the full
+/// range for both positive and negative values
+fn int_from_f32(sample: f32, bits: u32) -> i32 {
+    let sample = sample.clamp(-1.0, 1.0) as f64;
+    let half_range = (1u64 << (bits - 1)) as f64;
+
+    if sample < 0.0 {
+        (sample * half_range).round() as i32
+    } else {
+        (sample * (half_range - 1.0)).round() as i32
+    }
+}
+
+/// The inverse of [`int_from_f32`]
+fn f32_from_int(sample: i32, bits: u32) -> f32 {
+    let half_range = (1u64 << (bits - 1)) as f64;
+
+    if sample < 0 {
+        (sample as f64 / half_range) as f32
+    } else {
+        (sample as f64 / (half_range - 1.0)) as f32
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn i32_at(bytes: &[u8], offset: usize) -> i32 {
+    u32_at(bytes, offset) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WavError, WavFormat};
+    use crate::waveform::Waveform;
+
+    fn round_trip(waveform: &Waveform, format: WavFormat) -> Result<Waveform<'static>, WavError> {
+        let mut bytes = Vec::new();
+        waveform.write_wav(&mut bytes, format)?;
+
+        Waveform::read_wav(bytes.as_slice())
+    }
+
+    #[test]
+    fn round_trip_formats() -> Result<(), WavError> {
+        let waveform = Waveform::sine_wave(440.0, 0.1, Waveform::CD_SAMPLE_RATE);
+
+        for (format, epsilon) in [
+            (WavFormat::Int16, 1.0 / 32_767.0),
+            (WavFormat::Int24, 1.0 / 8_388_607.0),
+            (WavFormat::Float32, 0.0),
+        ] {
+            let read = round_trip(&waveform, format)?;
+
+            assert_eq!(read.sample_rate(), waveform.sample_rate());
+            assert_eq!(read.channels(), 1);
+            assert_eq!(read.len(), waveform.len());
+
+            for (original, read) in waveform.samples_iter().zip(read.samples_iter()) {
+                assert!(
+                    (original - read).abs() <= epsilon,
+                    "{format:?}: {original} != {read}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_multichannel() -> Result<(), WavError> {
+        // Three channels of 24 bit samples needs a pad byte after the data chunk
+        let waveform = Waveform::from_planar(
+            vec![
+                vec![0.0, 0.5, 1.0],
+                vec![0.0, -0.5, -1.0],
+                vec![1.0, 0.0, -1.0],
+            ],
+            8_000,
+        );
+
+        for format in [WavFormat::Int16, WavFormat::Int24, WavFormat::Float32] {
+            let read = round_trip(&waveform, format)?;
+
+            assert_eq!(read.channels(), 3);
+            assert_eq!(read.len(), 3);
+
+            for (original, read) in waveform.samples_iter().zip(read.samples_iter()) {
+                assert!(
+                    (original - read).abs() < 1e-4,
+                    "{format:?}: {original} != {read}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn riff_size() -> Result<(), WavError> {
+        // One frame of three 24 bit samples is 9 bytes of data, followed by a pad byte
+        let waveform = Waveform::from_planar(vec![vec![0.0], vec![0.5], vec![1.0]], 8_000);
+
+        for format in [WavFormat::Int16, WavFormat::Int24, WavFormat::Float32] {
+            let mut bytes = Vec::new();
+            waveform.write_wav(&mut bytes, format)?;
+
+            let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+            assert_eq!(size as usize, bytes.len() - 8, "{format:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            Waveform::read_wav(b"RIFF\0\0\0\0AVI ".as_slice()),
+            Err(WavError::NotWave)
+        ));
+        assert!(matches!(
+            Waveform::read_wav(b"RIFF\x04\0\0\0WAVE".as_slice()),
+            Err(WavError::MissingChunk("fmt "))
+        ));
+    }
+}
@@ -1,23 +1,68 @@
-use std::{borrow::Cow, f32::consts, slice::SliceIndex};
+use std::{
+    borrow::Cow,
+    f32::consts,
+    ops::{Bound, RangeBounds},
+};
 
-use lerp::Lerp;
+use crate::resample::ResampleQuality;
 
+/// A buffer of audio samples
+///
+/// Samples are stored interleaved, one frame (a sample for each channel) after
+/// the other, which is the same layout that cpal expects for its output buffers.
 #[derive(Debug)]
 pub struct Waveform<'s> {
     samples: Cow<'s, [f32]>,
+    channels: u16,
     sample_rate: u32,
 }
 
 impl Waveform<'static> {
     pub const CD_SAMPLE_RATE: u32 = 44_100;
 
+    /// Create a mono waveform
     pub fn new(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self::new_interleaved(samples, 1, sample_rate)
+    }
+
+    /// Create a waveform from interleaved samples
+    pub fn new_interleaved(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Self {
+        assert_ne!(channels, 0, "a waveform must have at least one channel");
+        assert_eq!(
+            samples.len() % channels as usize,
+            0,
+            "{} samples can not be split evenly into {channels} channels",
+            samples.len()
+        );
+
         Self {
             samples: Cow::Owned(samples),
+            channels,
             sample_rate,
         }
     }
 
+    /// Create a waveform from one buffer of samples per channel
+    pub fn from_planar(planes: Vec<Vec<f32>>, sample_rate: u32) -> Self {
+        let channels = planes.len();
+        let frames = planes.first().map_or(0, Vec::len);
+
+        assert!(
+            channels <= u16::MAX as usize,
+            "{channels} channels is more than a waveform can hold"
+        );
+        assert!(
+            planes.iter().all(|plane| plane.len() == frames),
+            "all channels must contain the same amount of samples"
+        );
+
+        let samples = (0..frames)
+            .flat_map(|frame| planes.iter().map(move |plane| plane[frame]))
+            .collect();
+
+        Self::new_interleaved(samples, channels as u16, sample_rate)
+    }
+
     pub fn sine_wave(frequency: f32, duration: f32, sample_rate: u32) -> Self {
         let samples_len = (duration * sample_rate as f32).round() as u32;
 
@@ -27,10 +72,12 @@ impl Waveform<'static> {
 
         Self {
             samples,
+            channels: 1,
             sample_rate,
         }
     }
 
+    /// The interleaved samples of this waveform
     pub fn as_samples(self) -> Vec<f32> {
         match self.samples {
             Cow::Borrowed(_) => unreachable!(),
@@ -49,6 +96,34 @@ mod test {
 
         assert_eq!(waveform.len(), waveform.as_samples().len());
     }
+
+    #[test]
+    fn planar_round_trip() {
+        let planes = vec![vec![1.0, 2.0, 3.0], vec![-1.0, -2.0, -3.0]];
+        let waveform = Waveform::from_planar(planes.clone(), Waveform::CD_SAMPLE_RATE);
+
+        assert_eq!(waveform.channels(), 2);
+        assert_eq!(waveform.len(), 3);
+        assert_eq!(waveform.samples(), &[1.0, -1.0, 2.0, -2.0, 3.0, -3.0]);
+        assert_eq!(waveform.to_planar(), planes);
+    }
+
+    #[test]
+    fn slice_frames() {
+        let waveform = Waveform::new_interleaved(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0], 2, 10);
+        let slice = waveform.slice(1..);
+
+        assert_eq!(slice.len(), 2);
+        assert_eq!(slice.channel(1).collect::<Vec<_>>(), [3.0, 5.0]);
+    }
+
+    #[test]
+    fn remix() {
+        let waveform = Waveform::new_interleaved(vec![1.0, 0.0, 0.5, 0.5], 2, 10);
+
+        assert_eq!(waveform.downmix().samples(), &[0.5, 0.5]);
+        assert_eq!(waveform.downmix().upmix(2).samples(), &[0.5, 0.5, 0.5, 0.5]);
+    }
 }
 
 impl Waveform<'_> {
@@ -58,22 +133,63 @@ impl Waveform<'_> {
         self.samples.into_owned()
     }
 
+    /// The interleaved samples of all channels
     pub fn samples(&self) -> &[f32] {
         &self.samples
     }
 
+    /// Iterate over the interleaved samples of all channels
     pub fn samples_iter(&self) -> impl ExactSizeIterator<Item = f32> + '_ {
         self.samples.iter().copied()
     }
 
+    /// Iterate over each frame, a slice containing one sample for every channel
+    pub fn frames(&self) -> impl ExactSizeIterator<Item = &[f32]> + '_ {
+        self.samples.chunks_exact(self.channels as usize)
+    }
+
+    /// Iterate over the samples of a single channel
+    pub fn channel(&self, channel: u16) -> impl ExactSizeIterator<Item = f32> + '_ {
+        assert!(
+            channel < self.channels,
+            "channel {channel} is out of range for a waveform with {} channels",
+            self.channels
+        );
+
+        self.frames().map(move |frame| frame[channel as usize])
+    }
+
+    /// Iterate over the average of all channels in each frame
+    pub fn downmix_iter(&self) -> impl ExactSizeIterator<Item = f32> + '_ {
+        let channels = self.channels as f32;
+
+        self.frames()
+            .map(move |frame| frame.iter().sum::<f32>() / channels)
+    }
+
+    /// Split the waveform into one buffer of samples per channel
+    pub fn to_planar(&self) -> Vec<Vec<f32>> {
+        (0..self.channels)
+            .map(|channel| self.channel(channel).collect())
+            .collect()
+    }
+
+    /// The amount of frames in this waveform
+    ///
+    /// A frame is made up of one sample from each channel, so this is also the
+    /// amount of samples in any single channel
     pub fn len(&self) -> usize {
-        self.samples.len()
+        self.samples.len() / self.channels as usize
     }
 
     pub fn is_empty(&self) -> bool {
         self.samples.is_empty()
     }
 
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
     pub fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
@@ -86,8 +202,8 @@ impl Waveform<'_> {
         sample as f32 / self.sample_rate as f32
     }
 
-    pub fn time_domain(&self) -> impl ExactSizeIterator<Item = (f32, f32)> + '_ {
-        self.samples_iter()
+    pub fn time_domain(&self, channel: u16) -> impl ExactSizeIterator<Item = (f32, f32)> + '_ {
+        self.channel(channel)
             .enumerate()
             .map(|(sample, x)| (self.time_from_sample(sample), x))
     }
@@ -95,48 +211,99 @@ impl Waveform<'_> {
     pub fn to_owned(&self) -> Waveform<'static> {
         Waveform {
             sample_rate: self.sample_rate,
+            channels: self.channels,
             samples: Cow::Owned(self.samples.clone().into_owned()),
         }
     }
 
-    #[must_use = "Waveform::slice() creates a new waveform over the shortened range"]
-    pub fn slice(&self, range: impl SliceIndex<[f32], Output = [f32]>) -> Waveform {
-        Waveform {
-            sample_rate: self.sample_rate,
-            samples: Cow::Borrowed(&self.samples[range]),
-        }
+    /// Average all channels together into a single channel
+    #[must_use = "Waveform::downmix() does not modify the provided waveform"]
+    pub fn downmix(&self) -> Waveform<'static> {
+        self.remix(1)
     }
 
-    #[must_use = "Waveform::resample() does not modify the provided waveform"]
-    pub fn resample(&self, new_sample_rate: u32) -> Waveform<'static> {
-        let new_sample_len =
-            (self.time_from_sample(self.len() - 1) * new_sample_rate as f32) as usize;
+    /// Spread the channels of this waveform over more channels
+    #[must_use = "Waveform::upmix() does not modify the provided waveform"]
+    pub fn upmix(&self, channels: u16) -> Waveform<'static> {
+        assert!(
+            channels >= self.channels,
+            "can not upmix {} channels into {channels} channels",
+            self.channels
+        );
+
+        self.remix(channels)
+    }
+
+    /// Convert this waveform into one with the given amount of channels
+    ///
+    /// When adding channels, the existing channels are repeated in order. When
+    /// removing channels, every input channel is averaged into the output channel
+    /// with the same index modulo the new channel count.
+    #[must_use = "Waveform::remix() does not modify the provided waveform"]
+    pub fn remix(&self, channels: u16) -> Waveform<'static> {
+        assert_ne!(channels, 0, "a waveform must have at least one channel");
 
-        let mut resampled = vec![0.0; new_sample_len];
+        if channels == self.channels {
+            return self.to_owned();
+        }
+
+        let samples = if channels > self.channels {
+            self.frames()
+                .flat_map(|frame| {
+                    (0..channels as usize).map(move |channel| frame[channel % frame.len()])
+                })
+                .collect()
+        } else {
+            let mut samples = Vec::with_capacity(self.len() * channels as usize);
 
-        // Resample the waveform
-        for (n, sample) in resampled.iter_mut().enumerate() {
-            // Calculate where this sample lies
-            let virtual_sample = (n as f32 / new_sample_rate as f32) * self.sample_rate as f32;
+            for frame in self.frames() {
+                for channel in 0..channels as usize {
+                    let (sum, count) = frame
+                        .iter()
+                        .skip(channel)
+                        .step_by(channels as usize)
+                        .fold((0.0, 0), |(sum, count), sample| (sum + sample, count + 1));
 
-            // Get the sample before and after this fractional sample
-            let before_sample = virtual_sample.floor() as usize;
-            let after_sample = virtual_sample.ceil() as usize;
+                    samples.push(sum / count as f32);
+                }
+            }
 
-            // Get the percentage between the two samples this sample is
-            let lerp_frac = virtual_sample.fract();
+            samples
+        };
 
-            // Linearly interpolate between the two
-            *sample = Lerp::lerp(
-                self.samples[before_sample],
-                self.samples[after_sample],
-                lerp_frac,
-            );
+        Waveform {
+            sample_rate: self.sample_rate,
+            channels,
+            samples: Cow::Owned(samples),
         }
+    }
+
+    /// Take a range of frames out of the waveform
+    #[must_use = "Waveform::slice() creates a new waveform over the shortened range"]
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Waveform {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+
+        let channels = self.channels as usize;
 
         Waveform {
-            sample_rate: new_sample_rate,
-            samples: Cow::Owned(resampled),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: Cow::Borrowed(&self.samples[start * channels..end * channels]),
         }
     }
+
+    /// Resample the waveform with the [`ResampleQuality::Balanced`] preset
+    #[must_use = "Waveform::resample() does not modify the provided waveform"]
+    pub fn resample(&self, new_sample_rate: u32) -> Waveform<'static> {
+        self.resample_with(new_sample_rate, ResampleQuality::Balanced)
+    }
 }
@@ -0,0 +1,421 @@
+//! A channel vocoder, which imposes the spectral envelope of a modulator onto a carrier
+//!
+//! Both signals are split into frames and transformed, then the buckets of each are grouped
+//! into logarithmically spaced bands. The energy of every modulator band is smoothed by an
+//! envelope follower and replaces the energy of the same carrier band, which lets a synth pad
+//! speak the words of a voice.
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::{
+    fft::{irfft, rfft},
+    Window,
+};
+
+/// Carrier bands quieter than this are left silent instead of being amplified
+const MIN_CARRIER_ENERGY: f32 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelVocoderOptions {
+    /// The amount of bands between the minimum and maximum frequency
+    pub bands: usize,
+    /// The lower edge of the lowest band in Hz
+    pub min_frequency: f32,
+    /// The upper edge of the highest band in Hz
+    pub max_frequency: f32,
+    /// How quickly a band follows a rise of the modulator, in seconds
+    pub attack: f32,
+    /// How quickly a band follows a fall of the modulator, in seconds
+    pub release: f32,
+    /// Move the modulator's formants up or down by this amount of semitones
+    pub formant_shift: f32,
+    /// The width of each frame, which must be fine enough to resolve the lowest band
+    pub fft_width: usize,
+}
+
+impl Default for ChannelVocoderOptions {
+    fn default() -> Self {
+        Self {
+            bands: 16,
+            min_frequency: 80.0,
+            max_frequency: 8000.0,
+            attack: 0.005,
+            release: 0.05,
+            formant_shift: 0.0,
+            fft_width: 1024,
+        }
+    }
+}
+
+/// A channel vocoder that processes its inputs in blocks of any size
+///
+/// The output lags behind the input by [`latency`](Self::latency) samples.
+#[derive(Debug, Clone)]
+pub struct ChannelVocoder {
+    options: ChannelVocoderOptions,
+    sample_rate: u32,
+
+    window: Box<[f32]>,
+    hop: usize,
+    /// The overlap-added squares of the window, which are the same for every sample
+    normalization: f32,
+
+    /// The band of each carrier and modulator bucket, if it is inside of the frequency range
+    carrier_bands: Box<[Option<usize>]>,
+    modulator_bands: Box<[Option<usize>]>,
+
+    envelopes: Box<[f32]>,
+    attack: f32,
+    release: f32,
+
+    modulator: Vec<f32>,
+    carrier: Vec<f32>,
+    overlap: Vec<f32>,
+}
+
+impl ChannelVocoder {
+    pub fn new(options: ChannelVocoderOptions, sample_rate: u32) -> Self {
+        let window = Window::Hann
+            .into_iter(options.fft_width)
+            .collect::<Box<_>>();
+        let hop = options.fft_width / 4;
+
+        let mut vocoder = Self {
+            options,
+            sample_rate,
+
+            normalization: window.iter().map(|w| w * w).sum::<f32>() / hop as f32,
+            window,
+            hop,
+
+            carrier_bands: Box::new([]),
+            modulator_bands: Box::new([]),
+
+            envelopes: vec![0.0; options.bands].into(),
+            attack: 0.0,
+            release: 0.0,
+
+            modulator: vec![0.0; options.fft_width - hop],
+            carrier: vec![0.0; options.fft_width - hop],
+            overlap: vec![0.0; options.fft_width],
+        };
+        vocoder.set_options(options);
+
+        vocoder
+    }
+
+    /// Vocode the whole modulator at once, returning a waveform of the same length
+    ///
+    /// Both waveforms are downmixed, and the carrier is resampled to the sample rate of the
+    /// modulator and looped if it is too short.
+    pub fn vocode(
+        options: ChannelVocoderOptions,
+        modulator: &Waveform<'_>,
+        carrier: &Waveform<'_>,
+    ) -> Waveform<'static> {
+        assert!(!carrier.is_empty(), "the carrier must not be empty");
+
+        let sample_rate = modulator.sample_rate();
+        let carrier = carrier.downmix().resample(sample_rate);
+
+        let mut vocoder = Self::new(options, sample_rate);
+        let latency = vocoder.latency();
+
+        // Feed silence after the end to push the last samples through
+        let modulator = modulator
+            .downmix_iter()
+            .chain(std::iter::repeat_n(0.0, latency))
+            .collect::<Vec<_>>();
+        let carrier = carrier
+            .samples()
+            .iter()
+            .copied()
+            .cycle()
+            .take(modulator.len())
+            .collect::<Vec<_>>();
+
+        let mut output = Vec::with_capacity(modulator.len());
+        vocoder.process(&modulator, &carrier, &mut output);
+
+        let mut output = output.split_off(latency.min(output.len()));
+        output.resize(modulator.len() - latency, 0.0);
+
+        Waveform::new(output, sample_rate)
+    }
+
+    pub fn options(&self) -> ChannelVocoderOptions {
+        self.options
+    }
+
+    /// Change the options while running
+    ///
+    /// Changing the fft width or the amount of bands starts over from silence.
+    pub fn set_options(&mut self, options: ChannelVocoderOptions) {
+        assert_ne!(options.bands, 0, "there must be at least one band");
+        assert!(
+            options.fft_width >= 4,
+            "the fft width must be at least four samples"
+        );
+        assert!(
+            0.0 < options.min_frequency && options.min_frequency < options.max_frequency,
+            "the frequency range must be positive and not empty"
+        );
+
+        if options.fft_width != self.options.fft_width || options.bands != self.options.bands {
+            *self = Self::new(
+                ChannelVocoderOptions {
+                    fft_width: options.fft_width,
+                    bands: options.bands,
+                    ..self.options
+                },
+                self.sample_rate,
+            );
+        }
+
+        let buckets = options.fft_width / 2 + 1;
+        let bucket_width = self.sample_rate as f32 / options.fft_width as f32;
+        let formant_ratio = 2f32.powf(options.formant_shift / 12.0);
+
+        let band = |frequency: f32| {
+            if frequency < options.min_frequency || frequency >= options.max_frequency {
+                return None;
+            }
+
+            let position = (frequency / options.min_frequency).ln()
+                / (options.max_frequency / options.min_frequency).ln();
+
+            Some(((position * options.bands as f32) as usize).min(options.bands - 1))
+        };
+
+        self.carrier_bands = (0..buckets)
+            .map(|bucket| band(bucket as f32 * bucket_width))
+            .collect();
+        // Measuring the modulator at lower frequencies moves its formants up in the output
+        self.modulator_bands = (0..buckets)
+            .map(|bucket| band(bucket as f32 * bucket_width * formant_ratio))
+            .collect();
+
+        let frames_per_second = self.sample_rate as f32 / self.hop as f32;
+        let smoothing = |seconds: f32| 1.0 - (-1.0 / (seconds * frames_per_second)).exp();
+
+        self.attack = smoothing(options.attack.max(f32::EPSILON));
+        self.release = smoothing(options.release.max(f32::EPSILON));
+        self.options = options;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// How many samples the output lags behind the input
+    pub fn latency(&self) -> usize {
+        self.options.fft_width - self.hop
+    }
+
+    /// Vocode the next block of mono samples, appending the result to `output`
+    ///
+    /// Output is produced a hop at a time, so it may lag behind by a few samples more than
+    /// the [`latency`](Self::latency) until the next block is processed.
+    pub fn process(&mut self, modulator: &[f32], carrier: &[f32], output: &mut Vec<f32>) {
+        assert_eq!(
+            modulator.len(),
+            carrier.len(),
+            "the modulator and carrier blocks must be the same length"
+        );
+
+        let fft_width = self.options.fft_width;
+
+        for (&modulator, &carrier) in modulator.iter().zip(carrier) {
+            self.modulator.push(modulator);
+            self.carrier.push(carrier);
+
+            if self.modulator.len() == fft_width {
+                self.process_frame(output);
+
+                self.modulator.drain(..self.hop);
+                self.carrier.drain(..self.hop);
+            }
+        }
+    }
+
+    /// Throw away everything that is still being processed
+    pub fn reset(&mut self) {
+        *self = Self::new(self.options, self.sample_rate);
+    }
+
+    fn process_frame(&mut self, output: &mut Vec<f32>) {
+        let windowed = |samples: &[f32]| {
+            samples
+                .iter()
+                .zip(&*self.window)
+                .map(|(sample, w)| sample * w)
+                .collect::<Vec<_>>()
+        };
+
+        let modulator = rfft(&windowed(&self.modulator));
+        let mut carrier = rfft(&windowed(&self.carrier));
+
+        let bands = self.options.bands;
+        let modulator_energy = band_energies(&modulator, &self.modulator_bands, bands);
+        let carrier_energy = band_energies(&carrier, &self.carrier_bands, bands);
+
+        for (envelope, &target) in self.envelopes.iter_mut().zip(&modulator_energy) {
+            let smoothing = if target > *envelope {
+                self.attack
+            } else {
+                self.release
+            };
+
+            *envelope += smoothing * (target - *envelope);
+        }
+
+        for (bucket, band) in carrier.iter_mut().zip(&*self.carrier_bands) {
+            *bucket = match band {
+                Some(band) if carrier_energy[*band] > MIN_CARRIER_ENERGY => {
+                    *bucket * (self.envelopes[*band] / carrier_energy[*band])
+                }
+                _ => Complex::new(0.0, 0.0),
+            };
+        }
+
+        let frame = irfft(&carrier, self.options.fft_width);
+
+        for ((overlap, sample), w) in self.overlap.iter_mut().zip(frame).zip(&*self.window) {
+            *overlap += sample * w;
+        }
+
+        output.extend(
+            self.overlap[..self.hop]
+                .iter()
+                .map(|sample| sample / self.normalization),
+        );
+
+        self.overlap.drain(..self.hop);
+        self.overlap.resize(self.options.fft_width, 0.0);
+    }
+}
+
+/// The root mean square magnitude of the buckets in each band
+fn band_energies(
+    buckets: &[Complex<f32>],
+    bucket_bands: &[Option<usize>],
+    bands: usize,
+) -> Vec<f32> {
+    let mut energies = vec![0.0; bands];
+    let mut counts = vec![0usize; bands];
+
+    for (bucket, band) in buckets.iter().zip(bucket_bands) {
+        if let Some(band) = *band {
+            energies[band] += bucket.norm_sqr();
+            counts[band] += 1;
+        }
+    }
+
+    energies
+        .into_iter()
+        .zip(counts)
+        .map(|(energy, count)| {
+            if count == 0 {
+                0.0
+            } else {
+                (energy / count as f32).sqrt()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{ChannelVocoder, ChannelVocoderOptions};
+    use crate::{WaveformSpectrum, Window};
+
+    /// A sawtooth wave, which has energy in every band
+    fn carrier(sample_rate: u32, len: usize) -> Waveform<'static> {
+        Waveform::new(
+            (0..len)
+                .map(|n| (n as f32 * 110.0 / sample_rate as f32).fract() * 2.0 - 1.0)
+                .collect(),
+            sample_rate,
+        )
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn silent_modulator() {
+        let modulator = Waveform::new(vec![0.0; 8000], 16_000);
+        let output = ChannelVocoder::vocode(
+            ChannelVocoderOptions::default(),
+            &modulator,
+            &carrier(16_000, 8000),
+        );
+
+        assert_eq!(output.len(), modulator.len());
+        assert!(output.samples().iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn follows_modulator_spectrum() {
+        let modulator = Waveform::sine_wave(1000.0, 0.5, 16_000);
+        let output = ChannelVocoder::vocode(
+            ChannelVocoderOptions::default(),
+            &modulator,
+            &carrier(16_000, 4000),
+        );
+
+        assert_eq!(output.len(), modulator.len());
+        assert!(rms(output.samples()) > 0.01);
+
+        // The carrier's harmonics around the modulator's frequency are the loudest
+        let middle = output.slice(2048..6144);
+        let spectrum = middle.spectrum(Window::Hann, 4096);
+        let frequency = spectrum
+            .main_frequency()
+            .map_or(0.0, |(bucket, _)| spectrum.freq_from_bucket(bucket));
+
+        assert!((800.0..1200.0).contains(&frequency), "{frequency}");
+    }
+
+    #[test]
+    fn blocks_match_offline() {
+        let options = ChannelVocoderOptions {
+            formant_shift: 3.0,
+            ..ChannelVocoderOptions::default()
+        };
+
+        let modulator = Waveform::sine_wave(440.0, 0.25, 16_000);
+        let carrier = carrier(16_000, modulator.len());
+
+        let mut vocoder = ChannelVocoder::new(options, 16_000);
+        let mut output = Vec::new();
+
+        let mut start = 0;
+        for block in [1, 7, 100, 512, 1023].iter().cycle() {
+            if start >= modulator.len() {
+                break;
+            }
+
+            let end = (start + block).min(modulator.len());
+            vocoder.process(
+                &modulator.samples()[start..end],
+                &carrier.samples()[start..end],
+                &mut output,
+            );
+
+            start = end;
+        }
+
+        let offline = ChannelVocoder::vocode(options, &modulator, &carrier);
+        let latency = vocoder.latency();
+
+        assert_eq!(
+            &output[latency..],
+            &offline.samples()[..output.len() - latency]
+        );
+    }
+}
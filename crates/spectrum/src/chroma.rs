@@ -0,0 +1,195 @@
+//! Chroma features, which fold a spectrum into the twelve pitch classes of an octave
+//!
+//! Octaves sound alike, so harmony can be described by how much energy lies on each pitch
+//! class regardless of its octave. Every frequency component is assigned to the closest pitch
+//! class in twelve tone equal temperament and contributes its energy, the squared magnitude.
+
+use std::{iter::Sum, ops::AddAssign};
+
+use crate::Spectrum;
+
+/// The amount of pitch classes in an octave
+pub const PITCH_CLASSES: usize = 12;
+
+/// The pitch class of A, counted in semitones from C
+const A: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChromaOptions {
+    /// The frequency of A4 in Hz
+    pub tuning: f32,
+    /// Components below this are ignored, in Hz
+    pub min_frequency: f32,
+    /// Components above this are ignored, in Hz
+    pub max_frequency: f32,
+}
+
+impl Default for ChromaOptions {
+    fn default() -> Self {
+        Self {
+            tuning: 440.0,
+            min_frequency: 55.0,
+            max_frequency: 5000.0,
+        }
+    }
+}
+
+/// The energy of each pitch class, starting at C
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Chroma([f32; PITCH_CLASSES]);
+
+impl Chroma {
+    pub fn new(energy: [f32; PITCH_CLASSES]) -> Self {
+        Self(energy)
+    }
+
+    /// Fold components given as their frequency and magnitude into their pitch classes
+    pub fn from_components(
+        components: impl IntoIterator<Item = (f32, f32)>,
+        options: ChromaOptions,
+    ) -> Self {
+        let mut energy = [0.0; PITCH_CLASSES];
+
+        for (frequency, magnitude) in components {
+            if frequency < options.min_frequency || frequency > options.max_frequency {
+                continue;
+            }
+
+            energy[pitch_class(frequency, options.tuning)] += magnitude * magnitude;
+        }
+
+        Self(energy)
+    }
+
+    pub fn energy(&self) -> &[f32; PITCH_CLASSES] {
+        &self.0
+    }
+
+    /// The energy of all pitch classes together
+    pub fn total(&self) -> f32 {
+        self.0.iter().sum()
+    }
+
+    /// Scale the energy to sum to one, leaving silence as it is
+    pub fn normalized(&self) -> Self {
+        let total = self.total();
+        if total <= 0.0 {
+            return *self;
+        }
+
+        Self(self.0.map(|energy| energy / total))
+    }
+
+    /// The Pearson correlation with a profile of the same pitch classes, between -1 and 1
+    ///
+    /// Zero if either is flat, which says nothing about how well they match.
+    pub fn correlation(&self, profile: &[f32; PITCH_CLASSES]) -> f32 {
+        let mean =
+            |values: &[f32; PITCH_CLASSES]| values.iter().sum::<f32>() / PITCH_CLASSES as f32;
+        let (own_mean, profile_mean) = (mean(&self.0), mean(profile));
+
+        let mut covariance = 0.0;
+        let mut own_variance = 0.0;
+        let mut profile_variance = 0.0;
+        for (own, profile) in self.0.iter().zip(profile) {
+            let (own, profile) = (own - own_mean, profile - profile_mean);
+
+            covariance += own * profile;
+            own_variance += own * own;
+            profile_variance += profile * profile;
+        }
+
+        let deviation = (own_variance * profile_variance).sqrt();
+        if deviation <= f32::EPSILON {
+            return 0.0;
+        }
+
+        covariance / deviation
+    }
+}
+
+impl AddAssign for Chroma {
+    fn add_assign(&mut self, other: Self) {
+        for (energy, other) in self.0.iter_mut().zip(other.0) {
+            *energy += other;
+        }
+    }
+}
+
+impl Sum for Chroma {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |mut sum, chroma| {
+            sum += chroma;
+            sum
+        })
+    }
+}
+
+/// The closest pitch class to the frequency, counted in semitones from C
+pub fn pitch_class(frequency: f32, tuning: f32) -> usize {
+    let semitones_from_a = (12.0 * (frequency / tuning).log2()).round() as isize;
+
+    (semitones_from_a + A as isize).rem_euclid(PITCH_CLASSES as isize) as usize
+}
+
+impl Spectrum<'_> {
+    /// Fold the non-negative frequencies into their pitch classes, see
+    /// [`Spectrum::magnitudes_real`]
+    pub fn chroma(&self, options: ChromaOptions) -> Chroma {
+        let components = self
+            .magnitudes_real()
+            .enumerate()
+            .map(|(bucket, magnitude)| (self.freq_from_bucket(bucket) as f32, magnitude));
+
+        Chroma::from_components(components, options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pitch_class, Chroma, ChromaOptions};
+    use crate::{fixtures, WaveformSpectrum, Window};
+
+    #[test]
+    fn pitch_classes() {
+        assert_eq!(pitch_class(440.0, 440.0), 9);
+        assert_eq!(pitch_class(261.63, 440.0), 0);
+        assert_eq!(pitch_class(27.5, 440.0), 9);
+        assert_eq!(pitch_class(246.94, 440.0), 11);
+        // Less than a quarter tone flat of C rounds to C, more rounds to B
+        assert_eq!(pitch_class(255.0, 440.0), 0);
+        assert_eq!(pitch_class(253.0, 440.0), 11);
+        // Baroque tuning moves A down
+        assert_eq!(pitch_class(415.3, 415.3), 9);
+    }
+
+    #[test]
+    fn major_triad() {
+        // C4, E4 and G4 in a lower and a higher octave
+        let waveform = fixtures::chord(
+            &[261.63, 329.63, 392.0, 1046.5, 1318.5, 1568.0]
+                .map(|frequency| (frequency, 1.0 / 6.0)),
+            0.2,
+            44_100,
+        );
+
+        let chroma = waveform
+            .spectrum(Window::Hann, 16384)
+            .chroma(ChromaOptions::default())
+            .normalized();
+
+        let energy = chroma.energy();
+        assert!((chroma.total() - 1.0).abs() < 1e-4);
+        for pitch_class in [0, 4, 7] {
+            assert!(energy[pitch_class] > 0.3, "{energy:?}");
+        }
+
+        // The triad is played, so it correlates well with itself
+        let mut triad = [0.0; 12];
+        for pitch_class in [0, 4, 7] {
+            triad[pitch_class] = 1.0;
+        }
+        assert!(chroma.correlation(&triad) > 0.95);
+        assert_eq!(Chroma::default().correlation(&triad), 0.0);
+    }
+}
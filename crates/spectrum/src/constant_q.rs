@@ -0,0 +1,209 @@
+//! A constant-Q transform, whose bins are spaced logarithmically like musical notes
+//!
+//! Every bin has its own windowed complex sinusoid as a kernel, which spans the same amount of
+//! periods of its center frequency in every bin. Low bins therefore look at long stretches of
+//! the waveform and high bins at short ones, so that every bin is as wide as a fixed fraction
+//! of an octave. All kernels of a frame share the same center, which keeps the bins of a frame
+//! aligned in time.
+
+use std::f32::consts::TAU;
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::Window;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConstantQOptions {
+    /// The center frequency of the lowest bin in Hz
+    pub min_frequency: f32,
+    pub bins_per_octave: usize,
+    pub bins: usize,
+    /// Scales the length of every kernel, where values below 1 give up frequency resolution
+    /// for time resolution
+    pub q_scale: f32,
+    pub window: Window,
+}
+
+impl ConstantQOptions {
+    /// One bin centered on each of the 88 keys of a piano, from A0 to C8
+    pub fn piano() -> Self {
+        Self {
+            min_frequency: 27.5,
+            bins_per_octave: 12,
+            bins: 88,
+            q_scale: 1.0,
+            window: Window::Hann,
+        }
+    }
+}
+
+impl Default for ConstantQOptions {
+    fn default() -> Self {
+        Self::piano()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstantQ {
+    options: ConstantQOptions,
+    sample_rate: u32,
+    kernels: Vec<Box<[Complex<f32>]>>,
+}
+
+impl ConstantQ {
+    pub fn new(options: ConstantQOptions, sample_rate: u32) -> Self {
+        assert!(
+            options.bins_per_octave > 0,
+            "an octave needs at least one bin"
+        );
+        assert!(
+            options.min_frequency > 0.0 && options.q_scale > 0.0,
+            "the lowest frequency and the q scale must be positive"
+        );
+
+        let max_frequency = options.min_frequency
+            * 2f32.powf((options.bins.max(1) - 1) as f32 / options.bins_per_octave as f32);
+        assert!(
+            max_frequency < sample_rate as f32 / 2.0,
+            "the highest bin at {max_frequency} Hz is above nyquist"
+        );
+
+        // The ratio of the center frequency of each bin to the distance to the next bin
+        let q = options.q_scale / (2f32.powf(1.0 / options.bins_per_octave as f32) - 1.0);
+
+        let kernels = (0..options.bins)
+            .map(|bin| {
+                let frequency =
+                    options.min_frequency * 2f32.powf(bin as f32 / options.bins_per_octave as f32);
+                let width = ((q * sample_rate as f32 / frequency).round() as usize).max(1);
+
+                let coefficients = options.window.into_iter(width).collect::<Vec<_>>();
+                // A sinusoid of amplitude 1 in the center of the bin reads as 1
+                let scale = 2.0 / coefficients.iter().sum::<f32>();
+
+                coefficients
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, coefficient)| {
+                        let angle = -TAU * frequency * n as f32 / sample_rate as f32;
+
+                        Complex::from_polar(coefficient * scale, angle)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            options,
+            sample_rate,
+            kernels,
+        }
+    }
+
+    pub fn options(&self) -> ConstantQOptions {
+        self.options
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn bins(&self) -> usize {
+        self.kernels.len()
+    }
+
+    /// The center frequency of the given bin in Hz
+    pub fn frequency(&self, bin: usize) -> f32 {
+        self.options.min_frequency * 2f32.powf(bin as f32 / self.options.bins_per_octave as f32)
+    }
+
+    /// The amount of samples the kernel of the given bin spans
+    pub fn kernel_width(&self, bin: usize) -> usize {
+        self.kernels[bin].len()
+    }
+
+    /// Transform the samples around `center`, treating everything outside of the samples as
+    /// silence
+    pub fn transform_at(&self, samples: &[f32], center: isize) -> Vec<Complex<f32>> {
+        self.kernels
+            .iter()
+            .map(|kernel| {
+                let start = center - kernel.len() as isize / 2;
+
+                // Only the part of the kernel that overlaps the samples contributes
+                let skip = usize::try_from(-start).unwrap_or(0).min(kernel.len());
+                let first = usize::try_from(start).unwrap_or(0).min(samples.len());
+
+                kernel[skip..]
+                    .iter()
+                    .zip(&samples[first..])
+                    .map(|(coefficient, &sample)| coefficient * sample)
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Transform frames centered every `hop` samples, starting at the first sample, of the
+    /// waveform downmixed to a single channel
+    pub fn transform(&self, waveform: &Waveform<'_>, hop: usize) -> Vec<Vec<Complex<f32>>> {
+        assert_ne!(hop, 0, "the hop must be at least one sample");
+        assert_eq!(
+            waveform.sample_rate(),
+            self.sample_rate,
+            "the waveform must have the sample rate the transform was created for"
+        );
+
+        let samples = waveform.downmix_iter().collect::<Vec<_>>();
+
+        (0..samples.len())
+            .step_by(hop)
+            .map(|center| self.transform_at(&samples, center as isize))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{ConstantQ, ConstantQOptions};
+
+    #[test]
+    fn piano_keys() {
+        let cqt = ConstantQ::new(ConstantQOptions::piano(), 44_100);
+
+        assert_eq!(cqt.bins(), 88);
+        assert!((cqt.frequency(48) - 440.0).abs() < 1e-3);
+        assert!((cqt.frequency(87) - 4186.01).abs() < 0.1);
+
+        // Every bin spans the same amount of periods of its frequency
+        let periods = |bin: usize| cqt.kernel_width(bin) as f32 * cqt.frequency(bin) / 44_100.0;
+        assert!((periods(0) - periods(87)).abs() < 1.0);
+    }
+
+    #[test]
+    fn resolves_low_keys() {
+        let cqt = ConstantQ::new(ConstantQOptions::piano(), 44_100);
+
+        // A1 and B♭1 are about 3.3 Hz apart, far closer than the 21.5 Hz between the buckets of a
+        // 2048 bucket transform
+        for (frequency, bin) in [(55.0, 12), (58.27, 13), (440.0, 48), (3520.0, 84)] {
+            let waveform = Waveform::sine_wave(frequency, 2.0, 44_100);
+            let samples = waveform.samples();
+            let magnitudes = cqt
+                .transform_at(samples, samples.len() as isize / 2)
+                .iter()
+                .map(|bin| bin.norm())
+                .collect::<Vec<_>>();
+
+            let loudest = (0..magnitudes.len())
+                .max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b]))
+                .unwrap_or_default();
+
+            assert_eq!(loudest, bin, "{frequency}");
+            assert!((magnitudes[bin] - 1.0).abs() < 0.01, "{}", magnitudes[bin]);
+            assert!(magnitudes[bin + 1] < 0.6 && magnitudes[bin - 1] < 0.6);
+        }
+    }
+}
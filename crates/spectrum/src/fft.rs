@@ -1,5 +1,16 @@
+//! Fast fourier transforms of any length
+//!
+//! Powers of two up to [`MICROFFT_MAX_WIDTH`] use the fixed size transforms from microfft,
+//! larger powers of two fall back to an iterative radix-2 transform and every other length is
+//! computed with Bluestein's algorithm on top of a power of two transform.
+
+use std::f64::consts;
+
 use num_complex::Complex;
 
+/// The largest transform that microfft has been compiled with
+pub const MICROFFT_MAX_WIDTH: usize = 16384;
+
 macro_rules! variable_width_fft {
     (
         use $algor:path;
@@ -11,15 +22,160 @@ macro_rules! variable_width_fft {
         match $samples.len() {
             $(
                 $num => paste::paste! {{
-                    [<$algor _ $num>](TryFrom::<&mut [Complex<f32>]>::try_from($samples).expect(concat!("spectrum.len() != ", $num))) as &mut [Complex<f32>]
+                    // The length was just checked, so the conversion can not fail
+                    if let Ok(samples) = TryFrom::<&mut [Complex<f32>]>::try_from($samples) {
+                        [<$algor _ $num>](samples);
+                    }
                 }},
             )+
-            _ => unimplemented!("unsupported width"),
+            _ => unreachable!("only called with supported widths"),
         }
     };
 }
 
+/// The algorithm that [`cfft`] uses for a transform of the given width
+pub fn algorithm(width: usize) -> &'static str {
+    if width <= 1 {
+        "identity"
+    } else if width.is_power_of_two() && width <= MICROFFT_MAX_WIDTH {
+        "microfft"
+    } else if width.is_power_of_two() {
+        "radix-2"
+    } else {
+        "bluestein"
+    }
+}
+
+/// Transform the samples into their frequency spectrum, in place
 pub fn cfft(samples: &mut [Complex<f32>]) {
+    let width = samples.len();
+
+    if width <= 1 {
+        // The transform of a single sample is itself
+    } else if width.is_power_of_two() && width <= MICROFFT_MAX_WIDTH {
+        microfft_cfft(samples);
+    } else if width.is_power_of_two() {
+        radix_2(samples);
+    } else {
+        bluestein(samples);
+    }
+}
+
+/// Transform a frequency spectrum back into samples, in place
+///
+/// The result is scaled by `1 / width`, so that this exactly undoes [`cfft`].
+pub fn icfft(spectrum: &mut [Complex<f32>]) {
+    let scale = 1.0 / spectrum.len() as f32;
+
+    // The inverse transform is the forward transform of the conjugate, conjugated again
+    spectrum
+        .iter_mut()
+        .for_each(|bucket| *bucket = bucket.conj());
+    cfft(spectrum);
+    spectrum
+        .iter_mut()
+        .for_each(|bucket| *bucket = bucket.conj() * scale);
+}
+
+/// Transform real samples into the `width / 2 + 1` buckets of their non-negative frequencies
+///
+/// The negative frequencies of a real signal are the complex conjugates of the positive ones,
+/// so they are not computed at all. Even widths are transformed as a complex signal of half
+/// the width.
+pub fn rfft(samples: &[f32]) -> Vec<Complex<f32>> {
+    let width = samples.len();
+
+    if width % 2 == 1 || width < 2 {
+        let mut buckets = samples
+            .iter()
+            .map(|&sample| Complex::new(sample, 0.0))
+            .collect::<Vec<_>>();
+
+        cfft(&mut buckets);
+        buckets.truncate(width / 2 + 1);
+
+        return buckets;
+    }
+
+    let half = width / 2;
+
+    // Pack the even samples into the real and the odd samples into the imaginary components
+    let mut packed = samples
+        .chunks_exact(2)
+        .map(|pair| Complex::new(pair[0], pair[1]))
+        .collect::<Vec<_>>();
+
+    cfft(&mut packed);
+
+    (0..=half)
+        .map(|bucket| {
+            let z = packed[bucket % half];
+            let mirror = packed[(half - bucket) % half].conj();
+
+            let even = (z + mirror) * 0.5;
+            let odd = (z - mirror) * Complex::new(0.0, -0.5);
+
+            even + twiddle(bucket, width) * odd
+        })
+        .collect()
+}
+
+/// Transform the non-negative frequencies of a real signal back into its `width` samples
+///
+/// This undoes [`rfft`]. The imaginary parts of the DC bucket, and of the nyquist bucket for
+/// even widths, are ignored since they are always zero for a real signal.
+pub fn irfft(buckets: &[Complex<f32>], width: usize) -> Vec<f32> {
+    assert_eq!(
+        buckets.len(),
+        width / 2 + 1,
+        "a real signal of width {width} has {} buckets",
+        width / 2 + 1
+    );
+
+    if width % 2 == 1 || width < 2 {
+        // Rebuild the negative frequencies from the positive ones
+        let mut spectrum = buckets
+            .iter()
+            .copied()
+            .chain(buckets[1..].iter().rev().map(Complex::conj))
+            .take(width)
+            .collect::<Vec<_>>();
+
+        icfft(&mut spectrum);
+
+        return spectrum.into_iter().map(|sample| sample.re).collect();
+    }
+
+    let half = width / 2;
+
+    let mut packed = (0..half)
+        .map(|bucket| {
+            let x = buckets[bucket];
+            let mirror = buckets[half - bucket].conj();
+
+            let even = (x + mirror) * 0.5;
+            let odd = (x - mirror) * twiddle(bucket, width).conj() * 0.5;
+
+            even + odd * Complex::new(0.0, 1.0)
+        })
+        .collect::<Vec<_>>();
+
+    icfft(&mut packed);
+
+    packed
+        .into_iter()
+        .flat_map(|pair| [pair.re, pair.im])
+        .collect()
+}
+
+/// `e^(-2πi * bucket / width)`, computed in double precision
+fn twiddle(bucket: usize, width: usize) -> Complex<f32> {
+    let angle = -consts::TAU * bucket as f64 / width as f64;
+
+    Complex::new(angle.cos() as f32, angle.sin() as f32)
+}
+
+fn microfft_cfft(samples: &mut [Complex<f32>]) {
     use microfft::complex::*;
 
     variable_width_fft! {
@@ -32,3 +188,218 @@ pub fn cfft(samples: &mut [Complex<f32>]) {
         ]
     };
 }
+
+/// An iterative, decimation in time transform for any power of two
+fn radix_2(samples: &mut [Complex<f32>]) {
+    let width = samples.len();
+    let bits = width.trailing_zeros();
+
+    for index in 0..width {
+        let reversed = index.reverse_bits() >> (usize::BITS - bits);
+
+        if reversed > index {
+            samples.swap(index, reversed);
+        }
+    }
+
+    // Every stage uses a subset of the twiddle factors of the full width
+    let twiddles = (0..width / 2)
+        .map(|bucket| twiddle(bucket, width))
+        .collect::<Vec<_>>();
+
+    let mut size = 2;
+    while size <= width {
+        let stride = width / size;
+
+        for chunk in samples.chunks_exact_mut(size) {
+            let (evens, odds) = chunk.split_at_mut(size / 2);
+
+            for (bucket, (even, odd)) in evens.iter_mut().zip(odds).enumerate() {
+                let odd_twiddled = *odd * twiddles[bucket * stride];
+
+                *odd = *even - odd_twiddled;
+                *even += odd_twiddled;
+            }
+        }
+
+        size *= 2;
+    }
+}
+
+/// Express a transform of any width as a convolution, which is computed with power of two
+/// transforms
+fn bluestein(samples: &mut [Complex<f32>]) {
+    let width = samples.len();
+    let convolution_width = (2 * width - 1).next_power_of_two();
+
+    // `e^(-πi * n² / width)`, reducing `n²` first to keep the angle precise
+    let chirp = (0..width)
+        .map(|n| {
+            let square = (n as u128 * n as u128 % (2 * width as u128)) as f64;
+            let angle = -consts::PI * square / width as f64;
+
+            Complex::new(angle.cos() as f32, angle.sin() as f32)
+        })
+        .collect::<Vec<_>>();
+
+    let mut signal = vec![Complex::new(0.0, 0.0); convolution_width];
+    for ((padded, &sample), &chirp) in signal.iter_mut().zip(samples.iter()).zip(&chirp) {
+        *padded = sample * chirp;
+    }
+
+    // The conjugated chirp, wrapped around so that the convolution is circular
+    let mut kernel = vec![Complex::new(0.0, 0.0); convolution_width];
+    kernel[0] = chirp[0].conj();
+    for n in 1..width {
+        kernel[n] = chirp[n].conj();
+        kernel[convolution_width - n] = chirp[n].conj();
+    }
+
+    cfft(&mut signal);
+    cfft(&mut kernel);
+
+    for (signal, kernel) in signal.iter_mut().zip(&kernel) {
+        *signal *= kernel;
+    }
+
+    icfft(&mut signal);
+
+    for ((sample, convolved), chirp) in samples.iter_mut().zip(signal).zip(chirp) {
+        *sample = convolved * chirp;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts;
+
+    use num_complex::Complex;
+
+    use super::{cfft, icfft, irfft, rfft};
+
+    /// The direct `O(n²)` definition of the transform
+    fn dft(samples: &[Complex<f32>]) -> Vec<Complex<f32>> {
+        let width = samples.len();
+
+        (0..width)
+            .map(|bucket| {
+                samples
+                    .iter()
+                    .enumerate()
+                    .map(|(n, sample)| {
+                        let angle = -consts::TAU * (bucket * n % width) as f64 / width as f64;
+                        let sample = Complex::new(sample.re as f64, sample.im as f64);
+
+                        sample * Complex::new(angle.cos(), angle.sin())
+                    })
+                    .sum::<Complex<f64>>()
+            })
+            .map(|bucket| Complex::new(bucket.re as f32, bucket.im as f32))
+            .collect()
+    }
+
+    fn signal(width: usize) -> Vec<f32> {
+        (0..width)
+            .map(|n| ((n * 7919) % 101) as f32 / 50.0 - 1.0)
+            .collect()
+    }
+
+    fn assert_close(actual: &[Complex<f32>], expected: &[Complex<f32>], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+
+        for (bucket, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (actual - expected).norm() <= tolerance,
+                "bucket {bucket}: {actual} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_dft() {
+        for width in [1, 2, 3, 5, 12, 64, 100, 127, 1000] {
+            let samples = signal(width)
+                .into_iter()
+                .zip(signal(width + 1).into_iter().skip(1))
+                .map(|(re, im)| Complex::new(re, im))
+                .collect::<Vec<_>>();
+
+            let mut spectrum = samples.clone();
+            cfft(&mut spectrum);
+
+            assert_close(&spectrum, &dft(&samples), 1e-3 * width as f32);
+        }
+    }
+
+    #[test]
+    fn beyond_microfft() {
+        let width = 1 << 16;
+        let samples = signal(width);
+
+        // A single large transform matches the half width transforms of the even and odd
+        // samples combined, which are handled by microfft
+        let full = rfft(&samples);
+        let evens = rfft(&samples.iter().copied().step_by(2).collect::<Vec<_>>());
+        let odds = rfft(
+            &samples
+                .iter()
+                .copied()
+                .skip(1)
+                .step_by(2)
+                .collect::<Vec<_>>(),
+        );
+
+        let combined = (0..width / 4)
+            .map(|bucket| {
+                let angle = -consts::TAU * bucket as f64 / width as f64;
+                let twiddle = Complex::new(angle.cos() as f32, angle.sin() as f32);
+
+                evens[bucket] + twiddle * odds[bucket]
+            })
+            .collect::<Vec<_>>();
+
+        assert_close(&full[..width / 4], &combined, 0.05);
+    }
+
+    #[test]
+    fn real_matches_complex() {
+        for width in [2, 7, 16, 90, 333, 4096] {
+            let samples = signal(width);
+
+            let mut spectrum = samples
+                .iter()
+                .map(|&sample| Complex::new(sample, 0.0))
+                .collect::<Vec<_>>();
+            cfft(&mut spectrum);
+
+            assert_close(
+                &rfft(&samples),
+                &spectrum[..width / 2 + 1],
+                1e-3 * width as f32,
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for width in [1, 2, 9, 48, 1024, 1000, 20_000] {
+            let samples = signal(width);
+
+            let mut spectrum = samples
+                .iter()
+                .map(|&sample| Complex::new(sample, 0.0))
+                .collect::<Vec<_>>();
+            cfft(&mut spectrum);
+            icfft(&mut spectrum);
+
+            let real = irfft(&rfft(&samples), width);
+
+            for (n, ((&sample, complex), real)) in
+                samples.iter().zip(spectrum).zip(real).enumerate()
+            {
+                assert!((complex.re - sample).abs() < 1e-3, "{width}: sample {n}");
+                assert!((real - sample).abs() < 1e-3, "{width}: sample {n}");
+            }
+        }
+    }
+}
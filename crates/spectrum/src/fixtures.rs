@@ -0,0 +1,20 @@
+//! Waveforms shared by the tests
+
+use audio::waveform::Waveform;
+
+/// Sine waves of the given frequencies and amplitudes played together
+pub fn chord(sines: &[(f32, f32)], duration: f32, sample_rate: u32) -> Waveform<'static> {
+    let mut samples = Vec::<f32>::new();
+
+    for &(frequency, amplitude) in sines {
+        let sine = Waveform::sine_wave(frequency, duration, sample_rate).into_samples();
+
+        samples.resize(samples.len().max(sine.len()), 0.0);
+        samples
+            .iter_mut()
+            .zip(sine)
+            .for_each(|(sum, sample)| *sum += sample * amplitude);
+    }
+
+    Waveform::new(samples, sample_rate)
+}
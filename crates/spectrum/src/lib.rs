@@ -2,20 +2,37 @@
 #![deny(clippy::unwrap_used, clippy::expect_used)]
 #![warn(missing_copy_implementations, missing_debug_implementations)]
 
-use std::{
-    cmp::Ordering,
-    f32::consts,
-    fmt::{self, Display},
-    iter,
-    ops::Range,
-};
+use std::{cmp::Ordering, f32::consts, iter};
 
 use audio::waveform::Waveform;
 pub use num_complex::Complex;
 
-mod fft;
-
-use fft::cfft;
+pub mod channel_vocoder;
+pub mod chroma;
+pub mod constant_q;
+pub mod fft;
+pub mod onset;
+pub mod peaks;
+pub mod phase_vocoder;
+pub mod pitch;
+pub mod stft;
+pub mod welch;
+pub mod window;
+
+#[cfg(test)]
+mod fixtures;
+
+pub use channel_vocoder::{ChannelVocoder, ChannelVocoderOptions};
+pub use chroma::{Chroma, ChromaOptions, PITCH_CLASSES};
+pub use constant_q::{ConstantQ, ConstantQOptions};
+use fft::{cfft, icfft, rfft};
+pub use onset::{Onset, OnsetDetector, OnsetFunction, OnsetOptions};
+pub use peaks::{Peak, PeakInterpolation, PeakOptions};
+pub use phase_vocoder::PhaseVocoder;
+pub use pitch::{Pitch, PitchAlgorithm, PitchOptions, PitchTracker};
+pub use stft::{Istft, Stft};
+pub use welch::PowerSpectralDensity;
+pub use window::{Symmetry, Window, WindowIter, WindowProperties};
 
 // pub fn pitch_change(samples: &[f32])
 
@@ -138,11 +155,51 @@ pub fn scale_spectrum(
     }
 }
 
+/// Magnitudes at or below this are shown as this level, instead of minus infinity
+pub const MIN_DBFS: f32 = -200.0;
+
+/// Convert a linear magnitude, where 1 is full scale, to decibels
+pub fn dbfs(magnitude: f32) -> f32 {
+    (20.0 * magnitude.log10()).max(MIN_DBFS)
+}
+
+/// Convert decibels relative to full scale to a linear magnitude
+pub fn from_dbfs(dbfs: f32) -> f32 {
+    10f32.powf(dbfs / 20.0)
+}
+
+/// Add or remove whole turns from each phase, so that no two consecutive phases are more than
+/// π apart
+pub fn unwrap_phases(phases: impl IntoIterator<Item = f32>) -> Vec<f32> {
+    let mut offset = 0.0;
+    let mut previous = None;
+
+    phases
+        .into_iter()
+        .map(|phase| {
+            if let Some(previous) = previous {
+                let jump: f32 = phase - previous;
+
+                offset -= consts::TAU * (jump / consts::TAU).round();
+            }
+            previous = Some(phase);
+
+            phase + offset
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct Spectrum<'waveform> {
     width: usize,
+    window: Window,
+    /// The sum of the window coefficients, which is the amplitude of a bucket holding a
+    /// sinusoid of amplitude 2
+    window_sum: f32,
     buckets: Box<[Complex<f32>]>,
     waveform: &'waveform Waveform<'waveform>,
+    /// The channel of the waveform that was transformed, `None` for the downmix of all channels
+    channel: Option<u16>,
 }
 
 impl<'w> Spectrum<'w> {
@@ -150,18 +207,32 @@ impl<'w> Spectrum<'w> {
         self.width
     }
 
+    /// The window the samples were multiplied with before the transform
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
     pub fn buckets(&self) -> &[Complex<f32>] {
         &self.buckets
     }
 
+    /// The buckets of the non-negative frequencies, which fully describe a real signal
+    pub fn buckets_real(&self) -> &[Complex<f32>] {
+        &self.buckets[..self.width / 2 + 1]
+    }
+
+    /// The unnormalized magnitudes of the buckets, which grow with the window width
+    ///
+    /// See [`magnitudes`](Self::magnitudes) for values that do not depend on the window.
     pub fn amplitudes(&self) -> impl Iterator<Item = f32> + '_ {
         self.buckets.iter().map(|complex| complex.norm())
     }
 
+    /// The phases of the buckets in radians, from -π to π
+    ///
+    /// See [`unwrap_phases`] to remove the jumps between -π and π.
     pub fn phases(&self) -> impl Iterator<Item = f32> + '_ {
-        self.buckets
-            .iter()
-            .map(|complex| complex.arg() / self.width as f32)
+        self.buckets.iter().map(|complex| complex.arg())
     }
 
     pub fn amplitudes_real(&self) -> impl Iterator<Item = f32> + '_ {
@@ -172,6 +243,50 @@ impl<'w> Spectrum<'w> {
         self.phases().take(self.width / 2 + 1)
     }
 
+    /// The magnitudes of all buckets, corrected for the gain of the window
+    ///
+    /// A sinusoid in the center of a bucket is split evenly between its positive and negative
+    /// frequency, so each of them reads half of its amplitude.
+    pub fn magnitudes(&self) -> impl Iterator<Item = f32> + '_ {
+        let scale = if self.window_sum > 0.0 {
+            1.0 / self.window_sum
+        } else {
+            0.0
+        };
+
+        self.amplitudes().map(move |amplitude| amplitude * scale)
+    }
+
+    /// The magnitudes of the non-negative frequencies, corrected for the gain of the window
+    ///
+    /// The negative frequencies are folded onto the positive ones, so a sinusoid in the center
+    /// of a bucket reads its amplitude, and a full scale sine wave reads 1.
+    pub fn magnitudes_real(&self) -> impl Iterator<Item = f32> + '_ {
+        let width = self.width;
+
+        self.magnitudes()
+            .take(width / 2 + 1)
+            .enumerate()
+            .map(move |(bucket, magnitude)| {
+                // DC and nyquist have no mirror image
+                if bucket == 0 || bucket * 2 == width {
+                    magnitude
+                } else {
+                    2.0 * magnitude
+                }
+            })
+    }
+
+    /// The [`magnitudes`](Self::magnitudes) of all buckets in decibels relative to full scale
+    pub fn dbfs(&self) -> impl Iterator<Item = f32> + '_ {
+        self.magnitudes().map(dbfs)
+    }
+
+    /// The [`magnitudes_real`](Self::magnitudes_real) in decibels relative to full scale
+    pub fn dbfs_real(&self) -> impl Iterator<Item = f32> + '_ {
+        self.magnitudes_real().map(dbfs)
+    }
+
     // TODO: rename?
     pub fn main_frequency(&self) -> Option<(usize, f32)> {
         self.amplitudes_real()
@@ -212,7 +327,10 @@ impl<'w> Spectrum<'w> {
 
         Spectrum {
             width: self.width,
+            window: self.window,
+            window_sum: self.window_sum,
             waveform: self.waveform,
+            channel: self.channel,
             buckets: iter::repeat(Complex::new(0.0, 0.0))
                 .take(shift)
                 .chain(self.buckets[..(half_spectrum - shift)].iter().copied())
@@ -224,19 +342,12 @@ impl<'w> Spectrum<'w> {
 
     #[must_use]
     pub fn waveform(&self) -> Waveform<'static> {
-        let mut spectrum = self
-            .buckets
-            .iter()
-            .map(|complex| Complex::new(complex.im, complex.re))
-            .collect::<Vec<_>>();
+        let mut spectrum = self.buckets.to_vec();
 
-        cfft(&mut spectrum);
+        icfft(&mut spectrum);
 
         Waveform::new(
-            spectrum
-                .into_iter()
-                .map(|complex| complex.im / self.width as f32)
-                .collect(),
+            spectrum.into_iter().map(|complex| complex.re).collect(),
             self.waveform.sample_rate(),
         )
     }
@@ -249,99 +360,156 @@ mod sealed {
 impl<'w> sealed::Sealed for Waveform<'w> {}
 
 pub trait WaveformSpectrum: sealed::Sealed {
+    /// The spectrum of the average of all channels
+    ///
+    /// Any width is supported, but powers of two are the fastest
     #[must_use]
     fn spectrum(&self, window: Window, fft_width: usize) -> Spectrum;
+
+    /// The spectrum of a single channel, see [`spectrum`](Self::spectrum)
+    #[must_use]
+    fn spectrum_channel(&self, channel: u16, window: Window, fft_width: usize) -> Spectrum;
 }
 
 impl<'w> WaveformSpectrum for Waveform<'w> {
-    // TODO: see if rfft would be worth using unsafe for over cfft
     #[must_use]
     fn spectrum(&self, window: Window, fft_width: usize) -> Spectrum {
-        assert!(
-            self.len() <= fft_width,
-            "{} is too many samples for a fft of width {fft_width}",
-            self.len()
-        );
-        assert!(
-            fft_width.is_power_of_two(),
-            "fft width length must be a power of two"
-        );
-
-        let window = window.into_iter(self.len());
-
-        // Copy samples into the spectrum, filling any extra space with zeros
-        let mut buckets = self
-            .samples_iter()
-            .zip(window)
-            .map(|(sample, scale)| Complex::new(sample * scale, 0.0))
-            .chain(iter::repeat(Complex::new(0.0, 0.0)))
-            .take(fft_width)
-            .collect::<Box<_>>();
-
-        // Perform the FFT based on the calculated width
-        cfft(&mut buckets);
+        spectrum_of(self, None, window, fft_width)
+    }
 
-        Spectrum {
-            buckets,
-            width: fft_width,
-            waveform: self,
-        }
+    fn spectrum_channel(&self, channel: u16, window: Window, fft_width: usize) -> Spectrum {
+        spectrum_of(self, Some(channel), window, fft_width)
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Window {
-    #[doc(alias = "Triangular")]
-    Bartlett,
-    Hamming,
-    /// Good default choice
-    Hann,
-    Rectangular,
+/// The samples of a channel of the waveform, or of the downmix of all channels
+pub(crate) fn channel_samples(waveform: &Waveform<'_>, channel: Option<u16>) -> Vec<f32> {
+    match channel {
+        Some(channel) => waveform.channel(channel).collect(),
+        None => waveform.downmix_iter().collect(),
+    }
 }
 
-impl Display for Window {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+fn spectrum_of<'w>(
+    waveform: &'w Waveform<'w>,
+    channel: Option<u16>,
+    window: Window,
+    fft_width: usize,
+) -> Spectrum<'w> {
+    assert!(
+        waveform.len() <= fft_width,
+        "{} is too many samples for a fft of width {fft_width}",
+        waveform.len()
+    );
+
+    let coefficients = window.into_iter(waveform.len());
+
+    // Copy samples into the spectrum, filling any extra space with zeros
+    let samples = channel_samples(waveform, channel)
+        .into_iter()
+        .zip(coefficients)
+        .map(|(sample, scale)| sample * scale)
+        .chain(iter::repeat(0.0))
+        .take(fft_width)
+        .collect::<Vec<_>>();
+
+    let half_spectrum = rfft(&samples);
+
+    // The negative frequencies of a real signal mirror the positive ones
+    let buckets = half_spectrum
+        .iter()
+        .copied()
+        .chain(
+            half_spectrum[1..fft_width.div_ceil(2)]
+                .iter()
+                .rev()
+                .map(Complex::conj),
+        )
+        .collect::<Box<_>>();
+
+    Spectrum {
+        buckets,
+        width: fft_width,
+        window,
+        window_sum: window.into_iter(waveform.len()).sum(),
+        waveform,
+        channel,
     }
 }
 
-impl Window {
-    pub const ALL: [Window; 4] = [Self::Bartlett, Self::Hamming, Self::Hann, Self::Rectangular];
+#[cfg(test)]
+mod test {
+    use std::f32::consts::{PI, TAU};
+
+    use audio::waveform::Waveform;
+
+    use crate::{dbfs, unwrap_phases, Spectrum, WaveformSpectrum, Window};
+
+    #[test]
+    fn calibrated_magnitudes() {
+        // 1000 Hz lies in the center of bucket 40 of a 1764 sample transform at 44.1 kHz
+        let samples = Waveform::sine_wave(1000.0, 0.04, 44_100)
+            .into_samples()
+            .into_iter()
+            .map(|sample| sample * 0.5)
+            .collect();
+        let waveform = Waveform::new(samples, 44_100);
 
-    pub fn into_iter(self, width: usize) -> WindowIter {
-        WindowIter {
-            range: 0..width,
-            width,
-            window: self,
+        for window in [Window::Rectangular, Window::Hann, Window::FlatTop] {
+            let spectrum = waveform.spectrum(window, 1764);
+            let magnitude = spectrum.magnitudes_real().nth(40).unwrap_or_default();
+            let level = spectrum.dbfs_real().nth(40).unwrap_or_default();
+
+            assert!((magnitude - 0.5).abs() < 1e-3, "{window}: {magnitude}");
+            assert!((level - dbfs(0.5)).abs() < 0.01, "{window}: {level}");
+            assert!((spectrum.magnitudes().nth(40).unwrap_or_default() - 0.25).abs() < 1e-3);
         }
     }
-}
 
-#[derive(Debug)]
-pub struct WindowIter {
-    range: Range<usize>,
-    width: usize,
-    window: Window,
-}
+    #[test]
+    fn channels() {
+        let waveform = Waveform::from_planar(
+            vec![
+                Waveform::sine_wave(440.0, 0.1, 44_100).into_samples(),
+                Waveform::sine_wave(1000.0, 0.1, 44_100).into_samples(),
+            ],
+            44_100,
+        );
+        let main_frequency = |spectrum: Spectrum| {
+            spectrum
+                .main_frequency()
+                .map_or(0.0, |(bucket, _)| spectrum.freq_from_bucket(bucket).round())
+        };
 
-impl Iterator for WindowIter {
-    type Item = f32;
+        assert_eq!(
+            main_frequency(waveform.spectrum_channel(0, Window::Hann, 44_100)),
+            440.0
+        );
+        assert_eq!(
+            main_frequency(waveform.spectrum_channel(1, Window::Hann, 44_100)),
+            1000.0
+        );
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(n) = self.range.next() {
-            let n = n as f32;
-            let width = self.width as f32;
+        // Both tones are in the downmix at half their level
+        let spectrum = waveform.spectrum(Window::Hann, 44_100);
+        for frequency in [440, 1000] {
+            let magnitude = spectrum
+                .magnitudes_real()
+                .nth(frequency)
+                .unwrap_or_default();
 
-            Some(match self.window {
-                Window::Rectangular => 1.0,
-                Window::Bartlett => 1.0 - f32::abs((n - width / 2.0) / (width / 2.0)),
-                Window::Hann => 0.5 * (1.0 - f32::cos((consts::TAU * n) / width)),
-                Window::Hamming => {
-                    (25.0 / 46.0) - ((21.0 / 46.0) * f32::cos((consts::TAU * n) / width))
-                }
-            })
-        } else {
-            None
+            assert!((magnitude - 0.5).abs() < 0.01, "{frequency}: {magnitude}");
+        }
+    }
+
+    #[test]
+    fn phase_unwrapping() {
+        // A phase that steadily advances by 1 radian, wrapped into -π..π
+        let phases = (0..20).map(|n| (n as f32 + PI).rem_euclid(TAU) - PI);
+        let unwrapped = unwrap_phases(phases);
+
+        for (n, phase) in unwrapped.iter().enumerate() {
+            assert!((phase - n as f32).abs() < 1e-4, "{n}: {phase}");
         }
     }
 }
@@ -0,0 +1,281 @@
+//! Onset detection, finding the moments at which notes begin
+//!
+//! Every frame of a short-time fourier transform is reduced to a single value by an onset
+//! detection function, which is high where the frame changes from the frames before it.
+//! [`OnsetFunction::SpectralFlux`] sums how much the magnitude of each bucket grew,
+//! [`OnsetFunction::ComplexDomain`] measures how far each growing bucket is from where a
+//! steady sinusoid would have continued, which also catches soft onsets that mostly change the
+//! phase, and [`OnsetFunction::HighFrequencyContent`] weights the energy of each bucket by its
+//! frequency, which favours percussive attacks. Onsets are the peaks of the normalized function
+//! that rise above its moving median by a threshold.
+
+use std::{
+    f32::consts::{PI, TAU},
+    fmt::{self, Display},
+};
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::{Stft, Window};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnsetFunction {
+    SpectralFlux,
+    ComplexDomain,
+    #[doc(alias = "HFC")]
+    HighFrequencyContent,
+}
+
+impl Display for OnsetFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpectralFlux => write!(f, "Spectral Flux"),
+            Self::ComplexDomain => write!(f, "Complex Domain"),
+            Self::HighFrequencyContent => write!(f, "HFC"),
+        }
+    }
+}
+
+impl OnsetFunction {
+    pub const ALL: [OnsetFunction; 3] = [
+        Self::SpectralFlux,
+        Self::ComplexDomain,
+        Self::HighFrequencyContent,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OnsetOptions {
+    pub function: OnsetFunction,
+    /// The amount of samples in each frame
+    pub frame_width: usize,
+    /// The amount of samples between the starts of consecutive frames
+    pub hop: usize,
+    /// How far the detection function, normalized to a maximum of 1, has to rise above its
+    /// moving median to be an onset
+    pub threshold: f32,
+    /// The amount of frames on either side of a frame that its moving median is taken over
+    pub median_frames: usize,
+    /// Onsets closer than this to the onset before them are dropped, in seconds
+    pub min_interval: f32,
+}
+
+impl Default for OnsetOptions {
+    fn default() -> Self {
+        Self {
+            function: OnsetFunction::SpectralFlux,
+            frame_width: 2048,
+            hop: 512,
+            threshold: 0.1,
+            median_frames: 8,
+            min_interval: 0.05,
+        }
+    }
+}
+
+/// The start of a note, see [`OnsetDetector::detect`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onset {
+    /// The center of the frame the onset was found in, in seconds
+    pub time: f32,
+    /// The normalized detection function at the onset, at most 1
+    pub strength: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct OnsetDetector {
+    options: OnsetOptions,
+    stft: Stft,
+}
+
+impl OnsetDetector {
+    pub fn new(options: OnsetOptions) -> Self {
+        assert!(
+            (1..=options.frame_width).contains(&options.hop),
+            "the hop must be between 1 and the frame width"
+        );
+
+        Self {
+            options,
+            stft: Stft::new(
+                Window::Hann,
+                options.frame_width,
+                options.frame_width,
+                options.hop,
+            ),
+        }
+    }
+
+    pub fn options(&self) -> OnsetOptions {
+        self.options
+    }
+
+    /// The center of the given frame in seconds, which is never before the first sample
+    pub fn frame_time(&self, frame: usize, sample_rate: u32) -> f32 {
+        let center = self.stft.frame_start(frame) + self.options.frame_width as isize / 2;
+
+        center.max(0) as f32 / sample_rate as f32
+    }
+
+    /// The onset detection function of every frame of the waveform, downmixed to a single
+    /// channel
+    pub fn detection_function(&self, waveform: &Waveform<'_>) -> Vec<f32> {
+        let buckets = self.stft.buckets();
+
+        // The first frames are compared to silence
+        let mut previous = vec![Complex::<f32>::default(); buckets];
+        let mut before_previous = vec![Complex::<f32>::default(); buckets];
+
+        self.stft
+            .frames(waveform)
+            .map(|frame| {
+                let value = match self.options.function {
+                    OnsetFunction::SpectralFlux => frame
+                        .iter()
+                        .zip(&previous)
+                        .map(|(bucket, previous)| (bucket.norm() - previous.norm()).max(0.0))
+                        .sum(),
+                    OnsetFunction::ComplexDomain => frame
+                        .iter()
+                        .zip(previous.iter().zip(&before_previous))
+                        .filter(|(bucket, (previous, _))| bucket.norm() >= previous.norm())
+                        .map(|(bucket, (previous, before_previous))| {
+                            // A steady sinusoid keeps its magnitude and advances its phase by
+                            // the same angle every frame
+                            let advance = previous.arg() - before_previous.arg();
+                            let phase = previous.arg() + (advance + PI).rem_euclid(TAU) - PI;
+
+                            (bucket - Complex::from_polar(previous.norm(), phase)).norm()
+                        })
+                        .sum(),
+                    OnsetFunction::HighFrequencyContent => {
+                        frame
+                            .iter()
+                            .enumerate()
+                            .map(|(bucket, value)| bucket as f32 * value.norm_sqr())
+                            .sum::<f32>()
+                            / buckets as f32
+                    }
+                };
+
+                before_previous = std::mem::replace(&mut previous, frame);
+
+                value
+            })
+            .collect()
+    }
+
+    /// Find the onsets of the waveform, from the first to the last
+    pub fn detect(&self, waveform: &Waveform<'_>) -> Vec<Onset> {
+        let function = self.detection_function(waveform);
+
+        let max = function.iter().copied().fold(0.0, f32::max);
+        if max <= f32::EPSILON {
+            return Vec::new();
+        }
+        let normalized = function.iter().map(|value| value / max).collect::<Vec<_>>();
+
+        let mut onsets = Vec::<Onset>::new();
+        for frame in 0..normalized.len() {
+            let value = normalized[frame];
+            let left = frame.checked_sub(1).map_or(0.0, |left| normalized[left]);
+            let right = normalized.get(frame + 1).copied().unwrap_or(0.0);
+
+            if value <= left || value < right {
+                continue;
+            }
+
+            let neighbourhood = &normalized[frame.saturating_sub(self.options.median_frames)
+                ..(frame + self.options.median_frames + 1).min(normalized.len())];
+            if value < median(neighbourhood) + self.options.threshold {
+                continue;
+            }
+
+            let time = self.frame_time(frame, waveform.sample_rate());
+            if onsets
+                .last()
+                .is_some_and(|onset| time - onset.time < self.options.min_interval)
+            {
+                continue;
+            }
+
+            onsets.push(Onset {
+                time,
+                strength: value,
+            });
+        }
+
+        onsets
+    }
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+
+    match sorted.len() {
+        0 => 0.0,
+        len if len % 2 == 0 => (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0,
+        len => sorted[len / 2],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{OnsetDetector, OnsetFunction, OnsetOptions};
+
+    /// Decaying tones like plucked strings, starting at the given times
+    fn plucks(notes: &[(f32, f32)], duration: f32) -> Waveform<'static> {
+        let sample_rate = 44_100;
+        let mut samples = vec![0.0; (duration * sample_rate as f32) as usize];
+
+        for &(start, frequency) in notes {
+            let first = (start * sample_rate as f32) as usize;
+            let tone = Waveform::sine_wave(frequency, duration - start, sample_rate);
+
+            for (n, (sample, tone)) in samples[first..].iter_mut().zip(tone.samples()).enumerate() {
+                let time = n as f32 / sample_rate as f32;
+                *sample += 0.5 * tone * (-time * 8.0).exp();
+            }
+        }
+
+        Waveform::new(samples, sample_rate)
+    }
+
+    #[test]
+    fn plucked_notes() {
+        // The same note twice in a row, then a higher one while the second still rings
+        let starts = [0.1, 0.4, 0.55];
+        let waveform = plucks(&[(0.1, 440.0), (0.4, 440.0), (0.55, 660.0)], 1.0);
+
+        for function in OnsetFunction::ALL {
+            let detector = OnsetDetector::new(OnsetOptions {
+                function,
+                ..Default::default()
+            });
+            let onsets = detector.detect(&waveform);
+
+            assert_eq!(onsets.len(), starts.len(), "{function}: {onsets:?}");
+            for (onset, start) in onsets.iter().zip(starts) {
+                assert!((onset.time - start).abs() < 0.03, "{function}: {onsets:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn silence() {
+        let waveform = Waveform::new(vec![0.0; 44_100], 44_100);
+
+        for function in OnsetFunction::ALL {
+            let detector = OnsetDetector::new(OnsetOptions {
+                function,
+                ..Default::default()
+            });
+
+            assert!(detector.detect(&waveform).is_empty(), "{function}");
+        }
+    }
+}
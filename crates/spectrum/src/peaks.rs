@@ -0,0 +1,277 @@
+//! Finding the peaks of a spectrum more precisely than its buckets
+//!
+//! A sinusoid between two bucket centers spreads over several buckets, so the frequency of its
+//! loudest bucket is off by up to half a bucket. Peaks can be refined by fitting a parabola
+//! through the magnitudes around each maximum, or through their logarithms, which exactly fits
+//! the main lobe of a Gaussian window and closely fits most others. The phase vocoder method
+//! instead measures how far the phase of the peak advances from one sample to the next.
+
+use std::f32::consts::{PI, TAU};
+use std::fmt::{self, Display};
+
+use num_complex::Complex;
+
+use crate::{channel_samples, fft::rfft, Spectrum, Window};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeakInterpolation {
+    /// The center frequency and magnitude of the loudest bucket
+    None,
+    /// A parabola through the magnitudes of the loudest bucket and its neighbours
+    Quadratic,
+    /// A parabola through the logarithms of the magnitudes of the loudest bucket and its
+    /// neighbours
+    Gaussian,
+    /// The phase difference of the loudest bucket between transforms of the samples shifted by
+    /// one sample, with the magnitude interpolated like [`Gaussian`](Self::Gaussian)
+    PhaseVocoder,
+}
+
+impl Display for PeakInterpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PhaseVocoder => write!(f, "Phase Vocoder"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl PeakInterpolation {
+    pub const ALL: [PeakInterpolation; 4] = [
+        Self::None,
+        Self::Quadratic,
+        Self::Gaussian,
+        Self::PhaseVocoder,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakOptions {
+    pub interpolation: PeakInterpolation,
+    /// Buckets with a lower magnitude than this are never peaks, see
+    /// [`Spectrum::magnitudes_real`]
+    pub threshold: f32,
+    /// Peaks closer than this to a louder peak are dropped, in Hz
+    pub min_distance: f64,
+    /// Keep at most this many of the loudest peaks
+    pub max_peaks: Option<usize>,
+}
+
+impl Default for PeakOptions {
+    fn default() -> Self {
+        Self {
+            interpolation: PeakInterpolation::Gaussian,
+            threshold: 0.0,
+            min_distance: 0.0,
+            max_peaks: None,
+        }
+    }
+}
+
+/// A local maximum of a spectrum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    /// The loudest bucket of the peak
+    pub bucket: usize,
+    /// The interpolated frequency in Hz
+    pub frequency: f64,
+    /// The interpolated amplitude, on the same scale as [`Spectrum::magnitudes_real`]
+    pub amplitude: f32,
+}
+
+impl Spectrum<'_> {
+    /// The local maxima of the non-negative frequencies, from the lowest to the highest
+    /// frequency
+    pub fn peaks(&self, options: PeakOptions) -> Vec<Peak> {
+        let amplitudes = self.magnitudes_real().collect::<Vec<_>>();
+
+        let maxima = (0..amplitudes.len()).filter(|&bucket| {
+            let left = bucket.checked_sub(1).map_or(0.0, |left| amplitudes[left]);
+            let right = amplitudes.get(bucket + 1).copied().unwrap_or(0.0);
+            let amplitude = amplitudes[bucket];
+
+            amplitude >= options.threshold && amplitude > left && amplitude >= right
+        });
+
+        let shifted = match options.interpolation {
+            PeakInterpolation::PhaseVocoder => self.shifted_transforms(),
+            _ => None,
+        };
+
+        let mut peaks = maxima
+            .map(|bucket| {
+                let neighbours = bucket
+                    .checked_sub(1)
+                    .zip(amplitudes.get(bucket + 1))
+                    .map(|(left, &right)| (amplitudes[left], right));
+                let amplitude = amplitudes[bucket];
+
+                let (offset, amplitude) = match (options.interpolation, neighbours) {
+                    (PeakInterpolation::None, _) | (_, None) => (0.0, amplitude),
+                    (PeakInterpolation::Quadratic, Some((left, right))) => {
+                        vertex(left, amplitude, right)
+                    }
+                    (PeakInterpolation::Gaussian, Some((left, right)))
+                    | (PeakInterpolation::PhaseVocoder, Some((left, right))) => {
+                        let ln = |amplitude: f32| amplitude.max(f32::MIN_POSITIVE).ln();
+                        let (offset, ln_amplitude) = vertex(ln(left), ln(amplitude), ln(right));
+
+                        (offset, ln_amplitude.exp())
+                    }
+                };
+
+                let frequency = match &shifted {
+                    Some([current, next]) => {
+                        // The phase advances by the angular frequency every sample
+                        let advance = next[bucket].arg() - current[bucket].arg();
+                        let frequency = ((advance + PI).rem_euclid(TAU) - PI).abs() / TAU;
+
+                        frequency as f64 * self.waveform.sample_rate() as f64
+                    }
+                    None => (bucket as f64 + offset as f64) * self.freq_resolution(),
+                };
+
+                Peak {
+                    bucket,
+                    frequency,
+                    amplitude,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Keep the loudest peaks that are far enough from the peaks kept before them
+        peaks.sort_by(|a, b| b.amplitude.total_cmp(&a.amplitude));
+
+        let mut kept = Vec::<Peak>::new();
+        for peak in peaks {
+            if options.max_peaks.is_some_and(|max| kept.len() >= max) {
+                break;
+            }
+
+            if kept
+                .iter()
+                .all(|kept| (kept.frequency - peak.frequency).abs() >= options.min_distance)
+            {
+                kept.push(peak);
+            }
+        }
+
+        kept.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+
+        kept
+    }
+
+    /// Transform the samples without their last and without their first sample, with the same
+    /// window and width as this spectrum
+    fn shifted_transforms(&self) -> Option<[Vec<Complex<f32>>; 2]> {
+        let samples = channel_samples(self.waveform, self.channel);
+
+        if samples.len() < 2 {
+            return None;
+        }
+
+        let transform = |samples: &[f32]| {
+            let mut windowed = samples
+                .iter()
+                .zip(Window::into_iter(self.window, samples.len()))
+                .map(|(sample, coefficient)| sample * coefficient)
+                .collect::<Vec<_>>();
+            windowed.resize(self.width, 0.0);
+
+            rfft(&windowed)
+        };
+
+        Some([
+            transform(&samples[..samples.len() - 1]),
+            transform(&samples[1..]),
+        ])
+    }
+}
+
+/// The offset from the center to the vertex of the parabola through three evenly spaced
+/// values, within half a step, and the value at that vertex
+pub(crate) fn vertex(left: f32, center: f32, right: f32) -> (f32, f32) {
+    let curvature = left - 2.0 * center + right;
+    if curvature.abs() <= f32::EPSILON {
+        return (0.0, center);
+    }
+
+    let offset = (0.5 * (left - right) / curvature).clamp(-0.5, 0.5);
+
+    (offset, center - 0.25 * (left - right) * offset)
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{PeakInterpolation, PeakOptions};
+    use crate::{fixtures, WaveformSpectrum, Window};
+
+    #[test]
+    fn interpolation() {
+        // Middle C lies between buckets 12 and 13 of a 2048 sample transform
+        let waveform = Waveform::sine_wave(261.63, 2048.0 / 44_100.0, 44_100);
+        let spectrum = waveform.spectrum(Window::Hann, 2048);
+
+        for (interpolation, tolerance) in [
+            (PeakInterpolation::None, 21.6 / 2.0),
+            (PeakInterpolation::Quadratic, 2.0),
+            (PeakInterpolation::Gaussian, 0.5),
+            (PeakInterpolation::PhaseVocoder, 0.5),
+        ] {
+            let peaks = spectrum.peaks(PeakOptions {
+                interpolation,
+                threshold: 0.1,
+                ..Default::default()
+            });
+
+            assert_eq!(peaks.len(), 1, "{interpolation}: {peaks:?}");
+            assert!(
+                (peaks[0].frequency - 261.63).abs() < tolerance,
+                "{interpolation}: {}",
+                peaks[0].frequency
+            );
+        }
+    }
+
+    #[test]
+    fn distance_and_count() {
+        let waveform = fixtures::chord(
+            &[(440.0, 1.0), (520.0, 0.5), (1000.0, 0.8), (3000.0, 0.3)],
+            0.2,
+            44_100,
+        );
+        let spectrum = waveform.spectrum(Window::Hann, 16384);
+
+        let frequencies = |options: PeakOptions| {
+            spectrum
+                .peaks(PeakOptions {
+                    threshold: 0.1,
+                    ..options
+                })
+                .iter()
+                .map(|peak| peak.frequency.round())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            frequencies(PeakOptions::default()),
+            [440.0, 520.0, 1000.0, 3000.0]
+        );
+        assert_eq!(
+            frequencies(PeakOptions {
+                min_distance: 100.0,
+                ..Default::default()
+            }),
+            [440.0, 1000.0, 3000.0]
+        );
+        assert_eq!(
+            frequencies(PeakOptions {
+                max_peaks: Some(2),
+                ..Default::default()
+            }),
+            [440.0, 1000.0]
+        );
+    }
+}
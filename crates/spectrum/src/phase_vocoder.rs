@@ -0,0 +1,348 @@
+//! Time stretching and pitch shifting that keep the phases of the frames coherent
+//!
+//! The waveform is read with an [`Stft`] at positions spaced by the stretch factor, and the
+//! phase of every bucket is advanced by its measured instantaneous frequency before the frames
+//! are overlap-added at the regular hop. Pitch shifting stretches the waveform and resamples it
+//! back to its original length.
+
+use std::f32::consts::{PI, TAU};
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::{Stft, Window};
+
+/// Frames whose magnitudes rise by more than this fraction of the previous frame are transients
+const DEFAULT_TRANSIENT_THRESHOLD: f32 = 1.0;
+
+#[derive(Debug, Clone)]
+pub struct PhaseVocoder {
+    stft: Stft,
+    phase_locking: bool,
+    transient_threshold: Option<f32>,
+}
+
+impl PhaseVocoder {
+    /// Frames are `window_width` samples long and placed `hop` samples apart in the output,
+    /// see [`Stft::new`]
+    pub fn new(window: Window, window_width: usize, fft_width: usize, hop: usize) -> Self {
+        Self {
+            stft: Stft::new(window, window_width, fft_width, hop),
+            phase_locking: true,
+            transient_threshold: Some(DEFAULT_TRANSIENT_THRESHOLD),
+        }
+    }
+
+    /// Lock the phases of the buckets around each spectral peak to the phase of the peak,
+    /// which keeps the shape of each partial and reduces the phasiness of the output
+    ///
+    /// Enabled by default.
+    #[must_use]
+    pub fn phase_locking(mut self, phase_locking: bool) -> Self {
+        self.phase_locking = phase_locking;
+        self
+    }
+
+    /// Reset the phases to those of the input at frames where the magnitudes rise by more than
+    /// `threshold` times the magnitudes of the previous frame, keeping transients sharp
+    ///
+    /// `None` never resets the phases.
+    #[must_use]
+    pub fn transient_threshold(mut self, threshold: Option<f32>) -> Self {
+        self.transient_threshold = threshold;
+        self
+    }
+
+    pub fn stft(&self) -> &Stft {
+        &self.stft
+    }
+
+    /// Change the duration of the waveform by `factor` without changing its pitch
+    ///
+    /// The waveform is downmixed to a single channel.
+    pub fn time_stretch(&self, waveform: &Waveform<'_>, factor: f64) -> Waveform<'static> {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "the stretch factor must be positive"
+        );
+
+        let samples = waveform.downmix_iter().collect::<Vec<_>>();
+        let output_len = (samples.len() as f64 * factor).round() as usize;
+
+        let stft = &self.stft;
+        let half_window = stft.window_width() as isize / 2;
+
+        let mut state = State::new(stft.buckets(), stft.fft_width());
+        let mut istft = stft.inverse();
+
+        for frame in 0..stft.frame_count(output_len) {
+            // Read the input around the position that the center of this frame maps to
+            let center = stft.frame_start(frame) + half_window;
+            let start = (center as f64 / factor).round() as isize - half_window;
+
+            let buckets = stft.frame_at(&samples, start);
+            let output = state.advance(
+                &buckets,
+                start,
+                stft.hop(),
+                self.phase_locking,
+                self.transient_threshold,
+            );
+
+            istft.push(&output);
+        }
+
+        istft.finish(output_len, waveform.sample_rate())
+    }
+
+    /// Change the pitch of the waveform by the given amount of semitones without changing its
+    /// duration
+    pub fn pitch_shift(&self, waveform: &Waveform<'_>, semitones: f64) -> Waveform<'static> {
+        self.pitch_shift_by(waveform, semitone_ratio(semitones))
+    }
+
+    /// Multiply every frequency in the waveform by `ratio` without changing its duration
+    pub fn pitch_shift_by(&self, waveform: &Waveform<'_>, ratio: f64) -> Waveform<'static> {
+        let sample_rate = waveform.sample_rate();
+        let stretched = self.time_stretch(waveform, ratio);
+
+        // Playing the stretched waveform faster by the same ratio restores its duration
+        let mut samples = Waveform::new(
+            stretched.into_samples(),
+            (sample_rate as f64 * ratio).round() as u32,
+        )
+        .resample(sample_rate)
+        .into_samples();
+
+        samples.resize(waveform.len(), 0.0);
+
+        Waveform::new(samples, sample_rate)
+    }
+}
+
+/// The frequency ratio of an interval of the given amount of semitones
+pub fn semitone_ratio(semitones: f64) -> f64 {
+    2f64.powf(semitones / 12.0)
+}
+
+/// What is carried over from one frame to the next
+struct State {
+    fft_width: usize,
+
+    /// The start of the last analysis frame, if there was one
+    previous_start: Option<isize>,
+    previous_phases: Vec<f32>,
+    previous_magnitudes: Vec<f32>,
+    /// The instantaneous frequency of each bucket, in radians per sample
+    frequencies: Vec<f32>,
+    synthesis_phases: Vec<f32>,
+}
+
+impl State {
+    fn new(buckets: usize, fft_width: usize) -> Self {
+        Self {
+            fft_width,
+
+            previous_start: None,
+            previous_phases: vec![0.0; buckets],
+            previous_magnitudes: vec![0.0; buckets],
+            frequencies: (0..buckets)
+                .map(|bucket| bucket_frequency(bucket, fft_width))
+                .collect(),
+            synthesis_phases: vec![0.0; buckets],
+        }
+    }
+
+    /// Take the analysis frame starting at `start` and return the synthesis frame that
+    /// follows the last one by `hop` samples
+    fn advance(
+        &mut self,
+        buckets: &[Complex<f32>],
+        start: isize,
+        hop: usize,
+        phase_locking: bool,
+        transient_threshold: Option<f32>,
+    ) -> Vec<Complex<f32>> {
+        let (magnitudes, phases): (Vec<_>, Vec<_>) =
+            buckets.iter().map(|bucket| bucket.to_polar()).unzip();
+
+        let elapsed = self.previous_start.map(|previous| start - previous);
+
+        let is_transient = transient_threshold.is_some_and(|threshold| {
+            let rise = magnitudes
+                .iter()
+                .zip(&self.previous_magnitudes)
+                .map(|(magnitude, previous)| (magnitude - previous).max(0.0))
+                .sum::<f32>();
+
+            rise > threshold * self.previous_magnitudes.iter().sum::<f32>()
+        });
+
+        match elapsed {
+            Some(elapsed) if !is_transient => {
+                // Measure how far each bucket is from its center frequency
+                if elapsed != 0 {
+                    for (bucket, ((frequency, phase), previous)) in self
+                        .frequencies
+                        .iter_mut()
+                        .zip(&phases)
+                        .zip(&self.previous_phases)
+                        .enumerate()
+                    {
+                        let center = bucket_frequency(bucket, self.fft_width);
+                        let deviation = wrap_phase(phase - previous - center * elapsed as f32);
+
+                        *frequency = center + deviation / elapsed as f32;
+                    }
+                }
+
+                if phase_locking {
+                    self.advance_locked(&magnitudes, &phases, hop);
+                } else {
+                    for (phase, frequency) in
+                        self.synthesis_phases.iter_mut().zip(&self.frequencies)
+                    {
+                        *phase = wrap_phase(*phase + frequency * hop as f32);
+                    }
+                }
+            }
+            // Start over from the phases of the input
+            _ => self.synthesis_phases.copy_from_slice(&phases),
+        }
+
+        self.previous_start = Some(start);
+        self.previous_phases = phases;
+
+        let output = magnitudes
+            .iter()
+            .zip(&self.synthesis_phases)
+            .map(|(&magnitude, &phase)| Complex::from_polar(magnitude, phase))
+            .collect();
+
+        self.previous_magnitudes = magnitudes;
+
+        output
+    }
+
+    /// Advance only the peaks, and keep the phases of the buckets around each peak relative to
+    /// it as they are in the input
+    fn advance_locked(&mut self, magnitudes: &[f32], phases: &[f32], hop: usize) {
+        let peaks = (0..magnitudes.len())
+            .filter(|&bucket| {
+                let left = bucket.checked_sub(1).map_or(0.0, |left| magnitudes[left]);
+                let right = magnitudes.get(bucket + 1).copied().unwrap_or(0.0);
+
+                magnitudes[bucket] > left && magnitudes[bucket] >= right
+            })
+            .collect::<Vec<_>>();
+
+        if peaks.is_empty() {
+            // Silence, there is nothing to lock to
+            self.synthesis_phases.copy_from_slice(phases);
+
+            return;
+        }
+
+        let peak_phases = peaks
+            .iter()
+            .map(|&peak| self.synthesis_phases[peak] + self.frequencies[peak] * hop as f32)
+            .collect::<Vec<_>>();
+
+        // Every bucket belongs to the closest peak
+        let mut region = 0;
+        for (bucket, synthesis_phase) in self.synthesis_phases.iter_mut().enumerate() {
+            let distance = |peak: usize| (peak as isize - bucket as isize).abs();
+
+            while region + 1 < peaks.len() && distance(peaks[region + 1]) < distance(peaks[region])
+            {
+                region += 1;
+            }
+
+            let peak = peaks[region];
+
+            *synthesis_phase = wrap_phase(peak_phases[region] + phases[bucket] - phases[peak]);
+        }
+    }
+}
+
+/// The center frequency of a bucket in radians per sample
+fn bucket_frequency(bucket: usize, fft_width: usize) -> f32 {
+    TAU * bucket as f32 / fft_width as f32
+}
+
+/// Wrap a phase into `-π..=π`
+fn wrap_phase(phase: f32) -> f32 {
+    (phase + PI).rem_euclid(TAU) - PI
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::PhaseVocoder;
+    use crate::{WaveformSpectrum, Window};
+
+    fn vocoder() -> PhaseVocoder {
+        PhaseVocoder::new(Window::Hann, 2048, 2048, 512)
+    }
+
+    /// The loudest frequency in the middle of the waveform
+    fn frequency(waveform: &Waveform) -> f64 {
+        let middle = waveform.len() / 2;
+        let slice = waveform.slice(middle - 4096..middle + 4096);
+        let spectrum = slice.spectrum(Window::Hann, 8192);
+
+        spectrum
+            .main_frequency()
+            .map_or(0.0, |(bucket, _)| spectrum.freq_from_bucket(bucket))
+    }
+
+    #[test]
+    fn identity() {
+        let waveform = Waveform::sine_wave(440.0, 0.5, 44_100);
+        let stretched = vocoder().time_stretch(&waveform, 1.0);
+
+        assert_eq!(stretched.len(), waveform.len());
+        for (n, (original, stretched)) in waveform
+            .samples()
+            .iter()
+            .zip(stretched.samples())
+            .enumerate()
+        {
+            assert!((original - stretched).abs() < 1e-3, "sample {n}");
+        }
+    }
+
+    #[test]
+    fn time_stretch() {
+        let waveform = Waveform::sine_wave(440.0, 1.0, 44_100);
+
+        for (factor, phase_locking) in [(0.5, true), (1.5, true), (2.25, true), (1.5, false)] {
+            let stretched = vocoder()
+                .phase_locking(phase_locking)
+                .time_stretch(&waveform, factor);
+
+            assert_eq!(
+                stretched.len(),
+                (waveform.len() as f64 * factor).round() as usize
+            );
+            assert!((frequency(&stretched) - 440.0).abs() < 10.0, "{factor}");
+        }
+    }
+
+    #[test]
+    fn pitch_shift() {
+        let waveform = Waveform::sine_wave(440.0, 1.0, 44_100);
+
+        for (semitones, expected) in [(12.0, 880.0), (-12.0, 220.0), (7.0, 659.26)] {
+            let shifted = vocoder().pitch_shift(&waveform, semitones);
+
+            assert_eq!(shifted.len(), waveform.len());
+            assert!(
+                (frequency(&shifted) - expected).abs() < 10.0,
+                "{semitones}: {}",
+                frequency(&shifted)
+            );
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Monophonic pitch detection in the time domain
+//!
+//! Instead of picking the loudest bucket of a spectrum, these trackers look for the lag at
+//! which a frame best matches a delayed copy of itself, which finds the fundamental even when
+//! a harmonic is louder than it. [`PitchAlgorithm::Yin`] takes the first lag whose cumulative
+//! mean normalized difference dips below a threshold, while [`PitchAlgorithm::Mpm`] (McLeod's
+//! pitch method) takes the first peak of the normalized square difference function that comes
+//! close to its highest peak. Both fit a parabola through the chosen lag and its neighbours, so
+//! the estimates are not limited to whole samples.
+
+use std::fmt::{self, Display};
+
+use audio::waveform::Waveform;
+
+use crate::{
+    fft::{irfft, rfft},
+    peaks::vertex,
+};
+
+/// YIN only accepts lags whose cumulative mean normalized difference is below this
+const YIN_THRESHOLD: f32 = 0.15;
+/// MPM takes the first peak that reaches this fraction of the highest peak
+const MPM_CUTOFF: f32 = 0.93;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PitchAlgorithm {
+    Yin,
+    #[doc(alias = "McLeod")]
+    Mpm,
+}
+
+impl Display for PitchAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Yin => write!(f, "YIN"),
+            Self::Mpm => write!(f, "MPM"),
+        }
+    }
+}
+
+impl PitchAlgorithm {
+    pub const ALL: [PitchAlgorithm; 2] = [Self::Yin, Self::Mpm];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchOptions {
+    pub algorithm: PitchAlgorithm,
+    /// The lowest frequency to look for in Hz
+    pub min_frequency: f32,
+    /// The highest frequency to look for in Hz
+    pub max_frequency: f32,
+    /// The amount of samples in each frame, which should hold at least two periods of the
+    /// lowest frequency
+    pub frame_width: usize,
+    /// The amount of samples between the starts of consecutive frames
+    pub hop: usize,
+    /// Frames whose RMS is below this are silent and have no pitch
+    pub silence: f32,
+}
+
+impl Default for PitchOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: PitchAlgorithm::Yin,
+            min_frequency: 40.0,
+            max_frequency: 2000.0,
+            frame_width: 2048,
+            hop: 512,
+            silence: 1e-3,
+        }
+    }
+}
+
+/// The fundamental frequency of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pitch {
+    /// The frequency in Hz
+    pub frequency: f32,
+    /// How periodic the frame is at that frequency, from 0 for noise to 1 for a pure tone
+    pub confidence: f32,
+}
+
+/// The pitch of one frame of a waveform, see [`PitchTracker::track`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchFrame {
+    /// The center of the frame in seconds
+    pub time: f32,
+    /// `None` if the frame is silent or no period could be found at all
+    pub pitch: Option<Pitch>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PitchTracker {
+    options: PitchOptions,
+}
+
+impl PitchTracker {
+    pub fn new(options: PitchOptions) -> Self {
+        assert!(
+            options.min_frequency > 0.0 && options.min_frequency < options.max_frequency,
+            "the frequency range must be positive and not empty"
+        );
+        assert_ne!(options.hop, 0, "the hop must be at least one sample");
+
+        Self { options }
+    }
+
+    pub fn options(&self) -> PitchOptions {
+        self.options
+    }
+
+    /// Estimate the pitch of every frame of the waveform, downmixed to a single channel
+    ///
+    /// Only whole frames are analyzed, so waveforms shorter than a frame have no pitch at all.
+    pub fn track(&self, waveform: &Waveform<'_>) -> Vec<PitchFrame> {
+        let samples = waveform.downmix_iter().collect::<Vec<_>>();
+        let sample_rate = waveform.sample_rate();
+        let frame_width = self.options.frame_width;
+
+        if samples.len() < frame_width {
+            return Vec::new();
+        }
+
+        (0..=samples.len() - frame_width)
+            .step_by(self.options.hop)
+            .map(|start| PitchFrame {
+                time: (start + frame_width / 2) as f32 / sample_rate as f32,
+                pitch: self.estimate(&samples[start..start + frame_width], sample_rate),
+            })
+            .collect()
+    }
+
+    /// Estimate the pitch of a single frame of any width
+    pub fn estimate(&self, frame: &[f32], sample_rate: u32) -> Option<Pitch> {
+        let energy = frame.iter().map(|sample| sample * sample).sum::<f32>();
+        if frame.is_empty() || (energy / frame.len() as f32).sqrt() < self.options.silence {
+            return None;
+        }
+
+        // Lags beyond half of the frame leave too little overlap to compare
+        let min_lag = ((sample_rate as f32 / self.options.max_frequency).floor() as usize).max(2);
+        let max_lag = ((sample_rate as f32 / self.options.min_frequency).ceil() as usize)
+            .min(frame.len() / 2);
+
+        if min_lag + 2 > max_lag {
+            return None;
+        }
+
+        let (lag, confidence) = match self.options.algorithm {
+            PitchAlgorithm::Yin => yin(frame, min_lag, max_lag),
+            PitchAlgorithm::Mpm => mpm(frame, min_lag, max_lag),
+        }?;
+
+        Some(Pitch {
+            frequency: sample_rate as f32 / lag,
+            confidence: confidence.clamp(0.0, 1.0),
+        })
+    }
+}
+
+/// The refined lag and confidence of the first dip of the cumulative mean normalized
+/// difference below [`YIN_THRESHOLD`], or of its deepest dip if there is none
+fn yin(frame: &[f32], min_lag: usize, max_lag: usize) -> Option<(f32, f32)> {
+    let width = frame.len() - max_lag;
+    let energies = running_energy(frame);
+    let correlation = correlate(&frame[..width], frame, max_lag + 1);
+
+    // The squared difference between the frame and itself delayed by each lag
+    let difference = (0..=max_lag).map(|lag| {
+        let delayed_energy = energies[lag + width] - energies[lag];
+
+        (energies[width] + delayed_energy - 2.0 * correlation[lag]).max(0.0)
+    });
+
+    // Dividing by the mean difference of the shorter lags removes the dip at a lag of zero
+    let mut sum = 0.0;
+    let normalized = difference
+        .enumerate()
+        .map(|(lag, difference)| {
+            sum += difference;
+
+            if lag == 0 || sum <= f32::EPSILON {
+                1.0
+            } else {
+                difference * lag as f32 / sum
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut lag = match (min_lag..=max_lag).find(|&lag| normalized[lag] < YIN_THRESHOLD) {
+        Some(lag) => lag,
+        None => (min_lag..=max_lag).min_by(|&a, &b| normalized[a].total_cmp(&normalized[b]))?,
+    };
+
+    // Follow the dip to its bottom
+    while lag < max_lag && normalized[lag + 1] < normalized[lag] {
+        lag += 1;
+    }
+
+    let (offset, value) = refine(&normalized, lag);
+
+    Some((lag as f32 + offset, 1.0 - value))
+}
+
+/// The refined lag and height of the first key maximum of the normalized square difference
+/// function that reaches [`MPM_CUTOFF`] of the highest one
+fn mpm(frame: &[f32], min_lag: usize, max_lag: usize) -> Option<(f32, f32)> {
+    let width = frame.len();
+    let energies = running_energy(frame);
+    let correlation = correlate(frame, frame, max_lag + 2);
+
+    let normalized = (0..=max_lag + 1)
+        .map(|lag| {
+            let energy = energies[width - lag] + energies[width] - energies[lag];
+
+            if energy > f32::EPSILON {
+                2.0 * correlation[lag] / energy
+            } else {
+                0.0
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // The highest point between each positive going zero crossing and the next negative going
+    // one, skipping the peak around a lag of zero
+    let mut key_maxima = Vec::<usize>::new();
+    let mut in_peak = false;
+    for lag in 1..=max_lag {
+        if normalized[lag] > 0.0 && normalized[lag - 1] <= 0.0 {
+            in_peak = true;
+            key_maxima.push(lag);
+        } else if normalized[lag] <= 0.0 {
+            in_peak = false;
+        }
+
+        if let Some(key_maximum) = key_maxima.last_mut() {
+            if in_peak && normalized[lag] > normalized[*key_maximum] {
+                *key_maximum = lag;
+            }
+        }
+    }
+
+    key_maxima.retain(|&lag| lag >= min_lag);
+
+    let highest = key_maxima
+        .iter()
+        .map(|&lag| normalized[lag])
+        .fold(f32::NEG_INFINITY, f32::max);
+    let lag = key_maxima
+        .into_iter()
+        .find(|&lag| normalized[lag] >= MPM_CUTOFF * highest)?;
+
+    let (offset, value) = refine(&normalized, lag);
+
+    Some((lag as f32 + offset, value))
+}
+
+/// The offset of the vertex of the parabola through `values[index]` and its neighbours, and
+/// the value at that vertex
+fn refine(values: &[f32], index: usize) -> (f32, f32) {
+    match (index.checked_sub(1), values.get(index + 1)) {
+        (Some(left), Some(&right)) => vertex(values[left], values[index], right),
+        _ => (0.0, values[index]),
+    }
+}
+
+/// The sum of the squares of the samples before each index, up to and including the length
+fn running_energy(samples: &[f32]) -> Vec<f32> {
+    let mut sum = 0.0;
+
+    Some(0.0)
+        .into_iter()
+        .chain(samples.iter().map(|sample| {
+            sum += sample * sample;
+            sum
+        }))
+        .collect()
+}
+
+/// `Σ a[n] * b[n + lag]` for the first `lags` lags, where `b` is at least as long as `a`
+fn correlate(a: &[f32], b: &[f32], lags: usize) -> Vec<f32> {
+    let width = (a.len() + b.len()).next_power_of_two();
+
+    let padded = |samples: &[f32]| {
+        let mut padded = samples.to_vec();
+        padded.resize(width, 0.0);
+
+        rfft(&padded)
+    };
+
+    let product = padded(a)
+        .into_iter()
+        .zip(padded(b))
+        .map(|(a, b)| a.conj() * b)
+        .collect::<Vec<_>>();
+
+    let mut correlation = irfft(&product, width);
+    correlation.truncate(lags);
+
+    correlation
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::TAU;
+
+    use audio::waveform::Waveform;
+
+    use super::{PitchAlgorithm, PitchOptions, PitchTracker};
+
+    fn tracker(algorithm: PitchAlgorithm) -> PitchTracker {
+        PitchTracker::new(PitchOptions {
+            algorithm,
+            ..Default::default()
+        })
+    }
+
+    /// The median frequency and the lowest confidence of all frames
+    fn track(algorithm: PitchAlgorithm, waveform: &Waveform) -> (f32, f32) {
+        let frames = tracker(algorithm).track(waveform);
+        assert!(!frames.is_empty());
+
+        let mut frequencies = Vec::new();
+        let mut confidence = 1.0f32;
+        for frame in frames {
+            let pitch = frame
+                .pitch
+                .unwrap_or_else(|| panic!("{algorithm}: no pitch"));
+
+            frequencies.push(pitch.frequency);
+            confidence = confidence.min(pitch.confidence);
+        }
+
+        frequencies.sort_by(f32::total_cmp);
+
+        (frequencies[frequencies.len() / 2], confidence)
+    }
+
+    #[test]
+    fn pure_tones() {
+        for algorithm in PitchAlgorithm::ALL {
+            // 441.3 Hz has a period of almost exactly 99.93 samples, which only parabolic
+            // refinement resolves
+            for frequency in [82.4, 220.0, 441.3, 1000.0] {
+                let waveform = Waveform::sine_wave(frequency, 0.25, 44_100);
+                let (estimate, confidence) = track(algorithm, &waveform);
+
+                assert!(
+                    (estimate - frequency).abs() < frequency * 0.002,
+                    "{algorithm}: {estimate} instead of {frequency}"
+                );
+                assert!(confidence > 0.9, "{algorithm}: {confidence}");
+            }
+        }
+    }
+
+    #[test]
+    fn weak_fundamental() {
+        // The second harmonic is louder than the fundamental, so the loudest bucket is wrong
+        let samples = (0..22_050)
+            .map(|n| {
+                let phase = TAU * 150.0 * n as f32 / 44_100.0;
+
+                0.3 * phase.sin() + (2.0 * phase).sin() + 0.6 * (3.0 * phase).sin()
+            })
+            .collect();
+        let waveform = Waveform::new(samples, 44_100);
+
+        for algorithm in PitchAlgorithm::ALL {
+            let (estimate, _) = track(algorithm, &waveform);
+
+            assert!((estimate - 150.0).abs() < 0.5, "{algorithm}: {estimate}");
+        }
+    }
+
+    #[test]
+    fn silence() {
+        let waveform = Waveform::new(vec![0.0; 8192], 44_100);
+
+        for algorithm in PitchAlgorithm::ALL {
+            let frames = tracker(algorithm).track(&waveform);
+
+            assert_eq!(frames.len(), 13);
+            assert!(frames.iter().all(|frame| frame.pitch.is_none()));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Short-time fourier transforms and their overlap-add inverse
+//!
+//! The waveform is padded with `window_width - hop` zeros at the start, so that every sample
+//! is covered by as many frames as every other. The inverse divides the overlapping frames by
+//! the summed product of the analysis and synthesis windows, which reconstructs the waveform
+//! exactly for every window and hop whose frames leave no sample uncovered.
+
+use std::iter;
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::{
+    fft::{irfft, rfft},
+    Window,
+};
+
+/// Overlap weights below this are treated as samples that no window covers
+const MIN_WEIGHT: f32 = 1e-6;
+
+/// Splits a waveform into overlapping, windowed frames and transforms each of them
+#[derive(Debug, Clone)]
+pub struct Stft {
+    window: Window,
+    coefficients: Box<[f32]>,
+    fft_width: usize,
+    hop: usize,
+}
+
+impl Stft {
+    /// Every frame is `window_width` samples long and starts `hop` samples after the last, and
+    /// is zero padded to `fft_width` before being transformed
+    pub fn new(window: Window, window_width: usize, fft_width: usize, hop: usize) -> Self {
+        assert_ne!(window_width, 0, "the window must hold at least one sample");
+        assert!(
+            window_width <= fft_width,
+            "a window of {window_width} samples does not fit into a fft of width {fft_width}"
+        );
+        assert!(
+            (1..=window_width).contains(&hop),
+            "the hop must be between 1 and the window width"
+        );
+
+        Self {
+            window,
+            coefficients: window.into_iter(window_width).collect(),
+            fft_width,
+            hop,
+        }
+    }
+
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    pub fn window_width(&self) -> usize {
+        self.coefficients.len()
+    }
+
+    pub fn fft_width(&self) -> usize {
+        self.fft_width
+    }
+
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// The amount of buckets in each frame
+    pub fn buckets(&self) -> usize {
+        self.fft_width / 2 + 1
+    }
+
+    /// The amount of frames needed to cover `len` samples
+    pub fn frame_count(&self, len: usize) -> usize {
+        if len == 0 {
+            return 0;
+        }
+
+        (len + self.padding()).div_ceil(self.hop)
+    }
+
+    /// The sample the given frame starts at, which is negative for the frames that overlap
+    /// the padding
+    pub fn frame_start(&self, frame: usize) -> isize {
+        (frame * self.hop) as isize - self.padding() as isize
+    }
+
+    /// The frequency of the given bucket in every frame
+    pub fn freq_from_bucket(&self, bucket: usize, sample_rate: u32) -> f64 {
+        bucket as f64 * sample_rate as f64 / self.fft_width as f64
+    }
+
+    /// Transform every frame of the waveform, downmixed to a single channel
+    ///
+    /// Each frame holds the [`buckets`](Self::buckets) of the non-negative frequencies.
+    pub fn frames(&self, waveform: &Waveform<'_>) -> StftFrames<'_> {
+        self.frames_of(waveform.downmix_iter().collect())
+    }
+
+    /// Transform every frame of the given mono samples
+    pub fn frames_of(&self, samples: Vec<f32>) -> StftFrames<'_> {
+        StftFrames {
+            stft: self,
+            count: self.frame_count(samples.len()),
+            samples,
+            next: 0,
+        }
+    }
+
+    /// An inverse transform with the same window, widths and hop
+    pub fn inverse(&self) -> Istft {
+        Istft::new(self.window, self.window_width(), self.fft_width, self.hop)
+    }
+
+    /// Transform the window of samples starting at any position, treating everything outside
+    /// of the samples as silence
+    pub fn frame_at(&self, samples: &[f32], start: isize) -> Vec<Complex<f32>> {
+        let windowed = self
+            .coefficients
+            .iter()
+            .enumerate()
+            .map(|(offset, coefficient)| {
+                let sample = usize::try_from(start + offset as isize)
+                    .ok()
+                    .and_then(|index| samples.get(index));
+
+                sample.map_or(0.0, |sample| sample * coefficient)
+            })
+            .chain(iter::repeat(0.0))
+            .take(self.fft_width)
+            .collect::<Vec<_>>();
+
+        rfft(&windowed)
+    }
+
+    fn padding(&self) -> usize {
+        self.window_width() - self.hop
+    }
+}
+
+/// The frames of a waveform, see [`Stft::frames`]
+#[derive(Debug)]
+pub struct StftFrames<'stft> {
+    stft: &'stft Stft,
+    samples: Vec<f32>,
+    next: usize,
+    count: usize,
+}
+
+impl Iterator for StftFrames<'_> {
+    type Item = Vec<Complex<f32>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.count {
+            return None;
+        }
+
+        let frame = self
+            .stft
+            .frame_at(&self.samples, self.stft.frame_start(self.next));
+        self.next += 1;
+
+        Some(frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.count - self.next;
+
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for StftFrames<'_> {}
+
+/// Rebuilds a waveform from its frames by overlap-adding them
+#[derive(Debug, Clone)]
+pub struct Istft {
+    coefficients: Box<[f32]>,
+    fft_width: usize,
+    hop: usize,
+
+    frames: usize,
+    samples: Vec<f32>,
+    weights: Vec<f32>,
+}
+
+impl Istft {
+    /// Frames are placed `hop` samples apart, which may differ from the hop they were
+    /// analyzed with to stretch the waveform in time
+    pub fn new(window: Window, window_width: usize, fft_width: usize, hop: usize) -> Self {
+        assert_ne!(window_width, 0, "the window must hold at least one sample");
+        assert!(
+            window_width <= fft_width,
+            "a window of {window_width} samples does not fit into a fft of width {fft_width}"
+        );
+        assert!(
+            (1..=window_width).contains(&hop),
+            "the hop must be between 1 and the window width"
+        );
+
+        Self {
+            coefficients: window.into_iter(window_width).collect(),
+            fft_width,
+            hop,
+
+            frames: 0,
+            samples: Vec::new(),
+            weights: Vec::new(),
+        }
+    }
+
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// The amount of frames added so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Add the next frame, holding the buckets of the non-negative frequencies
+    pub fn push(&mut self, buckets: &[Complex<f32>]) {
+        let window_width = self.coefficients.len();
+        let start = self.frames * self.hop;
+
+        self.samples.resize(start + window_width, 0.0);
+        self.weights.resize(start + window_width, 0.0);
+
+        let frame = irfft(buckets, self.fft_width);
+
+        for (offset, (&sample, &coefficient)) in frame.iter().zip(&*self.coefficients).enumerate() {
+            self.samples[start + offset] += sample * coefficient;
+            self.weights[start + offset] += coefficient * coefficient;
+        }
+
+        self.frames += 1;
+    }
+
+    /// Normalize the overlapping frames into a waveform of `len` samples
+    ///
+    /// Samples that were not covered by any window are left silent.
+    pub fn finish(self, len: usize, sample_rate: u32) -> Waveform<'static> {
+        let padding = self.coefficients.len() - self.hop;
+
+        let mut samples = self
+            .samples
+            .into_iter()
+            .zip(self.weights)
+            .skip(padding)
+            .map(|(sample, weight)| {
+                if weight > MIN_WEIGHT {
+                    sample / weight
+                } else {
+                    0.0
+                }
+            })
+            .collect::<Vec<_>>();
+
+        samples.resize(len, 0.0);
+
+        Waveform::new(samples, sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::Stft;
+    use crate::Window;
+
+    fn round_trip(waveform: &Waveform, stft: &Stft) -> f32 {
+        let mut istft = stft.inverse();
+
+        for frame in stft.frames(waveform) {
+            istft.push(&frame);
+        }
+
+        let reconstructed = istft.finish(waveform.len(), waveform.sample_rate());
+        assert_eq!(reconstructed.len(), waveform.len());
+
+        waveform
+            .samples()
+            .iter()
+            .zip(reconstructed.samples())
+            .map(|(original, reconstructed)| (original - reconstructed).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn reconstruction() {
+        let waveform = Waveform::sine_wave(440.0, 0.25, 44_100);
+
+        for stft in [
+            Stft::new(Window::Hann, 1024, 1024, 256),
+            Stft::new(Window::Hann, 1024, 2048, 512),
+            Stft::new(Window::Hamming, 500, 512, 125),
+            Stft::new(Window::Bartlett, 1000, 1000, 250),
+            Stft::new(Window::Rectangular, 256, 256, 256),
+        ] {
+            let error = round_trip(&waveform, &stft);
+
+            assert!(error < 1e-4, "{stft:?}: error of {error}");
+        }
+    }
+
+    #[test]
+    fn frame_count() {
+        let stft = Stft::new(Window::Hann, 8, 8, 2);
+
+        // Six samples of padding, then one frame per hop until the end is covered
+        assert_eq!(stft.frame_count(0), 0);
+        assert_eq!(stft.frame_count(1), 4);
+        assert_eq!(stft.frame_count(10), 8);
+        assert_eq!(stft.frame_start(0), -6);
+        assert_eq!(stft.frames_of(vec![0.0; 10]).len(), 8);
+    }
+}
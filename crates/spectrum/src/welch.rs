@@ -0,0 +1,169 @@
+//! Power spectral density estimation with Welch's method
+//!
+//! The waveform is split into overlapping windowed segments whose periodograms are averaged,
+//! which gives up frequency resolution for a much lower variance than a single periodogram of
+//! the whole waveform. The density is normalized by the power of the window, so that summing
+//! it over all buckets gives the mean power of the waveform regardless of the window.
+
+use audio::waveform::Waveform;
+
+use crate::{fft::rfft, Window, MIN_DBFS};
+
+/// The one-sided power spectral density of a waveform
+#[derive(Debug, Clone)]
+pub struct PowerSpectralDensity {
+    sample_rate: u32,
+    fft_width: usize,
+    segments: usize,
+    density: Vec<f32>,
+}
+
+impl PowerSpectralDensity {
+    /// Average the periodograms of segments of `window_width` samples that start `hop` samples
+    /// apart, each zero padded to `fft_width`
+    ///
+    /// The waveform is downmixed to a single channel. Only whole segments are used, unless the
+    /// waveform is shorter than a single segment, which is then zero padded.
+    pub fn welch(
+        waveform: &Waveform<'_>,
+        window: Window,
+        window_width: usize,
+        fft_width: usize,
+        hop: usize,
+    ) -> Self {
+        assert_ne!(window_width, 0, "the window must hold at least one sample");
+        assert!(
+            window_width <= fft_width,
+            "a window of {window_width} samples does not fit into a fft of width {fft_width}"
+        );
+        assert_ne!(hop, 0, "the hop must be at least one sample");
+
+        let samples = waveform.downmix_iter().collect::<Vec<_>>();
+        let sample_rate = waveform.sample_rate();
+
+        let coefficients = window.into_iter(window_width).collect::<Vec<_>>();
+        let window_power = coefficients.iter().map(|w| w * w).sum::<f32>();
+
+        let segments = samples.len().saturating_sub(window_width) / hop + 1;
+        let starts = (0..segments).map(|segment| segment * hop);
+
+        let mut density = vec![0.0; fft_width / 2 + 1];
+        for start in starts {
+            let mut windowed = samples[start..]
+                .iter()
+                .zip(&coefficients)
+                .map(|(sample, coefficient)| sample * coefficient)
+                .collect::<Vec<_>>();
+            windowed.resize(fft_width, 0.0);
+
+            for (density, bucket) in density.iter_mut().zip(rfft(&windowed)) {
+                *density += bucket.norm_sqr();
+            }
+        }
+
+        let scale = 1.0 / (segments as f32 * sample_rate as f32 * window_power);
+        for (bucket, density) in density.iter_mut().enumerate() {
+            // Fold the negative frequencies onto the positive ones, DC and nyquist have none
+            let sides = if bucket == 0 || bucket * 2 == fft_width {
+                1.0
+            } else {
+                2.0
+            };
+
+            *density *= sides * scale;
+        }
+
+        Self {
+            sample_rate,
+            fft_width,
+            segments,
+            density,
+        }
+    }
+
+    /// The density of each non-negative frequency bucket, in full scale squared per Hz
+    pub fn density(&self) -> &[f32] {
+        &self.density
+    }
+
+    /// The density of each non-negative frequency bucket, in decibels relative to full scale
+    /// squared per Hz
+    pub fn dbfs(&self) -> impl Iterator<Item = f32> + '_ {
+        self.density
+            .iter()
+            .map(|density| (10.0 * density.log10()).max(MIN_DBFS))
+    }
+
+    /// The amount of segments that were averaged
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    pub fn freq_resolution(&self) -> f64 {
+        self.sample_rate as f64 / self.fft_width as f64
+    }
+
+    pub fn freq_from_bucket(&self, bucket: usize) -> f64 {
+        bucket as f64 * self.freq_resolution()
+    }
+
+    /// The mean power of the waveform, found by integrating the density over all frequencies
+    pub fn power(&self) -> f32 {
+        self.density.iter().sum::<f32>() * self.freq_resolution() as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::PowerSpectralDensity;
+    use crate::Window;
+
+    #[test]
+    fn sine_power() {
+        // A sine wave of amplitude 0.5 has a mean power of 0.125
+        let samples = Waveform::sine_wave(1000.0, 1.0, 44_100)
+            .into_samples()
+            .into_iter()
+            .map(|sample| sample * 0.5)
+            .collect();
+        let waveform = Waveform::new(samples, 44_100);
+
+        for window in [Window::Rectangular, Window::Hann, Window::BlackmanHarris] {
+            let psd = PowerSpectralDensity::welch(&waveform, window, 1024, 2048, 512);
+
+            assert_eq!(psd.segments(), 85);
+            assert!(
+                (psd.power() - 0.125).abs() < 0.002,
+                "{window}: {}",
+                psd.power()
+            );
+
+            let loudest = (0..psd.density().len())
+                .max_by(|&a, &b| psd.density()[a].total_cmp(&psd.density()[b]))
+                .unwrap_or_default();
+            assert!((psd.freq_from_bucket(loudest) - 1000.0).abs() < psd.freq_resolution());
+        }
+    }
+
+    #[test]
+    fn white_noise() {
+        // Uniform noise between -1 and 1 has a power of 1/3, spread evenly up to nyquist
+        let mut state = 1u32;
+        let samples = (0..1 << 16)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect();
+        let waveform = Waveform::new(samples, 8000);
+
+        let psd = PowerSpectralDensity::welch(&waveform, Window::Hann, 256, 256, 128);
+        let expected = 1.0 / 3.0 / 4000.0;
+
+        for density in &psd.density()[1..128] {
+            assert!((density - expected).abs() < expected * 0.3, "{density}");
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Window functions and the properties needed to calibrate spectra taken with them
+//!
+//! Every window comes in a periodic form, which is what spectral analysis and overlap-add
+//! expect, and a symmetric form, which is what filter design expects. The periodic form of
+//! width `n` is the symmetric form of width `n + 1` without its last sample.
+
+use std::{
+    f32::consts::TAU,
+    fmt::{self, Display},
+    ops::Range,
+};
+
+use num_complex::Complex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Window {
+    #[doc(alias = "Triangular")]
+    Bartlett,
+    Hamming,
+    /// Good default choice
+    Hann,
+    Rectangular,
+    Blackman,
+    /// The four term Blackman-Harris window, with sidelobes below -92 dB
+    BlackmanHarris,
+    /// Nuttall's four term window with a continuous first derivative
+    Nuttall,
+    /// Has almost no scalloping loss, for reading off the amplitudes of sinusoids
+    FlatTop,
+    /// Trades main lobe width for sidelobe level as `beta` grows, where 0 is rectangular
+    Kaiser(f32),
+    /// A Gaussian whose standard deviation is the given fraction of half the width
+    Gaussian(f32),
+    /// Tapers the given fraction of the width with a cosine, where 0 is rectangular and 1 is
+    /// [`Hann`](Self::Hann)
+    #[doc(alias = "Tapered cosine")]
+    Tukey(f32),
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BlackmanHarris => write!(f, "Blackman-Harris"),
+            Self::FlatTop => write!(f, "Flat Top"),
+            Self::Kaiser(beta) => write!(f, "Kaiser (β = {beta})"),
+            Self::Gaussian(sigma) => write!(f, "Gaussian (σ = {sigma})"),
+            Self::Tukey(alpha) => write!(f, "Tukey (α = {alpha})"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Whether the window repeats after its last sample, or is mirrored around its center
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    Periodic,
+    Symmetric,
+}
+
+/// How a window changes the spectrum of the samples it is applied to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowProperties {
+    /// The mean of the coefficients, which is what the amplitude of a sinusoid in the center of
+    /// a bucket is scaled by
+    pub coherent_gain: f32,
+    /// The equivalent noise bandwidth in buckets, which is how much wider than a bucket the
+    /// window lets noise through
+    pub enbw: f32,
+    /// How much quieter a sinusoid halfway between two buckets appears, in decibels
+    pub scalloping_loss: f32,
+}
+
+impl Window {
+    /// Every window, with typical parameters for the ones that take one
+    pub const ALL: [Window; 11] = [
+        Self::Bartlett,
+        Self::Hamming,
+        Self::Hann,
+        Self::Rectangular,
+        Self::Blackman,
+        Self::BlackmanHarris,
+        Self::Nuttall,
+        Self::FlatTop,
+        Self::Kaiser(8.6),
+        Self::Gaussian(0.4),
+        Self::Tukey(0.5),
+    ];
+
+    /// The coefficients of the periodic form of the window
+    pub fn into_iter(self, width: usize) -> WindowIter {
+        self.coefficients(width, Symmetry::Periodic)
+    }
+
+    /// The coefficients of the symmetric form of the window
+    pub fn symmetric(self, width: usize) -> WindowIter {
+        self.coefficients(width, Symmetry::Symmetric)
+    }
+
+    pub fn coefficients(self, width: usize, symmetry: Symmetry) -> WindowIter {
+        let period = match symmetry {
+            Symmetry::Periodic => width,
+            Symmetry::Symmetric => width.saturating_sub(1),
+        };
+
+        WindowIter {
+            range: 0..width,
+            period: period as f32,
+            window: self,
+        }
+    }
+
+    /// Measure the properties of the periodic window of the given width
+    pub fn properties(self, width: usize) -> WindowProperties {
+        let coefficients = self.into_iter(width).collect::<Vec<_>>();
+
+        let sum = coefficients.iter().sum::<f32>();
+        let squares = coefficients.iter().map(|w| w * w).sum::<f32>();
+
+        // The response half a bucket away from the center of a bucket
+        let half_bucket = coefficients
+            .iter()
+            .enumerate()
+            .map(|(n, &w)| w * Complex::from_polar(1.0, -TAU * n as f32 / (2 * width) as f32))
+            .sum::<Complex<f32>>()
+            .norm();
+
+        WindowProperties {
+            coherent_gain: sum / width as f32,
+            enbw: width as f32 * squares / (sum * sum),
+            scalloping_loss: -20.0 * (half_bucket / sum).log10(),
+        }
+    }
+
+    /// The coefficient at `x`, which runs from 0 at the first sample to 1 at the end of a
+    /// period
+    fn at(self, x: f32) -> f32 {
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Bartlett => 1.0 - (2.0 * x - 1.0).abs(),
+            Window::Hann => cosine_sum(x, &[0.5, 0.5]),
+            Window::Hamming => cosine_sum(x, &[25.0 / 46.0, 21.0 / 46.0]),
+            Window::Blackman => cosine_sum(x, &[0.42, 0.5, 0.08]),
+            Window::BlackmanHarris => cosine_sum(x, &[0.35875, 0.48829, 0.14128, 0.01168]),
+            Window::Nuttall => cosine_sum(x, &[0.355768, 0.487396, 0.144232, 0.012604]),
+            Window::FlatTop => cosine_sum(
+                x,
+                &[0.21557895, 0.41663158, 0.27726316, 0.08357895, 0.006947368],
+            ),
+            Window::Kaiser(beta) => {
+                let distance = 2.0 * x - 1.0;
+
+                bessel_i0(beta * (1.0 - distance * distance).max(0.0).sqrt()) / bessel_i0(beta)
+            }
+            Window::Gaussian(sigma) => (-0.5 * ((2.0 * x - 1.0) / sigma).powi(2)).exp(),
+            Window::Tukey(alpha) => {
+                // The distance from the closer end, mirrored so that both tapers are the same
+                let edge = x.min(1.0 - x);
+
+                if alpha <= 0.0 || edge >= alpha / 2.0 {
+                    1.0
+                } else {
+                    0.5 * (1.0 - (TAU * edge / alpha).cos())
+                }
+            }
+        }
+    }
+}
+
+/// `a₀ - a₁cos(2πx) + a₂cos(4πx) - …`
+fn cosine_sum(x: f32, coefficients: &[f32]) -> f32 {
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(k, a)| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+
+            sign * a * (TAU * k as f32 * x).cos()
+        })
+        .sum()
+}
+
+/// The zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f32) -> f32 {
+    let quarter_square = (x as f64 / 2.0).powi(2);
+
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..100 {
+        term *= quarter_square / (k * k) as f64;
+        sum += term;
+
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+
+    sum as f32
+}
+
+#[derive(Debug)]
+pub struct WindowIter {
+    range: Range<usize>,
+    period: f32,
+    window: Window,
+}
+
+impl Iterator for WindowIter {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.range.next()?;
+
+        // A symmetric window of a single sample has no period
+        if self.period == 0.0 {
+            return Some(1.0);
+        }
+
+        Some(self.window.at(n as f32 / self.period))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl ExactSizeIterator for WindowIter {}
+
+#[cfg(test)]
+mod test {
+    use super::Window;
+
+    #[test]
+    fn properties() {
+        // Coherent gain, ENBW in buckets and scalloping loss in dB of well known windows
+        for (window, expected) in [
+            (Window::Rectangular, [1.0, 1.0, 3.92]),
+            (Window::Hann, [0.5, 1.5, 1.42]),
+            (Window::Hamming, [0.54, 1.36, 1.78]),
+            (Window::Blackman, [0.42, 1.73, 1.10]),
+            (Window::BlackmanHarris, [0.36, 2.00, 0.83]),
+            (Window::FlatTop, [0.22, 3.77, 0.01]),
+        ] {
+            let properties = window.properties(1024);
+            let actual = [
+                properties.coherent_gain,
+                properties.enbw,
+                properties.scalloping_loss,
+            ];
+
+            for (actual, expected) in actual.into_iter().zip(expected) {
+                assert!((actual - expected).abs() < 0.02, "{window}: {properties:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn symmetry() {
+        for window in Window::ALL {
+            let symmetric = window.symmetric(9).collect::<Vec<_>>();
+            let periodic = window.into_iter(8).collect::<Vec<_>>();
+
+            for (n, coefficient) in symmetric.iter().enumerate() {
+                assert!((coefficient - symmetric[8 - n]).abs() < 1e-6, "{window}");
+            }
+            assert_eq!(symmetric[..8], periodic, "{window}");
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let same = |a: Window, b: Window| {
+            a.into_iter(64)
+                .zip(b.into_iter(64))
+                .all(|(a, b)| (a - b).abs() < 1e-5)
+        };
+
+        assert!(same(Window::Kaiser(0.0), Window::Rectangular));
+        assert!(same(Window::Tukey(0.0), Window::Rectangular));
+        assert!(same(Window::Tukey(1.0), Window::Hann));
+        assert_eq!(Window::Hann.symmetric(1).collect::<Vec<_>>(), [1.0]);
+    }
+}
@@ -7,6 +7,8 @@ use std::{path::Path, rc::Rc};
 use tracing::info;
 use ttspico::{Engine, EngineStatus, System, Voice};
 
+pub mod sing;
+
 // #[cfg(target_arch = "wasm32")]
 // compile_error!("`tts` can not be used on 'wasm32' platforms, yet");
 
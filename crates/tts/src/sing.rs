@@ -0,0 +1,376 @@
+//! Singing synthesized speech along a melody
+//!
+//! Every word of the lyrics is spoken on its own and split into its syllables at the quietest
+//! points of its loudness envelope. Each syllable is then pitch corrected to the frequency of
+//! its note and stretched to the note's duration with a phase vocoder.
+
+use std::time::Duration;
+
+use audio::waveform::Waveform;
+use spectrum::{PhaseVocoder, PitchAlgorithm, PitchOptions, PitchTracker, Window};
+use tracing::warn;
+use ttspico::Engine;
+
+use crate::synthesize;
+
+/// The length of the frames that the loudness and pitch of speech are measured over
+const ENVELOPE_FRAME: Duration = Duration::from_millis(10);
+const PITCH_FRAME: Duration = Duration::from_millis(40);
+/// The range that the pitch of speech is searched in
+const MIN_SPEECH_FREQUENCY: f32 = 60.0;
+const MAX_SPEECH_FREQUENCY: f32 = 500.0;
+/// Frames quieter than this fraction of the loudest frame are treated as silence
+const SILENCE_THRESHOLD: f32 = 0.05;
+/// The confidence that the pitch of a frame needs to count as voiced
+const VOICING_THRESHOLD: f32 = 0.5;
+/// The fade applied to both ends of every syllable to avoid clicks
+const FADE: Duration = Duration::from_millis(5);
+
+/// A note of the melody
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    /// The frequency to sing at in Hz
+    pub frequency: f32,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// A word of the lyrics and how many notes it spans
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub text: String,
+    pub syllables: usize,
+}
+
+/// Split lyrics into words, counting the syllables of each
+///
+/// Syllables can be marked with hyphens, such as `some-bo-dy`, otherwise they are guessed
+/// from the groups of vowels in the word.
+pub fn split_lyrics(lyrics: &str) -> Vec<Word> {
+    lyrics
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| {
+            if word.contains('-') {
+                let parts = word.split('-').filter(|part| !part.is_empty());
+
+                Word {
+                    text: parts.clone().collect(),
+                    syllables: parts.count().max(1),
+                }
+            } else {
+                Word {
+                    text: word.to_string(),
+                    syllables: count_syllables(word),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Guess the syllables in an english word from its groups of vowels
+fn count_syllables(word: &str) -> usize {
+    let letters = word
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+
+    let is_vowel = |c: &char| "aeiouy".contains(*c);
+
+    let mut groups = letters
+        .iter()
+        .zip(letters.iter().skip(1))
+        .filter(|(current, next)| !is_vowel(current) && is_vowel(next))
+        .count()
+        + letters.first().map_or(0, |c| is_vowel(c) as usize);
+
+    // A trailing e is usually silent, as in "make", but not in "table"
+    if groups > 1 && letters.ends_with(&['e']) && !letters.ends_with(&['l', 'e']) {
+        groups -= 1;
+    }
+
+    groups.max(1)
+}
+
+/// Sing the lyrics along the melody, one syllable per note
+pub fn sing(
+    engine: &mut Engine,
+    lyrics: &str,
+    melody: &[Note],
+) -> color_eyre::Result<Waveform<'static>> {
+    sing_with(lyrics, melody, |text| synthesize(engine, text))
+}
+
+/// Sing the lyrics along the melody, speaking each word with `speak`
+///
+/// If there are more syllables than notes, the extra syllables are left out, and extra notes
+/// stay silent.
+pub fn sing_with(
+    lyrics: &str,
+    melody: &[Note],
+    mut speak: impl FnMut(&str) -> color_eyre::Result<Waveform<'static>>,
+) -> color_eyre::Result<Waveform<'static>> {
+    let mut notes = melody.to_vec();
+    notes.sort_by_key(|note| note.start);
+
+    let mut sample_rate = None;
+    let mut syllables = Vec::new();
+    for word in split_lyrics(lyrics) {
+        let speech = speak(&word.text)?.downmix();
+        sample_rate = Some(speech.sample_rate());
+
+        let speech = trim_silence(&speech);
+        syllables.extend(split_syllables(&speech, word.syllables));
+    }
+
+    if syllables.len() != notes.len() {
+        warn!(
+            syllables = syllables.len(),
+            notes = notes.len(),
+            "the lyrics do not line up with the melody"
+        );
+    }
+
+    let sample_rate = match sample_rate {
+        Some(sample_rate) => sample_rate,
+        None => return Ok(Waveform::new(Vec::new(), Waveform::CD_SAMPLE_RATE)),
+    };
+
+    let to_sample = |time: Duration| (time.as_secs_f64() * sample_rate as f64).round() as usize;
+
+    let len = notes
+        .iter()
+        .map(|note| to_sample(note.start + note.duration))
+        .max()
+        .unwrap_or(0);
+    let mut output = vec![0.0; len];
+
+    let vocoder = PhaseVocoder::new(Window::Hann, 1024, 1024, 256);
+    for (syllable, note) in syllables.iter().zip(&notes) {
+        let sung = fit_to_note(&vocoder, syllable, note);
+
+        for (output, sample) in output[to_sample(note.start)..]
+            .iter_mut()
+            .zip(sung.samples())
+        {
+            *output += sample;
+        }
+    }
+
+    Ok(Waveform::new(output, sample_rate))
+}
+
+/// Pitch correct and stretch a syllable onto a note
+fn fit_to_note(vocoder: &PhaseVocoder, syllable: &Waveform, note: &Note) -> Waveform<'static> {
+    let sample_rate = syllable.sample_rate();
+    let target_len = (note.duration.as_secs_f64() * sample_rate as f64).round() as usize;
+
+    if syllable.is_empty() || target_len == 0 {
+        return Waveform::new(Vec::new(), sample_rate);
+    }
+
+    let shifted = match estimate_pitch(syllable) {
+        Some(frequency) => vocoder.pitch_shift_by(syllable, (note.frequency / frequency) as f64),
+        // Consonants without a pitch only need to be stretched
+        None => syllable.to_owned(),
+    };
+
+    let stretched = vocoder.time_stretch(&shifted, target_len as f64 / shifted.len() as f64);
+    let mut samples = stretched.into_samples();
+
+    let fade = ((FADE.as_secs_f64() * sample_rate as f64) as usize).min(samples.len() / 2);
+    for n in 0..fade {
+        let gain = n as f32 / fade as f32;
+        let len = samples.len();
+
+        samples[n] *= gain;
+        samples[len - 1 - n] *= gain;
+    }
+
+    Waveform::new(samples, sample_rate)
+}
+
+/// The loudness of every frame of the waveform
+fn envelope(waveform: &Waveform) -> Vec<f32> {
+    let frame = (ENVELOPE_FRAME.as_secs_f64() * waveform.sample_rate() as f64) as usize;
+
+    waveform
+        .samples()
+        .chunks(frame.max(1))
+        .map(|frame| {
+            (frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32).sqrt()
+        })
+        .collect()
+}
+
+/// Cut off the silence before and after the speech
+fn trim_silence(speech: &Waveform) -> Waveform<'static> {
+    let envelope = envelope(speech);
+    let threshold = envelope.iter().copied().fold(0.0, f32::max) * SILENCE_THRESHOLD;
+
+    let first = envelope.iter().position(|&rms| rms > threshold);
+    let last = envelope.iter().rposition(|&rms| rms > threshold);
+
+    match (first, last) {
+        (Some(first), Some(last)) => {
+            let frame = (ENVELOPE_FRAME.as_secs_f64() * speech.sample_rate() as f64) as usize;
+            let frame = frame.max(1);
+
+            speech
+                .slice(first * frame..((last + 1) * frame).min(speech.len()))
+                .to_owned()
+        }
+        _ => Waveform::new(Vec::new(), speech.sample_rate()),
+    }
+}
+
+/// Split a word into the given amount of syllables at its quietest points
+///
+/// The boundaries are placed near where evenly sized syllables would end, at the quietest
+/// frame within a quarter syllable of that point.
+fn split_syllables(word: &Waveform, syllables: usize) -> Vec<Waveform<'static>> {
+    let envelope = envelope(word);
+
+    if syllables <= 1 || envelope.len() < syllables {
+        return vec![word.to_owned()];
+    }
+
+    let frame = (ENVELOPE_FRAME.as_secs_f64() * word.sample_rate() as f64) as usize;
+    let search = (envelope.len() / syllables / 4).max(1);
+
+    let mut boundaries = vec![0];
+    for syllable in 1..syllables {
+        let guess = syllable * envelope.len() / syllables;
+        let previous = boundaries.last().copied().unwrap_or(0);
+
+        let range =
+            (guess.saturating_sub(search).max(previous + 1))..(guess + search).min(envelope.len());
+        let quietest = range
+            .clone()
+            .min_by(|&a, &b| envelope[a].total_cmp(&envelope[b]))
+            .unwrap_or(range.start);
+
+        boundaries.push(quietest);
+    }
+
+    boundaries
+        .iter()
+        .zip(boundaries.iter().skip(1).copied().chain([envelope.len()]))
+        .map(|(&start, end)| {
+            word.slice((start * frame).min(word.len())..(end * frame).min(word.len()))
+                .to_owned()
+        })
+        .collect()
+}
+
+/// The median pitch of the voiced frames of a waveform
+fn estimate_pitch(waveform: &Waveform) -> Option<f32> {
+    let sample_rate = waveform.sample_rate() as f32;
+    let loudest = envelope(waveform).into_iter().fold(0.0, f32::max);
+
+    let tracker = PitchTracker::new(PitchOptions {
+        algorithm: PitchAlgorithm::Yin,
+        min_frequency: MIN_SPEECH_FREQUENCY,
+        max_frequency: MAX_SPEECH_FREQUENCY,
+        frame_width: (PITCH_FRAME.as_secs_f32() * sample_rate) as usize,
+        hop: ((ENVELOPE_FRAME.as_secs_f32() * sample_rate) as usize).max(1),
+        silence: loudest * SILENCE_THRESHOLD,
+    });
+
+    let mut pitches = tracker
+        .track(waveform)
+        .into_iter()
+        .filter_map(|frame| frame.pitch)
+        .filter(|pitch| pitch.confidence > VOICING_THRESHOLD)
+        .map(|pitch| pitch.frequency)
+        .collect::<Vec<_>>();
+
+    if pitches.is_empty() {
+        return None;
+    }
+
+    pitches.sort_by(f32::total_cmp);
+
+    Some(pitches[pitches.len() / 2])
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use audio::waveform::Waveform;
+    use spectrum::{WaveformSpectrum, Window};
+
+    use super::{count_syllables, sing_with, split_lyrics, Note, Word};
+
+    #[test]
+    fn syllables() {
+        for (word, syllables) in [
+            ("once", 1),
+            ("make", 1),
+            ("table", 2),
+            ("melody", 3),
+            ("a", 1),
+            ("rhythm", 1),
+        ] {
+            assert_eq!(count_syllables(word), syllables, "{word}");
+        }
+
+        assert_eq!(
+            split_lyrics("some-bo-dy once, -"),
+            [
+                Word {
+                    text: "somebody".to_string(),
+                    syllables: 3
+                },
+                Word {
+                    text: "once,".to_string(),
+                    syllables: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_melody() -> color_eyre::Result<()> {
+        let melody = [
+            Note {
+                frequency: 220.0,
+                start: Duration::from_millis(0),
+                duration: Duration::from_millis(500),
+            },
+            Note {
+                frequency: 330.0,
+                start: Duration::from_millis(500),
+                duration: Duration::from_millis(500),
+            },
+        ];
+
+        // Pretend to speak every syllable as a tone with a short pause in between
+        let sung = sing_with("la-la", &melody, |_| {
+            let mut samples = Waveform::sine_wave(150.0, 0.2, 16_000).into_samples();
+            samples.extend([0.0; 800]);
+            samples.extend(Waveform::sine_wave(150.0, 0.2, 16_000).into_samples());
+
+            Ok(Waveform::new(samples, 16_000))
+        })?;
+
+        assert_eq!(sung.len(), 16_000);
+
+        for (note, range) in melody.iter().zip([2000..6000, 10_000..14_000]) {
+            let slice = sung.slice(range);
+            let spectrum = slice.spectrum(Window::Hann, 4000);
+            let frequency = spectrum
+                .main_frequency()
+                .map_or(0.0, |(bucket, _)| spectrum.freq_from_bucket(bucket));
+
+            assert!(
+                (frequency - note.frequency as f64).abs() < 10.0,
+                "{frequency}"
+            );
+        }
+
+        Ok(())
+    }
+}